@@ -43,6 +43,7 @@ FILE_NAME = f'{NAME}_approx_{EPOCHS}epochs_{LEARING_RATE}lr_{STEP_SIZE}sz_downsc
 EPOCH_SNAPSHOTS = [1,2,4,8,16,32,64,80,96,112] # Save snapshots every X-th Epoch 
 EPOCH_CLAMPING = [] # Clamp opacity to 0.1 every X-th Epoch
 EPOCH_UPSAMPLE = [16,32,64] # Upsample epoch every X-th Epoch, this will increase each dimensions size by 2
+TF_FILE = None # Transfer function saved by the renderer as DiffDVR tensor, reconstructs a density volume with it instead of a preshaded one
 
 def main():
     print(pyrenderer.__doc__)
@@ -63,28 +64,37 @@ def main():
         cameras_json = json.load(f)
 
     look_at = cameras_json["look_at"]
+    # The renderer exports the box of the volume including its voxel spacing
+    box_min = cameras_json.get("box_min", [-X / min_dim / 2, -Y / min_dim / 2, -Z / min_dim / 2])
+    box_size = cameras_json.get("box_size", [X / min_dim, Y / min_dim, Z / min_dim])
     cameras_json = cameras_json["positions"]
 
     opacity_scaling = 1.0
-    tf = torch.tensor([[
-        [0.0,0.0,0.0,0.0 *opacity_scaling],
-        [1.0,1.0,1.0,1.0 *opacity_scaling]
-    ]], dtype=dtype, device=device)
+    if TF_FILE is None:
+        tf_mode = pyrenderer.TFMode.Preshaded
+        tf = torch.tensor([[
+            [0.0,0.0,0.0,0.0 *opacity_scaling],
+            [1.0,1.0,1.0,1.0 *opacity_scaling]
+        ]], dtype=dtype, device=device)
+    else:
+        tf_mode, tf = load_transfer_function(TF_FILE, dtype, device)
+    channels = 4 if TF_FILE is None else 1
 
     print("Create data set")
-    volume_tensor = torch.ones((4, X // DOWNSCALE, Y // DOWNSCALE, Z // DOWNSCALE), dtype=dtype, device=device) * 0.5
-    volume_tensor[3,:,:,:] = opacity_scaling
+    volume_tensor = torch.ones((channels, X // DOWNSCALE, Y // DOWNSCALE, Z // DOWNSCALE), dtype=dtype, device=device) * 0.5
+    if TF_FILE is None:
+        volume_tensor[3,:,:,:] = opacity_scaling
 
     print("Create renderer inputs")
     inputs = pyrenderer.RendererInputs()
     inputs.screen_size = pyrenderer.int2(W, H)
     inputs.volume = volume_tensor
-    inputs.volume_filter_mode = pyrenderer.VolumeFilterMode.Preshaded
-    inputs.box_min = pyrenderer.real3(-X / min_dim / 2, -Y / min_dim / 2, -Z / min_dim / 2)
-    inputs.box_size = pyrenderer.real3(X / min_dim, Y / min_dim, Z / min_dim)
+    inputs.volume_filter_mode = pyrenderer.VolumeFilterMode.Preshaded if TF_FILE is None else pyrenderer.VolumeFilterMode.Trilinear
+    inputs.box_min = make_real3(box_min)
+    inputs.box_size = make_real3(box_size)
     # inputs.step_size = 0.25 / max(X, max(Y, Z))
     inputs.step_size = STEP_SIZE
-    inputs.tf_mode = pyrenderer.TFMode.Preshaded
+    inputs.tf_mode = tf_mode
     inputs.tf = tf
     inputs.blend_mode = pyrenderer.BlendMode.BeerLambert
 
@@ -211,11 +221,11 @@ def main():
             # Upsample volume
             volume_tensor = volume_tensor.unsqueeze(0)
             volume_tensor = torch.nn.functional.interpolate(volume_tensor, scale_factor=2, mode='nearest')
-            volume_tensor = volume_tensor.squeeze()
+            volume_tensor = volume_tensor.squeeze(0)
             inputs.volume = volume_tensor
             grad_volume = grad_volume.unsqueeze(0)
             grad_volume = torch.nn.functional.interpolate(grad_volume, scale_factor=2, mode='nearest')
-            grad_volume = grad_volume.squeeze()
+            grad_volume = grad_volume.squeeze(0)
             adjoint_outputs.adj_volume = grad_volume
             print('Upsample')
 
@@ -273,13 +283,26 @@ def main():
     
     pyrenderer.cleanup()
 
+def load_transfer_function(path, dtype, device):
+    """Reads a transfer function the renderer saved as DiffDVR tensor, returns its TFMode and the tensor"""
+    with open(path) as f:
+        tf_json = json.load(f)
+    tf_mode = getattr(pyrenderer.TFMode, tf_json['tf_mode'].capitalize())
+    return tf_mode, torch.tensor(tf_json['tf'], dtype=dtype, device=device)
+
 def save_nc(filename, volume_tensor):
 
     ncfile = Dataset(filename + '.nc', mode='w', format='NETCDF4_CLASSIC')
-    cdim = ncfile.createDimension('c', 4)
     zdim = ncfile.createDimension('z', volume_tensor.size(dim=3))
     ydim = ncfile.createDimension('y', volume_tensor.size(dim=2))
     xdim = ncfile.createDimension('x', volume_tensor.size(dim=1))
+    if volume_tensor.size(dim=0) == 1:
+        # Density volume reconstructed with a transfer function
+        outfield_density = ncfile.createVariable('density', np.float32, ('z', 'y', 'x'))
+        outfield_density[:, :, :] = np.flip(np.flip(volume_tensor.detach().cpu().numpy()[0].transpose(2, 1, 0), 1), 2)
+        ncfile.close()
+        return
+    cdim = ncfile.createDimension('c', 4)
     outfield_color = ncfile.createVariable('color', np.float32, ('c', 'z', 'y', 'x'))
     #outfield_color[:, :, :, :] = volume_tensor.detach().cpu().numpy().flatten('F')
     #outfield_color.flatten('F') = volume_tensor.detach().cpu().numpy().flatten('F')
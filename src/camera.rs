@@ -5,6 +5,8 @@ use crate::transform::Transform;
 pub struct Camera {
     pub transform: Transform,
     aspect: f32,
+    /// Height of the viewport in pixels, used to estimate the footprint of a pixel for the level of detail
+    viewport_height: u32,
     fovy: f32,
     znear: f32,
     zfar: f32,
@@ -12,10 +14,11 @@ pub struct Camera {
 }
 
 impl Camera {
-    pub fn new(aspect_ratio: f32) -> Camera {
+    pub fn new(width: u32, height: u32) -> Camera {
         Camera {
             transform: Transform::default().move_pos(Vec3::new(-5.0, -5.0, -5.0)),
-            aspect: aspect_ratio,
+            aspect: width as f32 / height as f32,
+            viewport_height: height,
             fovy: 45.0,
             znear: 0.1,
             zfar: 1000.0,
@@ -30,10 +33,16 @@ impl Camera {
         return proj * view;
     }
 
-    pub fn set_aspect_ratio(&mut self, aspect_ratio: f32) {
-        self.aspect = aspect_ratio;
+    pub fn set_viewport_size(&mut self, width: u32, height: u32) {
+        self.aspect = width as f32 / height as f32;
+        self.viewport_height = height;
     }
-    
+
+    /// Height that a single pixel covers at a distance of 1 from the camera
+    pub fn pixel_spread(&self) -> f32 {
+        2.0 * (f32::to_radians(self.fovy) / 2.0).tan() / self.viewport_height.max(1) as f32
+    }
+
     pub fn fovy(&self) -> f32 {
         self.fovy
     }
@@ -43,6 +52,7 @@ impl Camera {
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct CameraUniform {
     // Position has to be a Vec4 because of what I believe to be alignment issues on the GPU
+    // w holds the size of a pixel at a distance of 1, used for the level of detail
     position: [f32; 4],
     view_proj: [f32; 16],
     inverse_view_proj: [f32; 16],
@@ -61,7 +71,7 @@ impl CameraUniform {
     pub fn update_view_proj(&mut self, camera: &mut Camera) {
         let matrix = camera.build_view_projection_matrix();
 
-        self.position = [camera.transform.position.x, camera.transform.position.y, camera.transform.position.z, 0.0];
+        self.position = [camera.transform.position.x, camera.transform.position.y, camera.transform.position.z, camera.pixel_spread()];
         self.view_proj = matrix.to_cols_array();
         self.inverse_view_proj = matrix.inverse().to_cols_array();
     }
@@ -0,0 +1,119 @@
+use crate::transfer_function::ColorPoint;
+
+/// Built-in colormaps that the colors of a transfer function can be set to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Colormap {
+    Viridis,
+    Magma,
+    Inferno,
+    Plasma,
+    Cividis,
+    /// Diverging blue to red map by Kenneth Moreland
+    CoolWarm,
+    Grayscale,
+    /// Ten distinct colors of constant segments for label volumes, the Tableau 10 palette
+    Segmentation,
+}
+
+impl Colormap {
+    pub const ALL: [Colormap; 8] = [
+        Colormap::Viridis,
+        Colormap::Magma,
+        Colormap::Inferno,
+        Colormap::Plasma,
+        Colormap::Cividis,
+        Colormap::CoolWarm,
+        Colormap::Grayscale,
+        Colormap::Segmentation,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Colormap::Viridis => "Viridis",
+            Colormap::Magma => "Magma",
+            Colormap::Inferno => "Inferno",
+            Colormap::Plasma => "Plasma",
+            Colormap::Cividis => "Cividis",
+            Colormap::CoolWarm => "Cool-Warm",
+            Colormap::Grayscale => "Grayscale",
+            Colormap::Segmentation => "Segmentation",
+        }
+    }
+
+    /// Evenly spaced colors of the map as 8-bit RGB
+    fn stops(self) -> &'static [[u8; 3]] {
+        match self {
+            Colormap::Viridis => &[
+                [68, 1, 84], [72, 36, 117], [65, 68, 135], [53, 95, 141], [42, 120, 142], [33, 145, 140],
+                [34, 168, 132], [68, 191, 112], [122, 209, 81], [189, 223, 38], [253, 231, 37],
+            ],
+            Colormap::Magma => &[
+                [0, 0, 4], [20, 14, 54], [59, 15, 112], [100, 26, 128], [140, 41, 129], [183, 55, 121],
+                [222, 73, 104], [247, 112, 92], [254, 159, 109], [254, 207, 146], [252, 253, 191],
+            ],
+            Colormap::Inferno => &[
+                [0, 0, 4], [22, 11, 57], [66, 10, 104], [106, 23, 110], [147, 38, 103], [188, 55, 84],
+                [221, 81, 58], [243, 120, 25], [252, 165, 10], [246, 215, 70], [252, 255, 164],
+            ],
+            Colormap::Plasma => &[
+                [13, 8, 135], [65, 4, 157], [106, 0, 168], [143, 13, 164], [177, 42, 144], [204, 71, 120],
+                [225, 100, 98], [242, 132, 75], [252, 166, 54], [252, 206, 37], [240, 249, 33],
+            ],
+            Colormap::Cividis => &[
+                [0, 32, 77], [0, 48, 111], [42, 64, 108], [72, 82, 107], [94, 98, 110], [114, 115, 116],
+                [135, 132, 121], [160, 150, 119], [187, 170, 113], [217, 191, 99], [255, 234, 70],
+            ],
+            Colormap::CoolWarm => &[
+                [59, 76, 192], [98, 130, 234], [141, 176, 254], [184, 208, 249], [221, 221, 221],
+                [245, 196, 173], [244, 154, 123], [222, 96, 77], [180, 4, 38],
+            ],
+            Colormap::Grayscale => &[[0, 0, 0], [255, 255, 255]],
+            Colormap::Segmentation => &[
+                [31, 119, 180], [255, 127, 14], [44, 160, 44], [214, 39, 40], [148, 103, 189],
+                [140, 86, 75], [227, 119, 194], [127, 127, 127], [188, 189, 34], [23, 190, 207],
+            ],
+        }
+    }
+
+    /// Whether the colors are separate classes that are not blended into each other
+    fn is_categorical(self) -> bool {
+        self == Colormap::Segmentation
+    }
+}
+
+/// A colormap and how it is laid onto the values of the volume
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColormapSelection {
+    pub colormap: Colormap,
+    /// Runs the map from its last to its first color
+    pub reversed: bool,
+    /// Value of the first color, lower values keep it
+    pub min: f32,
+    /// Value of the last color, higher values keep it
+    pub max: f32,
+}
+
+impl ColormapSelection {
+    pub fn new(colormap: Colormap) -> Self {
+        Self { colormap, reversed: false, min: 0.0, max: 1.0 }
+    }
+
+    /// Color control points of a transfer function that reproduce the colormap
+    pub fn color_points(&self) -> Vec<ColorPoint> {
+        let mut stops: Vec<[f32; 3]> = self.colormap.stops().iter().map(|stop| stop.map(|channel| channel as f32 / 255.0)).collect();
+        if self.reversed {
+            stops.reverse();
+        }
+        let value = |t: f32| self.min + t * (self.max - self.min);
+
+        let count = stops.len() as f32;
+        if self.colormap.is_categorical() {
+            // Two points per class with the same color make constant segments with hard steps in between
+            stops.iter().enumerate()
+                .flat_map(|(i, color)| [ColorPoint { value: value(i as f32 / count), color: *color }, ColorPoint { value: value((i + 1) as f32 / count), color: *color }])
+                .collect()
+        } else {
+            stops.iter().enumerate().map(|(i, color)| ColorPoint { value: value(i as f32 / (count - 1.0)), color: *color }).collect()
+        }
+    }
+}
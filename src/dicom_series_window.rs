@@ -0,0 +1,44 @@
+use egui::Context;
+
+use crate::loaders::dicom::DicomSeries;
+
+/// GUI window for choosing which series of a DICOM folder is loaded
+pub struct DicomSeriesWindow {
+    directory: String,
+    series: Vec<DicomSeries>,
+    selected: usize,
+    /// Whether the window center and width of the series select the displayed value range
+    pub apply_window: bool,
+}
+
+impl DicomSeriesWindow {
+    pub fn new(directory: &str, series: Vec<DicomSeries>) -> Self {
+        Self {
+            directory: directory.to_string(),
+            series,
+            selected: 0,
+            apply_window: true,
+        }
+    }
+
+    /// Draws the window and returns the selected series once the load button is pressed
+    pub fn show(&mut self, ctx: &Context, open: &mut bool) -> Option<DicomSeries> {
+        let mut request = None;
+
+        egui::Window::new("Open DICOM Folder").open(open).show(ctx, |ui| {
+            ui.label(&self.directory);
+
+            for (i, series) in self.series.iter().enumerate() {
+                ui.radio_value(&mut self.selected, i, series.name());
+            }
+
+            ui.checkbox(&mut self.apply_window, "Apply window center/width");
+
+            if ui.button("Load").clicked() {
+                request = self.series.get(self.selected).cloned();
+            }
+        });
+
+        request
+    }
+}
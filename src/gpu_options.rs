@@ -0,0 +1,99 @@
+use anyhow::{anyhow, bail, Result};
+use wgpu::{Adapter, Backends, Instance, Surface};
+
+/// Usage shown for `--help` and invalid arguments
+pub const USAGE: &str = "Usage: volume-renderer [--backend <primary|vulkan|metal|dx12|gl|all>] [--adapter <index|name>] [--list-adapters] [--help]";
+
+/// Which graphics backend and adapter the renderer runs on, chosen on the command line
+#[derive(Debug, Clone)]
+pub struct GpuOptions {
+    pub backends: Backends,
+    /// Index in the adapter list or part of the adapter's name, the most powerful adapter is used if it is not set
+    pub adapter: Option<String>,
+    /// Prints the available adapters instead of opening the window
+    pub list_adapters: bool,
+    /// Prints the usage instead of opening the window
+    pub help: bool,
+}
+
+impl Default for GpuOptions {
+    fn default() -> Self {
+        Self {
+            backends: Backends::PRIMARY,
+            adapter: None,
+            list_adapters: false,
+            help: false,
+        }
+    }
+}
+
+impl GpuOptions {
+    /// Parses the command line arguments without the program name
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--backend" => {
+                    let value = args.next().ok_or_else(|| anyhow!("'--backend' needs a value"))?;
+                    options.backends = parse_backends(&value)?;
+                },
+                "--adapter" => {
+                    options.adapter = Some(args.next().ok_or_else(|| anyhow!("'--adapter' needs a value"))?);
+                },
+                "--list-adapters" => options.list_adapters = true,
+                "--help" | "-h" => options.help = true,
+                _ => bail!("Unknown argument '{}'", arg),
+            }
+        }
+        Ok(options)
+    }
+
+    /// Prints every adapter of the chosen backends with the index that selects it
+    pub fn print_adapters(&self, instance: &Instance) {
+        for (index, adapter) in instance.enumerate_adapters(self.backends).iter().enumerate() {
+            let info = adapter.get_info();
+            println!("{}: {} ({:?}, {:?}), max 3D texture size {}", index, info.name, info.backend, info.device_type, adapter.limits().max_texture_dimension_3d);
+        }
+    }
+
+    /// Picks the adapter given on the command line, or the most powerful one that can draw to the surface
+    pub async fn request_adapter(&self, instance: &Instance, surface: &Surface<'_>) -> Result<Adapter> {
+        let Some(selection) = &self.adapter else {
+            return instance.request_adapter(
+                &wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::HighPerformance,
+                    compatible_surface: Some(surface),
+                    force_fallback_adapter: false,
+                },
+            ).await.ok_or_else(|| anyhow!("No graphics adapter supports drawing to the window, try another '--backend'"));
+        };
+
+        let adapters = instance.enumerate_adapters(self.backends);
+        let adapter = match selection.parse::<usize>() {
+            Ok(index) => adapters.into_iter().nth(index),
+            Err(_) => {
+                let selection = selection.to_lowercase();
+                adapters.into_iter().find(|adapter| adapter.get_info().name.to_lowercase().contains(&selection))
+            },
+        };
+        let adapter = adapter.ok_or_else(|| anyhow!("No adapter matches '{}', use '--list-adapters' to show all adapters", selection))?;
+        if !adapter.is_surface_supported(surface) {
+            bail!("Adapter '{}' can't draw to the window", adapter.get_info().name);
+        }
+        Ok(adapter)
+    }
+}
+
+fn parse_backends(value: &str) -> Result<Backends> {
+    let backends = match value.to_ascii_lowercase().as_str() {
+        "primary" => Backends::PRIMARY,
+        "vulkan" => Backends::VULKAN,
+        "metal" => Backends::METAL,
+        "dx12" => Backends::DX12,
+        "gl" => Backends::GL,
+        "all" => Backends::all(),
+        _ => bail!("Unknown backend '{}', expected primary, vulkan, metal, dx12, gl or all", value),
+    };
+    Ok(backends)
+}
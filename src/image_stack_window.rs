@@ -0,0 +1,56 @@
+use egui::Context;
+
+use crate::loaders::image_stack::{list_images, ImageStackMode};
+
+/// GUI window for choosing the slices of an image stack and how they are turned into voxels
+pub struct ImageStackWindow {
+    /// Directory or file pattern with wildcards the slices are taken from
+    pattern: String,
+    pub mode: ImageStackMode,
+    /// Number of images matching the pattern, or why none could be found
+    matches: Result<usize, String>,
+}
+
+impl ImageStackWindow {
+    pub fn new(directory: &str) -> Self {
+        let mut window = Self {
+            pattern: directory.to_string(),
+            mode: ImageStackMode::Density,
+            matches: Ok(0),
+        };
+        window.update_matches();
+        window
+    }
+
+    fn update_matches(&mut self) {
+        self.matches = list_images(&self.pattern)
+            .map(|files| files.len())
+            .map_err(|err| err.to_string());
+    }
+
+    /// Draws the window and returns the pattern of the stack once the load button is pressed
+    pub fn show(&mut self, ctx: &Context, open: &mut bool) -> Option<String> {
+        let mut request = None;
+
+        egui::Window::new("Open Image Stack").open(open).show(ctx, |ui| {
+            ui.label("Folder or file pattern, e.g. slices/img_*.tif");
+            if ui.text_edit_singleline(&mut self.pattern).changed() {
+                self.update_matches();
+            }
+
+            match &self.matches {
+                Ok(count) => ui.label(format!("{} images", count)),
+                Err(err) => ui.label(err),
+            };
+
+            ui.radio_value(&mut self.mode, ImageStackMode::Density, "Grayscale as density");
+            ui.radio_value(&mut self.mode, ImageStackMode::Color, "RGBA as preshaded color");
+
+            if ui.add_enabled(self.matches.is_ok(), egui::Button::new("Load")).clicked() {
+                request = Some(self.pattern.clone());
+            }
+        });
+
+        request
+    }
+}
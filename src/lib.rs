@@ -14,10 +14,27 @@ mod screenshot;
 mod sphere_screenshot_manager;
 mod loaders;
 mod compare;
+mod netcdf_variable_window;
+mod dicom_series_window;
+mod image_stack_window;
+mod vtk_array_window;
+mod raw_import_window;
+mod colormap;
+mod transfer_function;
+mod transfer_function_2d;
+mod transfer_function_preset;
+mod transfer_function_window;
+mod time_series;
+mod gpu_options;
+mod volume_load;
 
 use std::time::Instant;
 
 use state::State;
+
+pub use gpu_options::GpuOptions;
+pub use loaders::{progress::LoadProgress, registry::{LoaderRegistry, VolumeLoader}, stream::{Slab, SlabSink}};
+pub use voxel::{format::VoxelFormat, volume::VolumeData, voxel::Voxel};
 use winit::{
     dpi::{LogicalSize, Size}, event::*, event_loop::EventLoop, keyboard::{KeyCode, PhysicalKey}, window::WindowBuilder
 };
@@ -27,14 +44,51 @@ use winit::{
 /// Creates the window and runs it in an event loop until the application is exited.<br>
 /// +X = Right, +Y = Forward, +Z = Up
 pub async fn run() {
+    run_with_loaders(LoaderRegistry::default()).await;
+}
+
+/// Runs the application with a custom set of loaders, e.g. the built-in loaders extended by own formats.
+/// The graphics backend and adapter are taken from the command line.
+pub async fn run_with_loaders(loaders: LoaderRegistry) {
+    let gpu_options = match GpuOptions::from_args(std::env::args().skip(1)) {
+        Ok(gpu_options) => gpu_options,
+        Err(err) => {
+            eprintln!("{:#}\n{}", err, gpu_options::USAGE);
+            return;
+        }
+    };
+    run_with_options(loaders, gpu_options).await;
+}
+
+/// Runs the application with a custom set of loaders on the given graphics backend and adapter
+pub async fn run_with_options(loaders: LoaderRegistry, gpu_options: GpuOptions) {
     env_logger::init();
+    if gpu_options.help {
+        println!("{}", gpu_options::USAGE);
+        return;
+    }
+    if gpu_options.list_adapters {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: gpu_options.backends,
+            ..Default::default()
+        });
+        gpu_options.print_adapters(&instance);
+        return;
+    }
+
     let event_loop = EventLoop::new().unwrap();
     let window = WindowBuilder::new()
         .with_title("Volume Renderer")
         .with_inner_size(Size::Logical(LogicalSize::new(1024.0, 1024.0)))
         .build(&event_loop).unwrap();
 
-    let mut state = State::new(&window).await;
+    let mut state = match State::new(&window, loaders, &gpu_options).await {
+        Ok(state) => state,
+        Err(err) => {
+            eprintln!("Could not initialize the renderer: {:#}", err);
+            return;
+        }
+    };
 
     event_loop.run(move |event,control_flow| {
         match event {
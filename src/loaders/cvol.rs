@@ -0,0 +1,246 @@
+//! Reader and writer for the `.cvol` volume container of DiffDVR.
+//!
+//! All values are little endian. A file starts with a 32 byte header
+//! followed by the features, each holding a name, its resolution and its payload:
+//!
+//! | Field                | Size                                                         |
+//! |----------------------|--------------------------------------------------------------|
+//! | magic `CVOL`         | 4 bytes                                                      |
+//! | version              | i32                                                          |
+//! | world size x, y, z   | 3 × f32                                                      |
+//! | feature count        | i32                                                          |
+//! | flags                | i32, bit 0 marks LZ4 compressed payloads                     |
+//! | padding              | 4 bytes                                                      |
+//! | *per feature*        |                                                              |
+//! | name length          | u32                                                          |
+//! | name                 | ASCII bytes                                                  |
+//! | resolution x, y, z   | 3 × u64                                                      |
+//! | channels             | u32                                                          |
+//! | data type            | u32, 0 = uchar, 1 = ushort, 2 = float                        |
+//! | payload              | channels interleaved per voxel, x changing fastest           |
+//!
+//! Compressed payloads are a sequence of LZ4 blocks, each preceded by its compressed size as i32
+//! and terminated by a size of 0. Blocks may refer to the data of previous blocks.
+
+use std::{fs, io::Write};
+
+use anyhow::{anyhow, bail, Context, Result};
+use glam::{UVec3, Vec3};
+
+use crate::voxel::{grid::VoxelGrid, volume::VolumeData};
+
+use super::{progress::LoadProgress, scalar::{data_range, decode_scalars, volume_from_values, ByteOrder, ScalarType}};
+
+const MAGIC: &[u8; 4] = b"CVOL";
+const VERSION: i32 = 1;
+const FLAG_COMPRESSED: i32 = 1;
+/// Amount of uncompressed bytes per LZ4 block when writing
+const BLOCK_SIZE: usize = 1 << 20;
+/// LZ4 blocks can refer back at most this many bytes
+const LZ4_WINDOW: usize = 1 << 16;
+
+/// A named feature of a `.cvol` file, e.g. a density or color field
+#[derive(Debug, Clone)]
+pub struct CvolFeature {
+    pub name: String,
+    pub resolution: UVec3,
+    pub channels: usize,
+    pub scalar_type: ScalarType,
+    /// Values with the channels interleaved and x changing fastest
+    pub values: Vec<f32>,
+}
+
+/// Contents of a `.cvol` file
+#[derive(Debug, Clone)]
+pub struct CvolFile {
+    /// Size of the box the volume is rendered in
+    pub world_size: Vec3,
+    pub features: Vec<CvolFeature>,
+}
+
+/// Reads little endian values from a byte slice
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self.bytes.get(self.position..self.position + len)
+            .ok_or_else(|| anyhow!("File ends after {} bytes while reading {} more", self.position, len))?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn f32(&mut self) -> Result<f32> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    /// Decompresses a stream of LZ4 blocks into exactly `len` bytes
+    fn compressed(&mut self, len: usize) -> Result<Vec<u8>> {
+        let mut data = vec![0u8; len];
+        let mut written = 0;
+        loop {
+            let block_len = self.i32()?;
+            if block_len <= 0 {
+                break;
+            }
+            let block = self.take(block_len as usize)?;
+            let (previous, output) = data.split_at_mut(written);
+            let dictionary = &previous[previous.len().saturating_sub(LZ4_WINDOW)..];
+            written += lz4_flex::block::decompress_into_with_dict(block, output, dictionary)
+                .map_err(|err| anyhow!("Could not decompress LZ4 block: {}", err))?;
+        }
+        if written != len {
+            bail!("Compressed payload holds {} bytes, but {} are needed", written, len);
+        }
+        Ok(data)
+    }
+}
+
+fn parse_data_type(value: u32) -> Result<ScalarType> {
+    match value {
+        0 => Ok(ScalarType::U8),
+        1 => Ok(ScalarType::U16),
+        2 => Ok(ScalarType::F32),
+        _ => bail!("Unknown cvol data type {}", value),
+    }
+}
+
+impl CvolFile {
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader { bytes, position: 0 };
+        let magic = reader.take(4)?;
+        if !magic.eq_ignore_ascii_case(MAGIC) {
+            bail!("File does not start with the cvol magic, found {:?}", magic);
+        }
+        let version = reader.i32()?;
+        if version > VERSION {
+            bail!("Unsupported cvol version {}", version);
+        }
+        let world_size = Vec3::new(reader.f32()?, reader.f32()?, reader.f32()?);
+        let feature_count = reader.i32()?;
+        let compressed = reader.i32()? & FLAG_COMPRESSED != 0;
+        reader.take(4)?;
+
+        let mut features = vec![];
+        for _ in 0..feature_count {
+            let name_len = reader.u32()? as usize;
+            let name = String::from_utf8_lossy(reader.take(name_len)?).to_string();
+            let resolution = UVec3::new(reader.u64()? as u32, reader.u64()? as u32, reader.u64()? as u32);
+            let channels = reader.u32()? as usize;
+            let scalar_type = parse_data_type(reader.u32()?)?;
+
+            let len = resolution.x as usize * resolution.y as usize * resolution.z as usize * channels * scalar_type.size();
+            let values = if compressed {
+                decode_scalars(&reader.compressed(len)?, scalar_type, ByteOrder::LittleEndian)
+            } else {
+                decode_scalars(reader.take(len)?, scalar_type, ByteOrder::LittleEndian)
+            };
+            features.push(CvolFeature { name, resolution, channels, scalar_type, values });
+        }
+
+        Ok(Self { world_size, features })
+    }
+
+    /// Builds a volume from one of the features.
+    /// Float features with values between 0 and 1, like the preshaded volumes of DiffDVR, keep their values.
+    pub fn to_volume(&self, feature: usize) -> Result<VolumeData> {
+        let feature = self.features.get(feature).ok_or_else(|| anyhow!("cvol file has no feature {}", feature))?;
+        if !(1..=4).contains(&feature.channels) {
+            bail!("Feature '{}' has {} channels, only 1 to 4 are supported", feature.name, feature.channels);
+        }
+
+        let value_range = match feature.scalar_type {
+            ScalarType::U8 => (0.0, u8::MAX as f32),
+            ScalarType::U16 => (0.0, u16::MAX as f32),
+            _ => {
+                let (min, max) = data_range(&feature.values);
+                if min >= 0.0 && max <= 1.0 { (0.0, 1.0) } else { (min, max) }
+            }
+        };
+        let mut volume = volume_from_values(feature.resolution, feature.channels, &feature.values, value_range, feature.scalar_type)?;
+
+        // The world size stretches the volume, which is expressed through the voxel spacing
+        let spacing = self.world_size / feature.resolution.as_vec3();
+        if spacing.is_finite() && spacing.min_element() > 0.0 {
+            volume.spacing = spacing;
+        }
+        Ok(volume)
+    }
+}
+
+/// Reads a `.cvol` file
+pub fn read_file(path: &str, progress: &LoadProgress) -> Result<CvolFile> {
+    let bytes = progress.read_file(path).with_context(|| format!("Could not open cvol file '{}'", path))?;
+    CvolFile::parse(&bytes).with_context(|| format!("Could not parse cvol file '{}'", path))
+}
+
+/// Reads the first feature of a cvol-File into a volume
+pub fn read_volume(path: &str, progress: &LoadProgress) -> Result<VolumeData> {
+    let file = read_file(path, progress)?;
+    if file.features.len() > 1 {
+        let skipped: Vec<&str> = file.features[1..].iter().map(|feature| feature.name.as_str()).collect();
+        progress.notice(format!("The cvol file '{}' contains {} features, only '{}' is loaded. Skipped features: {}", path, file.features.len(), file.features[0].name, skipped.join(", ")));
+    }
+    file.to_volume(0)
+}
+
+/// Writes the voxels of a Voxel Grid into a compressed cvol-File with float values between 0 and 1 at the precision of the grid.
+/// Scalar volumes are stored as a single channel `density` feature, which DiffDVR colors with a transfer function,
+/// all others as a four channel `color` feature, which DiffDVR uses for preshaded volumes.
+/// The world size is the box the volume is rendered in.
+pub fn write_voxel_grid(path: &str, grid: &VoxelGrid) -> Result<()> {
+    let (_, box_size) = grid.bounding_box();
+    let (name, channels): (&[u8], usize) = if grid.is_scalar() { (b"density", 1) } else { (b"color", 4) };
+
+    let mut bytes = vec![];
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    for size in box_size.to_array() {
+        bytes.extend_from_slice(&size.to_le_bytes());
+    }
+    bytes.extend_from_slice(&1i32.to_le_bytes());
+    bytes.extend_from_slice(&FLAG_COMPRESSED.to_le_bytes());
+    bytes.extend_from_slice(&[0; 4]);
+
+    bytes.extend_from_slice(&(name.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(name);
+    for resolution in grid.dimensions.to_array() {
+        bytes.extend_from_slice(&(resolution as u64).to_le_bytes());
+    }
+    bytes.extend_from_slice(&(channels as u32).to_le_bytes());
+    bytes.extend_from_slice(&2u32.to_le_bytes());
+
+    let voxel_count = grid.voxels()?.len();
+    let mut payload = Vec::with_capacity(voxel_count * channels * 4);
+    for index in 0..voxel_count {
+        // Scalars are used for every channel of the color, the density is the last one
+        let color = grid.voxel_color(index);
+        for value in &color[4 - channels..] {
+            payload.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    for block in payload.chunks(BLOCK_SIZE) {
+        let compressed = lz4_flex::block::compress(block);
+        bytes.extend_from_slice(&(compressed.len() as i32).to_le_bytes());
+        bytes.extend_from_slice(&compressed);
+    }
+    bytes.extend_from_slice(&0i32.to_le_bytes());
+
+    let mut file = fs::File::create(path).with_context(|| format!("Could not create cvol file '{}'", path))?;
+    file.write_all(&bytes)?;
+    Ok(())
+}
@@ -1,82 +1,412 @@
-use std::{fs::File, io::{BufRead, BufReader, Read}, path::Path};
-
-use glam::UVec3;
-use wgpu::{Device, Queue};
-
-use crate::voxel::grid::VoxelGrid;
-use anyhow::Result;
-
-/// Loads a DAT-File into a Voxel Grid
-pub fn open_voxel_grid(path: &str, grid: &mut VoxelGrid, device: &Device, queue: &Queue) -> Result<()> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-
-    let mut raw_file_name: Option<String> = None;
-    let mut resolution: Option<UVec3> = None;
-    let mut bytes_to_read = 1usize;
-    for line in reader.lines() {
-        let line = line?;
-        let collection =  line.split(":").collect::<Vec<&str>>();
-        let value = collection[1].trim();
-        let key = collection[0];
-
-        drop(collection);
-        match key {
-            "ObjectFileName" => {
-                raw_file_name = Some(value.to_string());
-            },
-            "Resolution" => {
-                let sizes = value.split(" ").collect::<Vec<&str>>();
-                resolution = Some(UVec3::new(sizes[0].parse::<u32>()?, sizes[1].parse::<u32>()?, sizes[2].parse::<u32>()?));
-            },
-            "Format" => {
-                println!("{}", value);
-                if value.eq("USHORT") {
-                    bytes_to_read = 2;
-                }
-            },
-            _ => continue
+use std::{fs::File, io::{BufRead, BufReader, Read, Seek, SeekFrom}, path::{Path, PathBuf}};
+
+use glam::{UVec3, Vec3};
+
+use crate::voxel::{format::VoxelFormat, volume::VolumeData};
+use anyhow::{anyhow, bail, Context, Result};
+
+use super::{progress::LoadProgress, scalar::{data_range, decode_scalars, normalize, to_bytes, volume_from_components, volume_from_values, ByteOrder, ScalarType}, stream::{slab_depth, Slab, SlabSink, STREAMING_THRESHOLD}};
+
+/// Scalar type of a single voxel component, given by the `Format` key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatFormat {
+    UChar,
+    UShort,
+    Float,
+}
+
+/// Amount and meaning of the components stored per voxel, given by the `ObjectModel` key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectModel {
+    /// Single intensity value
+    I,
+    Rgb,
+    Rgba,
+}
+
+/// Parsed contents of a DAT header file
+#[derive(Debug, Clone)]
+pub struct DatHeader {
+    pub object_file_name: Option<String>,
+    /// Raw file of the tagged volume, which is read if the header has no `ObjectFileName`. `None` if the key is missing or `---`.
+    pub tagged_file_name: Option<String>,
+    pub resolution: UVec3,
+    pub slice_thickness: Vec3,
+    pub format: DatFormat,
+    pub object_model: ObjectModel,
+    /// Byte order given by the `ByteOrder` key, see [`DatHeader::byte_order`] for files without it
+    pub byte_order: Option<ByteOrder>,
+}
+
+impl DatFormat {
+    fn parse(value: &str) -> Result<Self> {
+        match value.to_ascii_uppercase().as_str() {
+            "UCHAR" | "BYTE" => Ok(Self::UChar),
+            "USHORT" => Ok(Self::UShort),
+            "FLOAT" => Ok(Self::Float),
+            _ => bail!("Unsupported DAT format '{}', expected UCHAR, USHORT or FLOAT", value),
         }
     }
 
-    let raw_file_name = raw_file_name.unwrap();
-    let resolution = resolution.unwrap();
+    pub fn scalar_type(&self) -> ScalarType {
+        match self {
+            Self::UChar => ScalarType::U8,
+            Self::UShort => ScalarType::U16,
+            Self::Float => ScalarType::F32,
+        }
+    }
 
-    let mut path = Path::new(path);
-    path = path.parent().unwrap();
-    let path = path.join(raw_file_name);
+    /// Integer formats are scaled by their maximum value, floats are normalized by the range of the data
+    fn value_range(&self, values: &[f32]) -> (f32, f32) {
+        match self {
+            Self::UChar => (0.0, u8::MAX as f32),
+            Self::UShort => (0.0, u16::MAX as f32),
+            Self::Float => data_range(values),
+        }
+    }
+}
 
+/// Parses the value of the `ByteOrder` or `Endianness` key
+fn parse_byte_order(value: &str) -> Result<ByteOrder> {
+    match value.to_ascii_uppercase().as_str() {
+        "LITTLE_ENDIAN" | "LITTLE" | "LSB" => Ok(ByteOrder::LittleEndian),
+        "BIG_ENDIAN" | "BIG" | "MSB" => Ok(ByteOrder::BigEndian),
+        _ => bail!("Unsupported byte order '{}', expected LITTLE_ENDIAN or BIG_ENDIAN", value),
+    }
+}
 
-    let mut raw_file = File::open(path)?;
-    let mut bytes: Vec<u8>;
+impl ObjectModel {
+    fn parse(value: &str) -> Result<Self> {
+        match value.to_ascii_uppercase().as_str() {
+            "I" | "L" => Ok(Self::I),
+            "RGB" => Ok(Self::Rgb),
+            "RGBA" => Ok(Self::Rgba),
+            _ => bail!("Unsupported object model '{}', expected I, RGB or RGBA", value),
+        }
+    }
 
-    let mut all_bytes = vec![0;0];
-    raw_file.read_to_end(&mut all_bytes)?;
-    let mut current_index: usize = 0;
+    pub fn components(&self) -> usize {
+        match self {
+            Self::I => 1,
+            Self::Rgb => 3,
+            Self::Rgba => 4,
+        }
+    }
+}
 
-    *grid = VoxelGrid::new(resolution, &device, &queue);
-    for z in 0..resolution.z {
-        for y in 0..resolution.y {
-            for x in 0..resolution.x {
-                // raw_file.read(&mut bytes)?;
-                bytes = all_bytes[current_index..current_index+bytes_to_read].to_vec();
-                 
-                let mut val: usize = 0;
-                for (i, byte) in (&bytes).into_iter().enumerate() {
-                    val |= *byte as usize;
-                    if i < bytes_to_read - 1 {
-                    val <<= 8;
-                    }
-                }
+impl DatHeader {
+    /// Parses a DAT header. Keys are matched case-insensitively and unknown keys are ignored.
+    /// `Resolution` and `ObjectFileName` or `TaggedFileName` are required, everything else falls back to
+    /// 8-bit intensity data with a slice thickness of 1.
+    pub fn from_reader<R: BufRead>(reader: R) -> Result<Self> {
+        let mut object_file_name: Option<String> = None;
+        let mut tagged_file_name: Option<String> = None;
+        let mut resolution: Option<UVec3> = None;
+        let mut slice_thickness = Vec3::ONE;
+        let mut format = DatFormat::UChar;
+        let mut object_model = ObjectModel::I;
+        let mut byte_order = None;
+
+        for (line_number, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
 
-                let byte: u8 = ((val as f32 / (usize::pow(2, bytes_to_read as u32 * 8) - 1) as f32) * 255.0) as u8;
-                // println!("Read {} vs {}", val, byte);
-                grid.set_color(UVec3::new(x,y,z), [byte, byte, byte, byte]);
-                current_index += bytes_to_read;
+            let (key, value) = line.split_once(':')
+                .ok_or_else(|| anyhow!("Malformed DAT header line {}: '{}' is not a 'Key: Value' pair", line_number + 1, line))?;
+            let value = value.trim();
+
+            match key.trim().to_ascii_lowercase().as_str() {
+                "objectfilename" => {
+                    object_file_name = Some(value.to_string());
+                },
+                // Files without a tagged volume name it '---'
+                "taggedfilename" if value != "---" => {
+                    tagged_file_name = Some(value.to_string());
+                },
+                "resolution" => {
+                    let sizes = parse_values::<u32>(value, "Resolution", line_number)?;
+                    if sizes.contains(&0) {
+                        bail!("Resolution on line {} must not contain zero sizes", line_number + 1);
+                    }
+                    resolution = Some(UVec3::from_array(sizes));
+                },
+                "slicethickness" => {
+                    let thickness = parse_values::<f32>(value, "SliceThickness", line_number)?;
+                    if thickness.iter().any(|t| !t.is_finite() || *t <= 0.0) {
+                        bail!("SliceThickness on line {} must be positive", line_number + 1);
+                    }
+                    slice_thickness = Vec3::from_array(thickness);
+                },
+                "format" => {
+                    format = DatFormat::parse(value)?;
+                },
+                "objectmodel" => {
+                    object_model = ObjectModel::parse(value)?;
+                },
+                "byteorder" | "endianness" | "endian" => {
+                    byte_order = Some(parse_byte_order(value)?);
+                },
+                _ => continue
             }
         }
-        // println!("Loaded {} out of {} layers", z, resolution.z);
+
+        if object_file_name.is_none() && tagged_file_name.is_none() {
+            bail!("DAT header is missing the 'ObjectFileName' or 'TaggedFileName' key");
+        }
+        Ok(Self {
+            object_file_name,
+            tagged_file_name,
+            resolution: resolution.ok_or_else(|| anyhow!("DAT header is missing the 'Resolution' key"))?,
+            slice_thickness,
+            format,
+            object_model,
+            byte_order,
+        })
+    }
+
+    /// Returns the path of the raw file, the `TaggedFileName` if there is no `ObjectFileName`.
+    /// Relative paths are resolved against the directory of the DAT file.
+    pub fn raw_file_path(&self, dat_path: &Path) -> PathBuf {
+        let parent = dat_path.parent().unwrap_or(Path::new(""));
+        let file_name = self.object_file_name.as_deref().or(self.tagged_file_name.as_deref()).unwrap_or_default();
+        parent.join(file_name)
+    }
+
+    /// Amount of bytes the raw file needs to contain for the given resolution, format and object model
+    pub fn expected_byte_len(&self) -> usize {
+        self.resolution.x as usize * self.resolution.y as usize * self.resolution.z as usize
+            * self.object_model.components() * self.format.scalar_type().size()
+    }
+
+    /// Byte order of the raw file. Without a `ByteOrder` key both byte orders decode a slice of the data,
+    /// given as `sample`, and the one with the smaller value range is used, since swapped bytes spread
+    /// the values over the whole range of the type. Swapped floats are mostly non-finite, denormal or huge,
+    /// so the order with fewer of those wins first. Files whose orders can't be told apart are big endian,
+    /// like the files the renderer always read before the key was supported.
+    pub fn byte_order(&self, sample: &[u8]) -> ByteOrder {
+        if let Some(byte_order) = self.byte_order {
+            return byte_order;
+        }
+
+        let scalar_type = self.format.scalar_type();
+        let score = |byte_order: ByteOrder| {
+            let values = decode_scalars(sample, scalar_type, byte_order);
+            let implausible = values.iter()
+                .filter(|value| !value.is_finite() || (**value != 0.0 && !(1e-20..1e20).contains(&value.abs())))
+                .count();
+            let (min, max) = data_range(&values);
+            (implausible, max - min)
+        };
+        if score(ByteOrder::LittleEndian) < score(ByteOrder::BigEndian) {
+            ByteOrder::LittleEndian
+        } else {
+            ByteOrder::BigEndian
+        }
+    }
+
+    /// Amount of bytes of a single z slice in the raw file
+    fn slice_byte_len(&self) -> usize {
+        self.resolution.x as usize * self.resolution.y as usize * self.object_model.components() * self.format.scalar_type().size()
+    }
+
+    /// Whether the RGBA voxels of the volume are too large to be read at once
+    pub fn needs_streaming(&self) -> bool {
+        self.resolution.x as u64 * self.resolution.y as u64 * self.resolution.z as u64 * 4 > STREAMING_THRESHOLD
+    }
+}
+
+/// Reads and parses the header of a DAT-File
+pub fn read_header(path: &str) -> Result<DatHeader> {
+    let file = File::open(path).with_context(|| format!("Could not open DAT file '{}'", path))?;
+    DatHeader::from_reader(BufReader::new(file))
+        .with_context(|| format!("Could not parse DAT header '{}'", path))
+}
+
+/// Reads a DAT-File and its raw file into a volume
+pub fn read_volume(path: &str, progress: &LoadProgress) -> Result<VolumeData> {
+    let header = read_header(path)?;
+
+    let raw_path = header.raw_file_path(Path::new(path));
+    let all_bytes = progress.read_file(&raw_path).with_context(|| format!("Could not open raw file '{}'", raw_path.display()))?;
+
+    let expected_len = header.expected_byte_len();
+    if all_bytes.len() < expected_len {
+        bail!("Raw file '{}' contains {} bytes, but the header describes {} bytes", raw_path.display(), all_bytes.len(), expected_len);
+    }
+
+    let slice_len = header.slice_byte_len();
+    let middle = header.resolution.z as usize / 2 * slice_len;
+    let byte_order = detect_byte_order(path, &header, &all_bytes[middle..middle + slice_len], progress);
+
+    let scalar_type = header.format.scalar_type();
+    let values = decode_scalars(&all_bytes[..expected_len], scalar_type, byte_order);
+    let range = header.format.value_range(&values);
+
+    let mut volume = volume_from_values(header.resolution, header.object_model.components(), &values, range, scalar_type)?;
+    volume.spacing = header.slice_thickness;
+    Ok(volume)
+}
+
+/// Reads the raw file of a DAT-File slab by slab into `sink`, so that only a few slices are kept in memory at once.
+/// Float data is read twice, first to find its value range and then to convert it.
+pub fn stream_volume(path: &str, progress: &LoadProgress, sink: &mut dyn SlabSink) -> Result<()> {
+    let header = read_header(path)?;
+
+    let raw_path = header.raw_file_path(Path::new(path));
+    let file_len = std::fs::metadata(&raw_path).with_context(|| format!("Could not open raw file '{}'", raw_path.display()))?.len();
+    let expected_len = header.expected_byte_len();
+    if file_len < expected_len as u64 {
+        bail!("Raw file '{}' contains {} bytes, but the header describes {} bytes", raw_path.display(), file_len, expected_len);
+    }
+
+    let byte_order = detect_byte_order(path, &header, &read_middle_slice(&raw_path, &header)?, progress);
+    let range = match header.format {
+        DatFormat::Float => {
+            let mut range = (f32::MAX, f32::MIN);
+            read_slabs(&raw_path, &header, byte_order, progress, |_, values| {
+                let (min, max) = data_range(&values);
+                range = (range.0.min(min), range.1.max(max));
+                Ok(())
+            })?;
+            range
+        },
+        format => format.value_range(&[]),
+    };
+
+    // Scalar volumes are streamed into a single channel texture at the precision of the file
+    let components = header.object_model.components();
+    let format = if components == 1 { header.format.scalar_type().scalar_format() } else { VoxelFormat::Rgba8 };
+    sink.start(header.resolution, header.slice_thickness, format)?;
+    read_slabs(&raw_path, &header, byte_order, progress, |z, values| {
+        if components == 1 {
+            return sink.write_slab(z, Slab::Scalars(normalize(&values, range)));
+        }
+        let depth = (values.len() / (header.resolution.x as usize * header.resolution.y as usize * components)) as u32;
+        let dimensions = UVec3::new(header.resolution.x, header.resolution.y, depth);
+        let slab = volume_from_components(dimensions, components, &to_bytes(&values, range))?;
+        sink.write_slab(z, Slab::Colors(slab.voxels))
+    })
+}
+
+/// Picks the byte order of the raw file with [`DatHeader::byte_order`] and tells the user if it was detected as little endian
+fn detect_byte_order(path: &str, header: &DatHeader, middle_slice: &[u8], progress: &LoadProgress) -> ByteOrder {
+    let byte_order = header.byte_order(middle_slice);
+    if header.byte_order.is_none() && header.format != DatFormat::UChar && byte_order == ByteOrder::LittleEndian {
+        progress.notice(format!("'{}' has no ByteOrder key, its values were read as little endian because their range is smaller that way", path));
+    }
+    byte_order
+}
+
+/// Reads the bytes of the middle z slice of the raw file
+fn read_middle_slice(raw_path: &Path, header: &DatHeader) -> Result<Vec<u8>> {
+    let mut file = File::open(raw_path).with_context(|| format!("Could not open raw file '{}'", raw_path.display()))?;
+    let slice_len = header.slice_byte_len();
+    let mut bytes = vec![0; slice_len];
+    file.seek(SeekFrom::Start((header.resolution.z as usize / 2 * slice_len) as u64))?;
+    file.read_exact(&mut bytes).with_context(|| format!("Could not read the middle slice of raw file '{}'", raw_path.display()))?;
+    Ok(bytes)
+}
+
+/// Decodes the raw file slab by slab and hands the values of each slab with its first z slice to `read_slab`
+fn read_slabs(raw_path: &Path, header: &DatHeader, byte_order: ByteOrder, progress: &LoadProgress, mut read_slab: impl FnMut(u32, Vec<f32>) -> Result<()>) -> Result<()> {
+    let mut file = File::open(raw_path).with_context(|| format!("Could not open raw file '{}'", raw_path.display()))?;
+    let resolution = header.resolution;
+    let slice_len = header.slice_byte_len();
+    let slab_depth = slab_depth(resolution);
+
+    progress.start_slices(resolution.z as usize);
+    let mut bytes = vec![];
+    for z in (0..resolution.z).step_by(slab_depth as usize) {
+        let depth = slab_depth.min(resolution.z - z);
+        bytes.resize(slice_len * depth as usize, 0);
+        file.read_exact(&mut bytes).with_context(|| format!("Could not read slices {} to {} of raw file '{}'", z, z + depth - 1, raw_path.display()))?;
+        read_slab(z, decode_scalars(&bytes, header.format.scalar_type(), byte_order))?;
+        progress.finish_slices(depth as usize)?;
     }
-    grid.update_buffer(&queue);
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Parses a whitespace separated list of exactly three values
+fn parse_values<T: std::str::FromStr>(value: &str, key: &str, line_number: usize) -> Result<[T; 3]>
+where T::Err: std::error::Error + Send + Sync + 'static {
+    let parsed = value.split_whitespace()
+        .map(|v| v.parse::<T>())
+        .collect::<std::result::Result<Vec<T>, _>>()
+        .with_context(|| format!("Could not parse '{}' value '{}' on line {}", key, value, line_number + 1))?;
+
+    parsed.try_into()
+        .map_err(|_| anyhow!("'{}' on line {} needs exactly three values, found '{}'", key, line_number + 1, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(header: &str) -> Result<DatHeader> {
+        DatHeader::from_reader(header.as_bytes())
+    }
+
+    #[test]
+    fn parses_all_keys() {
+        let header = parse("ObjectFileName: head.raw\nTaggedFileName: ---\nresolution: 4 5 6\nSliceThickness: 1 1 2.5\nFormat: USHORT\nObjectModel: RGBA\nByteOrder: LITTLE_ENDIAN\n").unwrap();
+        assert_eq!(header.object_file_name.as_deref(), Some("head.raw"));
+        assert_eq!(header.tagged_file_name, None);
+        assert_eq!(header.resolution, UVec3::new(4, 5, 6));
+        assert_eq!(header.slice_thickness, Vec3::new(1.0, 1.0, 2.5));
+        assert_eq!(header.format, DatFormat::UShort);
+        assert_eq!(header.object_model, ObjectModel::Rgba);
+        assert_eq!(header.byte_order, Some(ByteOrder::LittleEndian));
+        assert_eq!(header.expected_byte_len(), 4 * 5 * 6 * 4 * 2);
+    }
+
+    #[test]
+    fn falls_back_to_tagged_file_name() {
+        let header = parse("TaggedFileName: head.tag\nResolution: 2 2 2").unwrap();
+        assert_eq!(header.object_file_name, None);
+        assert_eq!(header.raw_file_path(Path::new("data/head.dat")), Path::new("data/head.tag"));
+    }
+
+    #[test]
+    fn byte_order_key_is_used() {
+        let header = parse("ObjectFileName: head.raw\nResolution: 2 2 2\nFormat: USHORT\nEndianness: little").unwrap();
+        let byte_order = header.byte_order(&[0x01, 0x02]);
+        assert_eq!(decode_scalars(&[0x01, 0x02], header.format.scalar_type(), byte_order), vec![513.0]);
+
+        // The key wins over the detection, which would pick big endian for these values
+        let sample: Vec<u8> = [1000u16, 1010, 1020].iter().flat_map(|value| value.to_be_bytes()).collect();
+        assert_eq!(header.byte_order(&sample), ByteOrder::LittleEndian);
+    }
+
+    #[test]
+    fn detects_byte_order_without_key() {
+        let header = parse("ObjectFileName: head.raw\nResolution: 2 2 2\nFormat: USHORT").unwrap();
+        assert_eq!(header.byte_order, None);
+
+        // 12-bit CT values
+        let values = [1000u16, 1010, 1020, 3000, 4095, 0];
+        let little: Vec<u8> = values.iter().flat_map(|value| value.to_le_bytes()).collect();
+        let big: Vec<u8> = values.iter().flat_map(|value| value.to_be_bytes()).collect();
+        assert_eq!(header.byte_order(&little), ByteOrder::LittleEndian);
+        assert_eq!(header.byte_order(&big), ByteOrder::BigEndian);
+        assert_eq!(header.byte_order(&[0; 8]), ByteOrder::BigEndian);
+
+        let header = parse("ObjectFileName: head.raw\nResolution: 2 2 2\nFormat: FLOAT").unwrap();
+        let values = [0.25f32, 0.5, 1.0, 2.0];
+        let little: Vec<u8> = values.iter().flat_map(|value| value.to_le_bytes()).collect();
+        let big: Vec<u8> = values.iter().flat_map(|value| value.to_be_bytes()).collect();
+        assert_eq!(header.byte_order(&little), ByteOrder::LittleEndian);
+        assert_eq!(header.byte_order(&big), ByteOrder::BigEndian);
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        assert!(parse("Resolution: 2 2 2").is_err());
+        assert!(parse("ObjectFileName: head.raw\nResolution: 2 0 2").is_err());
+        assert!(parse("ObjectFileName: head.raw\nResolution: 2 2").is_err());
+        assert!(parse("ObjectFileName: head.raw\nResolution: 2 2 2\nFormat: DOUBLE").is_err());
+        assert!(parse("ObjectFileName head.raw").is_err());
+    }
+}
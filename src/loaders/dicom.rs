@@ -0,0 +1,253 @@
+use std::{cmp::Ordering, collections::BTreeMap, fs, path::{Path, PathBuf}};
+
+use anyhow::{anyhow, bail, Context, Result};
+use dicom_dictionary_std::tags;
+use dicom_object::{DefaultDicomObject, OpenFileOptions};
+use glam::{UVec3, Vec3};
+
+use crate::voxel::volume::VolumeData;
+
+use super::{progress::LoadProgress, scalar::{data_range, decode_scalars, volume_from_values, ByteOrder, ScalarType}};
+
+/// Slices of a folder that belong to the same series
+#[derive(Debug, Clone)]
+pub struct DicomSeries {
+    pub uid: String,
+    pub description: String,
+    pub modality: String,
+    pub files: Vec<PathBuf>,
+}
+
+impl DicomSeries {
+    /// Human readable name of the series, used in the series window and the window title
+    pub fn name(&self) -> String {
+        let description = if self.description.is_empty() { &self.uid } else { &self.description };
+        format!("{} {} ({} files)", self.modality, description, self.files.len())
+    }
+}
+
+/// A single DICOM file of a series together with its position along the slice normal
+struct DicomSlice {
+    object: DefaultDicomObject,
+    position: Option<Vec3>,
+    instance_number: i32,
+}
+
+/// Reads a string attribute, returning an empty string if it is missing
+fn string_attribute(object: &DefaultDicomObject, tag: dicom_object::Tag) -> String {
+    object.element_opt(tag).ok().flatten()
+        .and_then(|element| element.to_str().ok())
+        .map(|value| value.trim().to_string())
+        .unwrap_or_default()
+}
+
+/// Reads all values of a numeric attribute, returning `None` if it is missing or malformed
+fn float_attribute(object: &DefaultDicomObject, tag: dicom_object::Tag) -> Option<Vec<f64>> {
+    object.element_opt(tag).ok().flatten()
+        .and_then(|element| element.to_multi_float64().ok())
+        .filter(|values| !values.is_empty())
+}
+
+fn int_attribute(object: &DefaultDicomObject, tag: dicom_object::Tag) -> Option<i64> {
+    object.element_opt(tag).ok().flatten()
+        .and_then(|element| element.to_int::<i64>().ok())
+}
+
+fn vec3_attribute(object: &DefaultDicomObject, tag: dicom_object::Tag) -> Option<Vec3> {
+    float_attribute(object, tag)
+        .filter(|values| values.len() >= 3)
+        .map(|values| Vec3::new(values[0] as f32, values[1] as f32, values[2] as f32))
+}
+
+/// Collects all files below a directory
+fn collect_files(directory: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(directory).with_context(|| format!("Could not read directory '{}'", directory.display()))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Scans a directory and its subdirectories for DICOM files and groups them by their series.
+/// Files that are not DICOM files are skipped.
+pub fn list_series(directory: &str, progress: &LoadProgress) -> Result<Vec<DicomSeries>> {
+    let mut files = vec![];
+    collect_files(Path::new(directory), &mut files)?;
+    files.sort();
+    progress.start_slices(files.len());
+
+    let mut series: BTreeMap<String, DicomSeries> = BTreeMap::new();
+    for file in files {
+        progress.finish_slice()?;
+        // Only the attributes before the pixel data are needed for grouping
+        let Ok(object) = OpenFileOptions::new().read_until(tags::PIXEL_DATA).open_file(&file) else {
+            continue;
+        };
+        let uid = string_attribute(&object, tags::SERIES_INSTANCE_UID);
+        series.entry(uid.clone())
+            .or_insert_with(|| DicomSeries {
+                uid,
+                description: string_attribute(&object, tags::SERIES_DESCRIPTION),
+                modality: string_attribute(&object, tags::MODALITY),
+                files: vec![],
+            })
+            .files
+            .push(file);
+    }
+
+    if series.is_empty() {
+        bail!("Directory '{}' does not contain any DICOM files", directory);
+    }
+    Ok(series.into_values().collect())
+}
+
+/// Reads the slices of a series into a volume.
+/// Slices are ordered by their position along the slice normal, or by their instance number if positions are missing.
+/// Stored values are converted with the rescale slope and intercept and, if `apply_window` is set,
+/// the window center and width of the first slice select the mapped value range.
+pub fn read_series(series: &DicomSeries, apply_window: bool, progress: &LoadProgress) -> Result<VolumeData> {
+    progress.start_slices(series.files.len());
+    let mut slices = series.files.iter()
+        .map(|file| {
+            let object = dicom_object::open_file(file).with_context(|| format!("Could not open DICOM file '{}'", file.display()))?;
+            progress.finish_slice()?;
+            Ok(DicomSlice {
+                position: vec3_attribute(&object, tags::IMAGE_POSITION_PATIENT),
+                instance_number: int_attribute(&object, tags::INSTANCE_NUMBER).unwrap_or(0) as i32,
+                object,
+            })
+        })
+        .collect::<Result<Vec<DicomSlice>>>()?;
+    let first = &slices.first().ok_or_else(|| anyhow!("DICOM series '{}' contains no files", series.uid))?.object;
+
+    // Image orientation holds the directions of the rows and columns in patient space
+    let orientation = float_attribute(first, tags::IMAGE_ORIENTATION_PATIENT)
+        .filter(|values| values.len() >= 6)
+        .map(|values| values.iter().map(|value| *value as f32).collect::<Vec<f32>>());
+    let (row_direction, column_direction) = match &orientation {
+        Some(values) => (Vec3::from_slice(&values[0..3]), Vec3::from_slice(&values[3..6])),
+        None => (Vec3::X, Vec3::Y),
+    };
+    let normal = row_direction.cross(column_direction).normalize_or_zero();
+
+    let use_positions = normal != Vec3::ZERO && slices.iter().all(|slice| slice.position.is_some());
+    if use_positions {
+        slices.sort_by(|a, b| {
+            let a = a.position.unwrap_or_default().dot(normal);
+            let b = b.position.unwrap_or_default().dot(normal);
+            a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+        });
+    } else {
+        slices.sort_by_key(|slice| slice.instance_number);
+    }
+
+    let first = &slices[0].object;
+    let columns = int_attribute(first, tags::COLUMNS).ok_or_else(|| anyhow!("DICOM file is missing the Columns attribute"))? as u32;
+    let rows = int_attribute(first, tags::ROWS).ok_or_else(|| anyhow!("DICOM file is missing the Rows attribute"))? as u32;
+    let pixel_spacing = float_attribute(first, tags::PIXEL_SPACING).filter(|values| values.len() >= 2);
+    let window = match (float_attribute(first, tags::WINDOW_CENTER), float_attribute(first, tags::WINDOW_WIDTH)) {
+        (Some(center), Some(width)) if width[0] > 0.0 => Some((center[0] as f32, width[0] as f32)),
+        _ => None,
+    };
+
+    let mut values = vec![];
+    let mut scalar_type = ScalarType::U8;
+    let mut slice_count = 0;
+    progress.start_slices(slices.len());
+    for (i, slice) in slices.iter().enumerate() {
+        let slice_rows = int_attribute(&slice.object, tags::ROWS).unwrap_or(0) as u32;
+        let slice_columns = int_attribute(&slice.object, tags::COLUMNS).unwrap_or(0) as u32;
+        if (slice_rows, slice_columns) != (rows, columns) {
+            bail!("Slice {} of the series has a size of {}x{} instead of {}x{}", i, slice_columns, slice_rows, columns, rows);
+        }
+        let (frames, slice_type) = read_frames(&slice.object, (rows * columns) as usize)
+            .with_context(|| format!("Could not read the pixel data of slice {}", i))?;
+        if slice_type.size() > scalar_type.size() {
+            scalar_type = slice_type;
+        }
+        slice_count += frames.len() / (rows * columns) as usize;
+        values.extend(frames);
+        progress.finish_slice()?;
+    }
+
+    let value_range = match window {
+        Some((center, width)) if apply_window => (center - width / 2.0, center + width / 2.0),
+        _ => data_range(&values),
+    };
+    let mut volume = volume_from_values(UVec3::new(columns, rows, slice_count as u32), 1, &values, value_range, scalar_type)?;
+
+    let slice_spacing = if use_positions && slices.len() > 1 {
+        median_distance(&slices, normal)
+    } else {
+        None
+    };
+    let slice_spacing = slice_spacing
+        .or_else(|| float_attribute(first, tags::SPACING_BETWEEN_SLICES).map(|values| values[0] as f32))
+        .or_else(|| float_attribute(first, tags::SLICE_THICKNESS).map(|values| values[0] as f32))
+        .filter(|spacing| spacing.is_finite() && *spacing > 0.0)
+        .unwrap_or(1.0);
+    volume.spacing = match pixel_spacing {
+        // Pixel spacing is given as the distance between rows followed by the distance between columns
+        Some(spacing) => Vec3::new(spacing[1] as f32, spacing[0] as f32, slice_spacing),
+        None => Vec3::new(1.0, 1.0, slice_spacing),
+    };
+
+    // Patient space is left-posterior-superior, the renderer uses right-anterior-superior
+    if orientation.is_some() {
+        let to_ras = Vec3::new(-1.0, -1.0, 1.0);
+        volume.reorient([row_direction * to_ras, column_direction * to_ras, normal * to_ras]);
+    }
+    Ok(volume)
+}
+
+/// Median distance between neighbouring slices along the slice normal
+fn median_distance(slices: &[DicomSlice], normal: Vec3) -> Option<f32> {
+    let mut distances: Vec<f32> = slices.windows(2)
+        .filter_map(|pair| Some((pair[1].position? - pair[0].position?).dot(normal).abs()))
+        .filter(|distance| *distance > 1e-4)
+        .collect();
+    distances.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    distances.get(distances.len() / 2).copied()
+}
+
+/// Decodes the native pixel data of a file into rescaled values, one block of `pixels_per_frame` values per frame.
+/// Also returns the type the pixels are stored as.
+fn read_frames(object: &DefaultDicomObject, pixels_per_frame: usize) -> Result<(Vec<f32>, ScalarType)> {
+    let samples_per_pixel = int_attribute(object, tags::SAMPLES_PER_PIXEL).unwrap_or(1);
+    if samples_per_pixel != 1 {
+        bail!("Only grayscale DICOM images are supported, found {} samples per pixel", samples_per_pixel);
+    }
+
+    let bits_allocated = int_attribute(object, tags::BITS_ALLOCATED).unwrap_or(16);
+    let signed = int_attribute(object, tags::PIXEL_REPRESENTATION).unwrap_or(0) == 1;
+    let scalar_type = match (bits_allocated, signed) {
+        (8, false) => ScalarType::U8,
+        (8, true) => ScalarType::I8,
+        (16, false) => ScalarType::U16,
+        (16, true) => ScalarType::I16,
+        (32, false) => ScalarType::U32,
+        (32, true) => ScalarType::I32,
+        _ => bail!("Unsupported DICOM bit depth of {} bits", bits_allocated),
+    };
+
+    let pixel_data = object.element(tags::PIXEL_DATA).context("DICOM file contains no pixel data")?;
+    // Native pixel data is held in the byte order of the machine after parsing
+    let bytes = pixel_data.to_bytes()
+        .with_context(|| format!("Compressed pixel data with transfer syntax {} is not supported", object.meta().transfer_syntax()))?;
+
+    let frame_count = int_attribute(object, tags::NUMBER_OF_FRAMES).unwrap_or(1).max(1) as usize;
+    let byte_len = frame_count * pixels_per_frame * scalar_type.size();
+    if bytes.len() < byte_len {
+        bail!("Pixel data contains {} bytes, but {} frames need {}", bytes.len(), frame_count, byte_len);
+    }
+
+    let slope = float_attribute(object, tags::RESCALE_SLOPE).map_or(1.0, |values| values[0] as f32);
+    let intercept = float_attribute(object, tags::RESCALE_INTERCEPT).map_or(0.0, |values| values[0] as f32);
+    let mut values = decode_scalars(&bytes[..byte_len], scalar_type, ByteOrder::native());
+    values.iter_mut().for_each(|value| *value = *value * slope + intercept);
+    Ok((values, scalar_type))
+}
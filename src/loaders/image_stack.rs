@@ -0,0 +1,189 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use anyhow::{anyhow, bail, Context, Result};
+use glam::UVec3;
+use image::{DynamicImage, RgbaImage};
+
+use crate::voxel::{grid::VoxelGrid, volume::VolumeData};
+
+use super::{natural_cmp, progress::LoadProgress, scalar::{data_range, volume_from_values, ScalarType}};
+
+/// Extensions of the images that can be stacked into a volume
+pub const IMAGE_EXTENSIONS: [&str; 5] = ["png", "jpg", "jpeg", "tif", "tiff"];
+
+/// How the pixels of the slices are turned into voxels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageStackMode {
+    /// The gray value of a pixel is used as density
+    Density,
+    /// The pixel is used as preshaded color, images without alpha use their brightest channel as density
+    Color,
+}
+
+/// Axis along which a volume is cut into slices
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SliceAxis {
+    X,
+    Y,
+    Z,
+}
+
+impl SliceAxis {
+    fn name(&self) -> &'static str {
+        match self {
+            SliceAxis::X => "x",
+            SliceAxis::Y => "y",
+            SliceAxis::Z => "z",
+        }
+    }
+}
+
+fn is_image_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| IMAGE_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()))
+}
+
+/// Matches a file name against a pattern where `*` stands for any number of characters and `?` for a single one
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` and the name position it currently covers up to
+    let mut backtrack = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star, covered)) = backtrack {
+            p = star + 1;
+            n = covered + 1;
+            backtrack = Some((star, n));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Lists the slices of an image stack in natural order.
+/// `pattern` is either a directory, in which case all images inside it are used,
+/// or a path whose file name contains the wildcards `*` and `?`, e.g. `scan/slice_*.tif`.
+pub fn list_images(pattern: &str) -> Result<Vec<PathBuf>> {
+    let path = Path::new(pattern);
+    let (directory, file_pattern) = if path.is_dir() {
+        (path, "*")
+    } else {
+        let file_pattern = path.file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| anyhow!("'{}' is neither a directory nor a file pattern", pattern))?;
+        (path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new(".")), file_pattern)
+    };
+
+    let mut files = vec![];
+    for entry in fs::read_dir(directory).with_context(|| format!("Could not read directory '{}'", directory.display()))? {
+        let file = entry?.path();
+        let matches = file.file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| wildcard_match(file_pattern, name));
+        if file.is_file() && matches && is_image_path(&file) {
+            files.push(file);
+        }
+    }
+    files.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+
+    if files.is_empty() {
+        bail!("No images found for '{}'", pattern);
+    }
+    Ok(files)
+}
+
+/// Stacks the images along the z axis, the first image becoming the slice at z = 0.
+/// Images are stored top to bottom, so their rows are flipped to keep them upright along the y axis.
+pub fn read_stack(files: &[PathBuf], mode: ImageStackMode, progress: &LoadProgress) -> Result<VolumeData> {
+    progress.start_slices(files.len());
+    let mut size = None;
+    let mut components = 0;
+    // Widest type of the slices, 16-bit and float slices are kept at full precision
+    let mut scalar_type = ScalarType::U8;
+    let mut values = vec![];
+
+    for file in files {
+        let image = image::open(file).with_context(|| format!("Could not open image '{}'", file.display()))?;
+        let image_size = (image.width(), image.height());
+        if *size.get_or_insert(image_size) != image_size {
+            let (width, height) = size.unwrap_or_default();
+            bail!("Image '{}' has a size of {}x{} instead of {}x{}", file.display(), image_size.0, image_size.1, width, height);
+        }
+
+        let (slice, slice_components) = slice_values(&image, mode);
+        if components != 0 && components != slice_components {
+            bail!("Image '{}' has {} channels, but the previous slices have {}", file.display(), slice_components, components);
+        }
+        components = slice_components;
+        let image_type = match image {
+            DynamicImage::ImageLuma8(_) | DynamicImage::ImageLumaA8(_) | DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgba8(_) => ScalarType::U8,
+            DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => ScalarType::F32,
+            _ => ScalarType::U16,
+        };
+        if image_type.size() > scalar_type.size() {
+            scalar_type = image_type;
+        }
+
+        let row_len = image_size.0 as usize * components;
+        for row in slice.chunks_exact(row_len).rev() {
+            values.extend_from_slice(row);
+        }
+        progress.finish_slice()?;
+    }
+
+    let (width, height) = size.ok_or_else(|| anyhow!("Image stack contains no images"))?;
+    // 16-bit and float slices, e.g. from microscopes, often only use a small part of their range
+    let value_range = if scalar_type != ScalarType::U8 && mode == ImageStackMode::Density { data_range(&values) } else { (0.0, 1.0) };
+    volume_from_values(UVec3::new(width, height, files.len() as u32), components, &values, value_range, scalar_type)
+}
+
+/// Converts an image into values between 0 and 1 with the number of components per pixel
+fn slice_values(image: &DynamicImage, mode: ImageStackMode) -> (Vec<f32>, usize) {
+    match mode {
+        ImageStackMode::Density => (image.to_luma32f().into_raw(), 1),
+        ImageStackMode::Color if image.color().has_alpha() => (image.to_rgba32f().into_raw(), 4),
+        ImageStackMode::Color => (image.to_rgb32f().into_raw(), 3),
+    }
+}
+
+/// Writes every slice of a Voxel Grid along an axis as RGBA PNG files into a directory.
+/// Files are named like `slice_z_0000.png`, and Z slices can be loaded again as an image stack.
+pub fn write_slices(directory: &str, grid: &VoxelGrid, axis: SliceAxis) -> Result<()> {
+    let dimensions = grid.dimensions;
+    let voxels = grid.voxels()?;
+    // Slices are indexed by the cut axis, images by their column and their row counted from the top
+    let (count, width, height) = match axis {
+        SliceAxis::X => (dimensions.x, dimensions.y, dimensions.z),
+        SliceAxis::Y => (dimensions.y, dimensions.x, dimensions.z),
+        SliceAxis::Z => (dimensions.z, dimensions.x, dimensions.y),
+    };
+    let digits = count.max(1).to_string().len().max(4);
+
+    fs::create_dir_all(directory).with_context(|| format!("Could not create directory '{}'", directory))?;
+    for slice in 0..count {
+        let image = RgbaImage::from_fn(width, height, |column, row| {
+            let up = height - 1 - row;
+            let position = match axis {
+                SliceAxis::X => UVec3::new(slice, column, up),
+                SliceAxis::Y => UVec3::new(column, slice, up),
+                SliceAxis::Z => UVec3::new(column, up, slice),
+            };
+            let index = position.x + dimensions.x * (position.y + dimensions.y * position.z);
+            image::Rgba(voxels[index as usize].color)
+        });
+
+        let path = Path::new(directory).join(format!("slice_{}_{:0digits$}.png", axis.name(), slice, digits = digits));
+        image.save(&path).with_context(|| format!("Could not write slice '{}'", path.display()))?;
+    }
+    Ok(())
+}
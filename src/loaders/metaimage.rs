@@ -0,0 +1,239 @@
+use std::{io::Read, path::{Path, PathBuf}};
+
+use anyhow::{anyhow, bail, Context, Result};
+use flate2::read::ZlibDecoder;
+use glam::{UVec3, Vec3};
+
+use crate::voxel::volume::VolumeData;
+
+use super::{expand_file_pattern, progress::LoadProgress, scalar::{decode_scalars, normalization_range, volume_from_values, ByteOrder, ScalarType}};
+
+/// Parsed contents of a MetaImage header
+#[derive(Debug, Clone)]
+pub struct MetaImageHeader {
+    pub dimensions: UVec3,
+    pub scalar_type: ScalarType,
+    pub components: usize,
+    pub spacing: Vec3,
+    pub byte_order: ByteOrder,
+    /// Whether the data is a zlib stream
+    pub compressed: bool,
+    /// Amount of bytes to skip at the start of every data file, -1 means that the data is at the end of the file
+    pub header_size: i64,
+    /// Direction of the x, y and z axis in left-posterior-superior space, if the header has a `TransformMatrix`
+    pub directions: Option<[Vec3; 3]>,
+    /// Detached data files in reading order, empty if the data follows the header (`LOCAL`)
+    pub data_files: Vec<PathBuf>,
+}
+
+/// Parses the value of the `ElementType` key
+fn parse_element_type(value: &str) -> Result<ScalarType> {
+    let element_type = value.to_ascii_uppercase();
+    let scalar_type = match element_type.trim_end_matches("_ARRAY") {
+        "MET_CHAR" => ScalarType::I8,
+        "MET_UCHAR" => ScalarType::U8,
+        "MET_SHORT" => ScalarType::I16,
+        "MET_USHORT" => ScalarType::U16,
+        "MET_INT" | "MET_LONG" => ScalarType::I32,
+        "MET_UINT" | "MET_ULONG" => ScalarType::U32,
+        "MET_LONG_LONG" => ScalarType::I64,
+        "MET_ULONG_LONG" => ScalarType::U64,
+        "MET_FLOAT" => ScalarType::F32,
+        "MET_DOUBLE" => ScalarType::F64,
+        _ => bail!("Unsupported MetaImage element type '{}'", value),
+    };
+    Ok(scalar_type)
+}
+
+/// Parses a MetaImage boolean such as `True` or `False`
+fn parse_bool(value: &str) -> bool {
+    matches!(value.to_ascii_lowercase().as_str(), "true" | "t" | "1" | "yes")
+}
+
+/// Parses a whitespace separated list of exactly `N` values
+fn parse_values<T: std::str::FromStr, const N: usize>(value: &str, key: &str) -> Result<[T; N]>
+where T::Err: std::error::Error + Send + Sync + 'static {
+    let parsed = value.split_whitespace()
+        .map(|v| v.parse::<T>())
+        .collect::<std::result::Result<Vec<T>, _>>()
+        .with_context(|| format!("Could not parse MetaImage '{}' value '{}'", key, value))?;
+
+    parsed.try_into()
+        .map_err(|_| anyhow!("MetaImage '{}' needs exactly {} values, found '{}'", key, N, value))
+}
+
+impl MetaImageHeader {
+    /// Parses the header at the start of a MetaImage file.
+    /// The header ends with the `ElementDataFile` key, the returned offset points behind it.
+    /// Relative data files are resolved against `directory`.
+    pub fn parse(bytes: &[u8], directory: &Path) -> Result<(Self, usize)> {
+        let mut dimensions = None;
+        let mut scalar_type = None;
+        let mut components = 1;
+        let mut spacing = Vec3::ONE;
+        let mut byte_order = ByteOrder::LittleEndian;
+        let mut compressed = false;
+        let mut header_size = 0;
+        let mut directions = None;
+        let mut data_files = None;
+
+        let mut line_start = 0;
+        while data_files.is_none() {
+            if line_start >= bytes.len() {
+                bail!("MetaImage header is missing the 'ElementDataFile' key");
+            }
+            let line_end = bytes[line_start..].iter()
+                .position(|byte| *byte == b'\n')
+                .map_or(bytes.len(), |i| line_start + i);
+            let line = std::str::from_utf8(&bytes[line_start..line_end]).context("MetaImage header is not valid text")?;
+            line_start = line_end + 1;
+
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line.split_once('=')
+                .ok_or_else(|| anyhow!("Malformed MetaImage header line '{}' is not a 'Key = Value' pair", line))?;
+            let value = value.trim();
+
+            match key.trim().to_ascii_lowercase().as_str() {
+                "ndims" => {
+                    if value != "3" {
+                        bail!("Only three dimensional MetaImages are supported, found NDims = {}", value);
+                    }
+                },
+                "dimsize" => {
+                    let sizes = parse_values::<u32, 3>(value, "DimSize")?;
+                    if sizes.contains(&0) {
+                        bail!("MetaImage 'DimSize' must not contain zero sizes");
+                    }
+                    dimensions = Some(UVec3::from_array(sizes));
+                },
+                "elementtype" => scalar_type = Some(parse_element_type(value)?),
+                "elementnumberofchannels" => {
+                    components = value.parse().with_context(|| format!("Could not parse MetaImage channel count '{}'", value))?;
+                },
+                "elementspacing" | "elementsize" => {
+                    spacing = Vec3::from_array(parse_values::<f32, 3>(value, "ElementSpacing")?).abs();
+                },
+                "binarydatabyteordermsb" | "elementbyteordermsb" => {
+                    byte_order = if parse_bool(value) { ByteOrder::BigEndian } else { ByteOrder::LittleEndian };
+                },
+                "compresseddata" => compressed = parse_bool(value),
+                "headersize" => {
+                    header_size = value.parse().with_context(|| format!("Could not parse MetaImage header size '{}'", value))?;
+                },
+                "transformmatrix" | "rotation" | "orientation" => {
+                    let matrix = parse_values::<f32, 9>(value, "TransformMatrix")?;
+                    directions = Some([0, 1, 2].map(|axis| Vec3::from_slice(&matrix[axis * 3..axis * 3 + 3])));
+                },
+                "elementdatafile" => {
+                    let parts: Vec<&str> = value.split_whitespace().collect();
+                    data_files = Some(if value.eq_ignore_ascii_case("local") {
+                        vec![]
+                    } else if parts.first().is_some_and(|part| part.eq_ignore_ascii_case("list")) {
+                        // All remaining lines of the file are file names
+                        let rest = std::str::from_utf8(bytes.get(line_start..).unwrap_or(&[])).context("MetaImage file list is not valid text")?;
+                        rest.lines()
+                            .map(|line| line.trim())
+                            .filter(|line| !line.is_empty())
+                            .map(|line| directory.join(line))
+                            .collect()
+                    } else if parts.len() >= 4 && parts[0].contains('%') {
+                        let [min, max, step] = [parts[1], parts[2], parts[3]]
+                            .map(|v| v.parse::<i64>().with_context(|| format!("Could not parse MetaImage data file range '{}'", value)));
+                        expand_file_pattern(parts[0], min?, max?, step?, directory)?
+                    } else {
+                        vec![directory.join(value)]
+                    });
+                },
+                _ => continue
+            }
+        }
+
+        let header = Self {
+            dimensions: dimensions.ok_or_else(|| anyhow!("MetaImage header is missing the 'DimSize' key"))?,
+            scalar_type: scalar_type.ok_or_else(|| anyhow!("MetaImage header is missing the 'ElementType' key"))?,
+            components,
+            spacing,
+            byte_order,
+            compressed,
+            header_size,
+            directions,
+            data_files: data_files.unwrap_or_default(),
+        };
+        Ok((header, line_start.min(bytes.len())))
+    }
+
+    /// Amount of scalars the data contains
+    pub fn value_count(&self) -> usize {
+        self.dimensions.x as usize * self.dimensions.y as usize * self.dimensions.z as usize * self.components
+    }
+}
+
+/// Reads a MetaImage-File with inline (`.mha`) or detached (`.mhd`) data into a volume
+pub fn read_volume(path: &str, progress: &LoadProgress) -> Result<VolumeData> {
+    let bytes = progress.read_file(path).with_context(|| format!("Could not open MetaImage file '{}'", path))?;
+    let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+    let (header, data_offset) = MetaImageHeader::parse(&bytes, directory)
+        .with_context(|| format!("Could not parse MetaImage header '{}'", path))?;
+
+    if header.components > 4 {
+        bail!("MetaImage '{}' has {} channels per voxel, at most 4 are supported", path, header.components);
+    }
+
+    // Slices of a file list are read one after another, the header size applies to each of them
+    let value_count = header.value_count();
+    let mut values = Vec::with_capacity(value_count);
+    if header.data_files.is_empty() {
+        let local_header = MetaImageHeader { header_size: 0, ..header.clone() };
+        values.extend(read_values(&bytes[data_offset..], &local_header, value_count)?);
+    } else {
+        let values_per_file = value_count / header.data_files.len();
+        for data_file in &header.data_files {
+            let data = progress.read_file(data_file).with_context(|| format!("Could not open MetaImage data file '{}'", data_file.display()))?;
+            values.extend(read_values(&data, &header, values_per_file)
+                .with_context(|| format!("Could not read MetaImage data file '{}'", data_file.display()))?);
+        }
+    }
+    if values.len() < value_count {
+        bail!("MetaImage '{}' contains {} values, but the header describes {}", path, values.len(), value_count);
+    }
+
+    let values = &values[..value_count];
+    let mut volume = volume_from_values(header.dimensions, header.components, values, normalization_range(header.scalar_type, values), header.scalar_type)?;
+    volume.spacing = header.spacing;
+
+    // ITK writes directions in left-posterior-superior space, the renderer uses right-anterior-superior
+    if let Some(directions) = header.directions {
+        volume.reorient(directions.map(|direction| direction * Vec3::new(-1.0, -1.0, 1.0)));
+    }
+    Ok(volume)
+}
+
+/// Decodes the values of a single data file or of the inline data
+fn read_values(data: &[u8], header: &MetaImageHeader, value_count: usize) -> Result<Vec<f32>> {
+    let byte_len = value_count * header.scalar_type.size();
+    let data = if header.header_size < 0 {
+        let start = data.len().checked_sub(byte_len)
+            .ok_or_else(|| anyhow!("Data contains {} bytes, but {} are needed", data.len(), byte_len))?;
+        &data[start..]
+    } else {
+        data.get(header.header_size as usize..).ok_or_else(|| anyhow!("Data ends before the skipped header"))?
+    };
+
+    let decompressed;
+    let data = if header.compressed {
+        let mut bytes = Vec::with_capacity(byte_len);
+        ZlibDecoder::new(data).read_to_end(&mut bytes).context("Could not decompress zlib compressed data")?;
+        decompressed = bytes;
+        &decompressed[..]
+    } else {
+        data
+    };
+
+    if data.len() < byte_len {
+        bail!("Data contains {} bytes, but {} are needed", data.len(), byte_len);
+    }
+    Ok(decode_scalars(&data[..byte_len], header.scalar_type, header.byte_order))
+}
@@ -1,3 +1,93 @@
 /// Loaders for different file formats
+pub mod cvol;
 pub mod dat;
-pub mod netcdf;
\ No newline at end of file
+pub mod dicom;
+pub mod image_stack;
+pub mod metaimage;
+pub mod netcdf;
+pub mod nifti;
+pub mod nrrd;
+pub mod progress;
+pub mod raw;
+pub mod registry;
+pub mod scalar;
+pub mod stream;
+pub mod vtk;
+
+use std::{cmp::Ordering, path::{Path, PathBuf}};
+
+use anyhow::{anyhow, bail, Result};
+
+/// Compares two strings so that embedded numbers are ordered by their value, e.g. `frame2` before `frame10`
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = take_number(&mut a);
+                let y = take_number(&mut b);
+                // Compare by length first, so that numbers of any size are ordered correctly
+                let ordering = x.len().cmp(&y.len()).then_with(|| x.cmp(&y));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.cmp(&y);
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+/// Consumes all consecutive digits and returns them without leading zeros
+fn take_number(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut number = String::new();
+    while let Some(c) = chars.peek().copied().filter(|c| c.is_ascii_digit()) {
+        number.push(c);
+        chars.next();
+    }
+    let trimmed = number.trim_start_matches('0');
+    if trimmed.is_empty() { "0".to_string() } else { trimmed.to_string() }
+}
+
+/// Expands a printf style file name pattern such as `slice%03d.raw` over the indices from `min` to `max`.
+/// The file names are resolved against `directory`.
+pub fn expand_file_pattern(pattern: &str, min: i64, max: i64, step: i64, directory: &Path) -> Result<Vec<PathBuf>> {
+    if step == 0 {
+        bail!("Step of the file pattern '{}' must not be zero", pattern);
+    }
+
+    let mut files = vec![];
+    let mut index = min;
+    while (step > 0 && index <= max) || (step < 0 && index >= max) {
+        files.push(directory.join(format_index(pattern, index)?));
+        index += step;
+    }
+    Ok(files)
+}
+
+/// Replaces the single integer conversion such as `%03d` in a file name pattern
+fn format_index(pattern: &str, index: i64) -> Result<String> {
+    let start = pattern.find('%').ok_or_else(|| anyhow!("File pattern '{}' contains no conversion", pattern))?;
+    let end = pattern[start..].find(['d', 'i', 'u'])
+        .map(|i| start + i)
+        .ok_or_else(|| anyhow!("File pattern '{}' contains no integer conversion", pattern))?;
+    let flags = &pattern[start + 1..end];
+    let width = flags.trim_start_matches('0').parse::<usize>().unwrap_or(0);
+    let number = if flags.starts_with('0') {
+        format!("{:0width$}", index, width = width)
+    } else {
+        format!("{:width$}", index, width = width)
+    };
+    Ok(format!("{}{}{}", &pattern[..start], number, &pattern[end + 1..]))
+}
@@ -1,69 +1,354 @@
-use anyhow::Result;
-use glam::UVec3;
-use wgpu::{Device, Queue};
+use anyhow::{anyhow, bail, Context, Result};
+use glam::{UVec3, Vec3};
+use wgpu::Queue;
 
-use crate::voxel::grid::VoxelGrid;
+use crate::{transfer_function::{ColorPoint, OpacityPoint, TransferFunction}, voxel::{grid::VoxelGrid, volume::VolumeData}};
 
-/// Exports a Voxel Grid into a NetCDF-File
-pub fn write_voxel_grid(path: &str, grid: &VoxelGrid) -> Result<()> {
+use super::{progress::LoadProgress, scalar::{data_range, volume_from_values, ScalarType}};
 
-    let mut file = netcdf::create(path)?;
+/// Settings for exporting a Voxel Grid into a NetCDF-File
+#[derive(Debug, Clone, Copy)]
+pub struct NetcdfExportOptions {
+    /// Deflate level between 1 and 9, 0 disables compression
+    pub deflate_level: u32,
+    /// Edge length of the cubic chunks the volume is stored in, 0 stores the volume contiguously
+    pub chunk_size: usize,
+}
+
+impl Default for NetcdfExportOptions {
+    fn default() -> Self {
+        Self {
+            deflate_level: 4,
+            chunk_size: 64,
+        }
+    }
+}
+
+/// Exports a Voxel Grid into a NetCDF-File.
+/// The attenuation, the transfer function, the render box and the source of the volume are stored as global attributes.
+pub fn write_voxel_grid(path: &str, grid: &VoxelGrid, options: &NetcdfExportOptions) -> Result<()> {
+    let mut file = netcdf::create(path).with_context(|| format!("Could not create NetCDF file '{}'", path))?;
+    let (x, y, z) = (grid.dimensions.x as usize, grid.dimensions.y as usize, grid.dimensions.z as usize);
     file.add_dimension("c", 4)?;
-    file.add_dimension("z", grid.dimensions.z as usize)?;
-    file.add_dimension("y", grid.dimensions.y as usize)?;
-    file.add_dimension("x", grid.dimensions.x as usize)?;
+    file.add_dimension("z", z)?;
+    file.add_dimension("y", y)?;
+    file.add_dimension("x", x)?;
+
+    let (box_min, box_size) = grid.bounding_box();
+    let transfer_function = &grid.transfer_function;
+    file.add_attribute("spacing", grid.spacing.to_array().to_vec())?;
+    file.add_attribute("box_min", box_min.to_array().to_vec())?;
+    file.add_attribute("box_size", box_size.to_array().to_vec())?;
+    file.add_attribute("attenuation", grid.attenuation)?;
+    // Control points are flattened into (value, r, g, b) and (value, opacity)
+    file.add_attribute("transfer_function_colors", transfer_function.colors.iter().flat_map(|point| [point.value, point.color[0], point.color[1], point.color[2]]).collect::<Vec<f32>>())?;
+    file.add_attribute("transfer_function_opacities", transfer_function.opacities.iter().flat_map(|point| [point.value, point.opacity]).collect::<Vec<f32>>())?;
+    file.add_attribute("use_transfer_function", grid.transfer_function_settings.use_transfer_function_active() as i32)?;
+    if let Some(source_path) = &grid.source_path {
+        file.add_attribute("source_path", source_path.as_str())?;
+    }
 
     let mut color = file.add_variable::<f32>("color", &["c", "z", "y", "x"])?;
+    if options.chunk_size > 0 {
+        color.set_chunking(&[1, options.chunk_size.min(z), options.chunk_size.min(y), options.chunk_size.min(x)])?;
+    }
+    if options.deflate_level > 0 {
+        color.set_compression(options.deflate_level.min(9) as i32, true)?;
+    }
 
-    for x in 0..grid.dimensions.x as usize {
-        for y in 0..grid.dimensions.y as usize {
-            for z in 0..grid.dimensions.z as usize {
-                let voxel = grid[UVec3::new(x as u32,y as u32,z as u32)];
-                for channel in 0..4  as usize{
-                    let val = voxel.color[channel as usize] as f32 / 255.0;
-                    let extents: netcdf::Extents = [channel..channel+1, z..z+1,y..y+1,x..x+1].try_into().unwrap();
-                    color.put_value::<f32, _>(val, extents)?;
-                }
-            }
+    // Voxels are stored with x changing fastest, so every channel is a contiguous (z, y, x) block
+    let voxel_count = x * y * z;
+    let mut values = vec![0f32; 4 * voxel_count];
+    grid.voxels()?;
+    for i in 0..voxel_count {
+        for (channel, value) in grid.voxel_color(i).into_iter().enumerate() {
+            values[channel * voxel_count + i] = value;
         }
     }
+    color.put_values(&values, ..)?;
     Ok(())
 }
 
-/// Loads a NetCDF-File into a Voxel Grid
-pub fn open_voxel_grid(path: &str, grid: &mut VoxelGrid, device: &Device, queue: &Queue) -> Result<()> {
-    let file = netcdf::open(path)?;
+/// Names of dimensions that hold the four color channels of preshaded volumes
+const CHANNEL_DIMENSION_NAMES: &[&str] = &["c", "channel", "channels", "rgba"];
 
-    let var = &file.variable("color").expect("Could not find 'color' variable");
+/// Name and length of a NetCDF dimension
+#[derive(Debug, Clone)]
+pub struct DimensionInfo {
+    pub name: String,
+    pub len: usize,
+}
 
-    let x = &file.dimension("x").unwrap().len();
-    let y = &file.dimension("y").unwrap().len();
-    let z = &file.dimension("z").unwrap().len();
-    
-    println!("{} {} {}", x, y, z);
+/// Name and dimensions of a NetCDF variable
+#[derive(Debug, Clone)]
+pub struct VariableInfo {
+    pub name: String,
+    pub dimensions: Vec<DimensionInfo>,
+}
+
+/// Roles of a variable's dimensions when it is loaded as a volume
+#[derive(Debug, Clone, Copy)]
+pub struct VariableLayout {
+    /// Indices of the z, y and x dimensions
+    pub spatial: [usize; 3],
+    /// Index of the dimension holding four color channels
+    pub channel: Option<usize>,
+}
+
+/// Variable and slice of a NetCDF file that is loaded into a Voxel Grid
+#[derive(Debug, Clone)]
+pub struct VariableSelection {
+    pub variable: String,
+    /// Index for every dimension of the variable. Only used for dimensions that are neither spatial nor color channels.
+    pub slice_indices: Vec<usize>,
+    /// Values that are mapped to zero and full density, detected from the data if not given
+    pub value_range: Option<(f32, f32)>,
+}
 
-    let data= var.get::<f32, _>((..,..,..,..))?;
+impl VariableSelection {
+    /// Selects the `color` variable with values between 0 and 1 that is written by this renderer and DiffDVR
+    pub fn color() -> Self {
+        Self {
+            variable: "color".to_string(),
+            slice_indices: vec![],
+            value_range: Some((0.0, 1.0)),
+        }
+    }
+}
+
+impl VariableInfo {
+    fn from_variable(var: &netcdf::Variable) -> Self {
+        Self {
+            name: var.name(),
+            dimensions: var.dimensions().iter().map(|dim| DimensionInfo { name: dim.name(), len: dim.len() }).collect(),
+        }
+    }
+
+    /// The last three dimensions that don't hold color channels are used as z, y and x.
+    /// Returns `None` if the variable has less than three of those dimensions.
+    pub fn layout(&self) -> Option<VariableLayout> {
+        let channel = self.dimensions.iter()
+            .position(|dim| dim.len == 4 && CHANNEL_DIMENSION_NAMES.contains(&dim.name.to_ascii_lowercase().as_str()));
+        let remaining: Vec<usize> = (0..self.dimensions.len()).filter(|i| Some(*i) != channel).collect();
+        if remaining.len() < 3 {
+            return None;
+        }
+        let spatial = [remaining[remaining.len() - 3], remaining[remaining.len() - 2], remaining[remaining.len() - 1]];
+        Some(VariableLayout { spatial, channel })
+    }
+
+    /// Dimensions that have to be sliced at a single index, e.g. time or ensemble members
+    pub fn extra_dimensions(&self) -> Vec<usize> {
+        match self.layout() {
+            Some(layout) => (0..self.dimensions.len())
+                .filter(|i| !layout.spatial.contains(i) && layout.channel != Some(*i))
+                .collect(),
+            None => vec![]
+        }
+    }
+}
+
+/// Lists all variables of a NetCDF-File that can be loaded as a volume
+pub fn list_volume_variables(path: &str) -> Result<Vec<VariableInfo>> {
+    let file = netcdf::open(path).with_context(|| format!("Could not open NetCDF file '{}'", path))?;
+    Ok(file.variables()
+        .map(|var| VariableInfo::from_variable(&var))
+        .filter(|info| info.layout().is_some())
+        .collect())
+}
+
+/// Restores the render settings that were stored by [`write_voxel_grid`] onto a Voxel Grid loaded from the file
+pub fn apply_render_settings(path: &str, grid: &mut VoxelGrid, queue: &Queue) -> Result<()> {
+    let file = netcdf::open(path).with_context(|| format!("Could not open NetCDF file '{}'", path))?;
+    if let Some([attenuation]) = float_attribute(&file, "attenuation").as_deref() {
+        grid.attenuation = *attenuation;
+        grid.update_voxel_grid_buffer(queue);
+    }
+    if let Some(transfer_function) = read_transfer_function(&file) {
+        grid.transfer_function = transfer_function;
+    }
+    if let Some([active]) = float_attribute(&file, "use_transfer_function").as_deref() {
+        grid.transfer_function_settings.set_transfer_function_active(*active != 0.0);
+    }
+    grid.update_transfer_function_buffer(queue);
+    Ok(())
+}
 
-    *grid = VoxelGrid::new(UVec3::new(*x as u32,*y as u32,*z as u32), device, queue);
+/// Reads a scalar or four channel variable of a NetCDF-File into a volume.
+/// Scalar values are used for all four channels, values are mapped from the value range to 0..1.
+pub fn read_variable(path: &str, selection: &VariableSelection, progress: &LoadProgress) -> Result<VolumeData> {
+    read_variable_with_range(path, selection, progress).map(|(volume, _)| volume)
+}
 
-    for x in 0..grid.dimensions.x {
-        for y in 0..grid.dimensions.y {
-            for z in 0..grid.dimensions.z {
+/// Reads the `color` variable of a NetCDF-File, or the first variable that can be loaded as a volume if there is none,
+/// e.g. the `density` of scalar files. Its value range is detected from the data and its extra dimensions are read at their first index.
+pub fn read_default_variable(path: &str, progress: &LoadProgress) -> Result<VolumeData> {
+    let variables = list_volume_variables(path)?;
+    if variables.iter().any(|info| info.name == "color") {
+        return read_variable(path, &VariableSelection::color(), progress);
+    }
 
+    let info = variables.first()
+        .ok_or_else(|| anyhow!("NetCDF file '{}' does not contain a variable with three spatial dimensions", path))?;
+    if variables.len() > 1 || !info.extra_dimensions().is_empty() {
+        progress.notice(format!(
+            "'{}' has no 'color' variable, so '{}' was loaded, at the first index of dimensions like time. Other variables and indices can be picked with \"Open NetCDF Variable\".",
+            path, info.name
+        ));
+    }
+    let selection = VariableSelection { variable: info.name.clone(), slice_indices: vec![], value_range: None };
+    read_variable(path, &selection, progress)
+}
 
-                let data_0 = (data[[0,z as usize,y as usize,x as usize]] * 255.0) as u8;
-                let data_1 = (data[[1,z as usize,y as usize,x as usize]] * 255.0) as u8;
-                let data_2 = (data[[2,z as usize,y as usize,x as usize]] * 255.0) as u8;
-                let data_3 = (data[[3,z as usize,y as usize,x as usize]] * 255.0) as u8;
+/// Same as [`read_variable`], but also returns the value range that was used for mapping the values
+pub fn read_variable_with_range(path: &str, selection: &VariableSelection, progress: &LoadProgress) -> Result<(VolumeData, (f32, f32))> {
+    let file = netcdf::open(path).with_context(|| format!("Could not open NetCDF file '{}'", path))?;
+    let var = file.variable(&selection.variable)
+        .ok_or_else(|| anyhow!("Could not find variable '{}' in '{}'", selection.variable, path))?;
+    let info = VariableInfo::from_variable(&var);
+    let layout = info.layout()
+        .ok_or_else(|| anyhow!("Variable '{}' needs at least three spatial dimensions", info.name))?;
 
-                grid.set_color(UVec3::new(x,y,z), [data_0, data_1, data_2, data_3])
+    // Read the full spatial and channel dimensions, but only a single index of all other dimensions
+    let mut start = Vec::with_capacity(info.dimensions.len());
+    let mut count = Vec::with_capacity(info.dimensions.len());
+    for (i, dim) in info.dimensions.iter().enumerate() {
+        if layout.spatial.contains(&i) || layout.channel == Some(i) {
+            start.push(0);
+            count.push(dim.len);
+        } else {
+            let index = selection.slice_indices.get(i).copied().unwrap_or(0);
+            if index >= dim.len {
+                bail!("Index {} is out of range for dimension '{}' with length {}", index, dim.name, dim.len);
             }
+            start.push(index);
+            count.push(1);
         }
     }
 
+    // The variable is read one z slice at a time, so that progress can be shown and the load can be cancelled
+    let [z_dim, y_dim, x_dim] = layout.spatial;
+    let slice_count = count[z_dim];
+    let mut slice_start = start.clone();
+    let mut slice_counts = count.clone();
+    slice_counts[z_dim] = 1;
+    let mut data = Vec::new();
+    progress.start_slices(slice_count);
+    for z in 0..slice_count {
+        slice_start[z_dim] = z;
+        let extents: netcdf::Extents = (slice_start.clone(), slice_counts.clone()).try_into()?;
+        data.extend(var.get_values::<f32, _>(extents)?);
+        progress.finish_slice()?;
+    }
+    apply_packing(&var, &mut data);
+
+    let value_range = match selection.value_range {
+        Some(range) => range,
+        None => data_range(&data),
+    };
+
+    // Row-major strides within a slice, the slices follow each other
+    let mut strides = vec![1usize; slice_counts.len()];
+    for i in (0..slice_counts.len().saturating_sub(1)).rev() {
+        strides[i] = strides[i + 1] * slice_counts[i + 1];
+    }
+    strides[z_dim] = slice_counts.iter().product();
 
+    let dimensions = UVec3::new(count[x_dim] as u32, count[y_dim] as u32, count[z_dim] as u32);
+    println!("Loading '{}' with dimensions {}", info.name, dimensions);
 
-    grid.update_buffer(&queue);
+    // Reorders the values so that x changes fastest and the channels of each voxel are interleaved
+    let components = if layout.channel.is_some() { 4 } else { 1 };
+    let mut values = Vec::with_capacity(dimensions.x as usize * dimensions.y as usize * dimensions.z as usize * components);
+    progress.start_slices(dimensions.z as usize);
+    for z in 0..dimensions.z as usize {
+        for y in 0..dimensions.y as usize {
+            for x in 0..dimensions.x as usize {
+                let index = z * strides[z_dim] + y * strides[y_dim] + x * strides[x_dim];
+                match layout.channel {
+                    Some(c) => values.extend((0..4).map(|channel| data[index + channel * strides[c]])),
+                    None => values.push(data[index]),
+                }
+            }
+        }
+        progress.finish_slice()?;
+    }
+    // NetCDF variables are read as floats, which are kept at full precision
+    let mut volume = volume_from_values(dimensions, components, &values, value_range, ScalarType::F32)?;
+
+    let axis_names = [&info.dimensions[x_dim].name, &info.dimensions[y_dim].name, &info.dimensions[z_dim].name];
+    volume.spacing = read_spacing(&file, axis_names);
     println!("Finished loading NetCDF Model");
-    Ok(())
-}
\ No newline at end of file
+    Ok((volume, value_range))
+}
+
+/// Applies the CF `scale_factor` and `add_offset` attributes and replaces fill values with NaN
+fn apply_packing(var: &netcdf::Variable, data: &mut [f32]) {
+    let attribute = |name: &str| -> Option<f64> {
+        var.attribute_value(name).and_then(|value| value.ok()).and_then(|value| f64::try_from(value).ok())
+    };
+    let fill_values: Vec<f32> = ["_FillValue", "missing_value"].iter()
+        .filter_map(|name| attribute(name))
+        .map(|value| value as f32)
+        .collect();
+    let scale_factor = attribute("scale_factor").unwrap_or(1.0) as f32;
+    let add_offset = attribute("add_offset").unwrap_or(0.0) as f32;
+
+    for value in data.iter_mut() {
+        if fill_values.contains(value) {
+            *value = f32::NAN;
+        } else {
+            *value = *value * scale_factor + add_offset;
+        }
+    }
+}
+
+/// Reads the voxel spacing from a global `spacing` attribute (x, y, z).
+/// Falls back to the distance between the first two values of the coordinate variables of the x, y and z axes
+/// and to a spacing of 1 for every axis where neither is available.
+fn read_spacing(file: &netcdf::File, axis_names: [&String; 3]) -> Vec3 {
+    if let Some([x, y, z]) = float_attribute(file, "spacing").as_deref() {
+        if *x > 0.0 && *y > 0.0 && *z > 0.0 {
+            return Vec3::new(*x, *y, *z);
+        }
+    }
+
+    let axis_spacing = |name: &str| -> f32 {
+        file.variable(name)
+            .filter(|var| var.dimensions().len() == 1 && var.len() > 1)
+            .and_then(|var| var.get_values::<f64, _>(0..2).ok())
+            .map(|values| (values[1] - values[0]).abs() as f32)
+            .filter(|spacing| spacing.is_finite() && *spacing > 0.0)
+            .unwrap_or(1.0)
+    };
+    Vec3::new(axis_spacing(axis_names[0]), axis_spacing(axis_names[1]), axis_spacing(axis_names[2]))
+}
+
+/// Reads the control points of a transfer function, files of older versions store three colors that are spread over the value range
+fn read_transfer_function(file: &netcdf::File) -> Option<TransferFunction> {
+    if let (Some(colors), Some(opacities)) = (float_attribute(file, "transfer_function_colors"), float_attribute(file, "transfer_function_opacities")) {
+        let mut transfer_function = TransferFunction {
+            colors: colors.chunks_exact(4).map(|point| ColorPoint { value: point[0], color: [point[1], point[2], point[3]] }).collect(),
+            opacities: opacities.chunks_exact(2).map(|point| OpacityPoint { value: point[0], opacity: point[1] }).collect(),
+        };
+        transfer_function.sort();
+        return Some(transfer_function);
+    }
+
+    let colors = ["transfer_function_color_a", "transfer_function_color_b", "transfer_function_color_c"].iter()
+        .map(|name| float_attribute(file, name).filter(|color| color.len() >= 3).map(|color| [color[0], color[1], color[2]]))
+        .collect::<Option<Vec<_>>>()?;
+    Some(TransferFunction::from_colors(colors))
+}
+
+/// Reads a numeric global attribute as a list of floats
+fn float_attribute(file: &netcdf::File, name: &str) -> Option<Vec<f32>> {
+    let value = file.attribute(name)?.value().ok()?;
+    let values = match value {
+        netcdf::AttributeValue::Floats(values) => values,
+        netcdf::AttributeValue::Doubles(values) => values.into_iter().map(|v| v as f32).collect(),
+        netcdf::AttributeValue::Ints(values) => values.into_iter().map(|v| v as f32).collect(),
+        value => vec![f64::try_from(value).ok()? as f32],
+    };
+    Some(values)
+}
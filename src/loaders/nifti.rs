@@ -0,0 +1,419 @@
+use std::{io::Read, path::{Path, PathBuf}};
+
+use anyhow::{anyhow, bail, Context, Result};
+use flate2::read::MultiGzDecoder;
+use glam::{Mat3, Quat, UVec3, Vec3};
+
+use crate::voxel::volume::VolumeData;
+
+use super::{progress::LoadProgress, scalar::{data_range, decode_scalars, normalization_range, volume_from_values, ByteOrder, ScalarType}};
+
+/// Size of a NIfTI-1 header in bytes
+const NIFTI1_HEADER_SIZE: i32 = 348;
+/// Size of a NIfTI-2 header in bytes
+const NIFTI2_HEADER_SIZE: i32 = 540;
+
+/// Parsed contents of a NIfTI-1 or NIfTI-2 header
+#[derive(Debug, Clone)]
+pub struct NiftiHeader {
+    pub version: u8,
+    pub byte_order: ByteOrder,
+    /// Number of used dimensions followed by their sizes
+    pub dim: [i64; 8],
+    pub scalar_type: ScalarType,
+    pub components: usize,
+    /// qfac followed by the size of a voxel along each dimension
+    pub pixdim: [f64; 8],
+    pub vox_offset: u64,
+    pub scl_slope: f64,
+    pub scl_inter: f64,
+    pub qform_code: i32,
+    pub sform_code: i32,
+    pub quatern: [f64; 3],
+    pub srow: [[f64; 4]; 3],
+    /// Whether the data follows the header in the same file (`n+1`/`n+2`) instead of a separate `.img` file
+    pub single_file: bool,
+}
+
+/// Reads little or big endian values at byte offsets of a header
+struct HeaderReader<'a> {
+    bytes: &'a [u8],
+    byte_order: ByteOrder,
+}
+
+impl HeaderReader<'_> {
+    fn read<const N: usize>(&self, offset: usize) -> [u8; N] {
+        let mut value: [u8; N] = self.bytes[offset..offset + N].try_into().expect("Header is large enough");
+        if self.byte_order == ByteOrder::BigEndian {
+            value.reverse();
+        }
+        value
+    }
+
+    fn i16(&self, offset: usize) -> i16 {
+        i16::from_le_bytes(self.read(offset))
+    }
+
+    fn i32(&self, offset: usize) -> i32 {
+        i32::from_le_bytes(self.read(offset))
+    }
+
+    fn i64(&self, offset: usize) -> i64 {
+        i64::from_le_bytes(self.read(offset))
+    }
+
+    /// Reads a single precision float, widened so that both header versions share their fields
+    fn f32(&self, offset: usize) -> f64 {
+        f32::from_le_bytes(self.read(offset)) as f64
+    }
+
+    fn f64(&self, offset: usize) -> f64 {
+        f64::from_le_bytes(self.read(offset))
+    }
+}
+
+/// Maps the NIfTI `datatype` code to the scalar type and the amount of components per voxel
+fn parse_datatype(datatype: i16) -> Result<(ScalarType, usize)> {
+    let datatype = match datatype {
+        2 => (ScalarType::U8, 1),
+        4 => (ScalarType::I16, 1),
+        8 => (ScalarType::I32, 1),
+        16 => (ScalarType::F32, 1),
+        64 => (ScalarType::F64, 1),
+        128 => (ScalarType::U8, 3),
+        256 => (ScalarType::I8, 1),
+        512 => (ScalarType::U16, 1),
+        768 => (ScalarType::U32, 1),
+        1024 => (ScalarType::I64, 1),
+        1280 => (ScalarType::U64, 1),
+        2304 => (ScalarType::U8, 4),
+        32 | 1536 | 1792 | 2048 => bail!("Complex NIfTI datatype {} is not supported", datatype),
+        _ => bail!("Unknown NIfTI datatype {}", datatype),
+    };
+    Ok(datatype)
+}
+
+impl NiftiHeader {
+    /// Parses a NIfTI-1 or NIfTI-2 header, detecting the version and byte order from `sizeof_hdr`
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 4 {
+            bail!("File is too small to contain a NIfTI header");
+        }
+        let sizeof_hdr = i32::from_le_bytes(bytes[0..4].try_into()?);
+        let (size, byte_order) = match (sizeof_hdr, sizeof_hdr.swap_bytes()) {
+            (NIFTI1_HEADER_SIZE | NIFTI2_HEADER_SIZE, _) => (sizeof_hdr, ByteOrder::LittleEndian),
+            (_, NIFTI1_HEADER_SIZE | NIFTI2_HEADER_SIZE) => (sizeof_hdr.swap_bytes(), ByteOrder::BigEndian),
+            _ => bail!("File is not a NIfTI file, header size is {}", sizeof_hdr),
+        };
+        if bytes.len() < size as usize {
+            bail!("NIfTI header is truncated, expected {} bytes but found {}", size, bytes.len());
+        }
+
+        let reader = HeaderReader { bytes, byte_order };
+        let header = if size == NIFTI1_HEADER_SIZE {
+            let (scalar_type, components) = parse_datatype(reader.i16(70))?;
+            Self {
+                version: 1,
+                byte_order,
+                dim: std::array::from_fn(|i| reader.i16(40 + 2 * i) as i64),
+                scalar_type,
+                components,
+                pixdim: std::array::from_fn(|i| reader.f32(76 + 4 * i)),
+                vox_offset: reader.f32(108).max(0.0) as u64,
+                scl_slope: reader.f32(112),
+                scl_inter: reader.f32(116),
+                qform_code: reader.i16(252) as i32,
+                sform_code: reader.i16(254) as i32,
+                quatern: std::array::from_fn(|i| reader.f32(256 + 4 * i)),
+                srow: std::array::from_fn(|row| std::array::from_fn(|i| reader.f32(280 + 16 * row + 4 * i))),
+                single_file: &bytes[344..347] == b"n+1",
+            }
+        } else {
+            let (scalar_type, components) = parse_datatype(reader.i16(12))?;
+            Self {
+                version: 2,
+                byte_order,
+                dim: std::array::from_fn(|i| reader.i64(16 + 8 * i)),
+                scalar_type,
+                components,
+                pixdim: std::array::from_fn(|i| reader.f64(104 + 8 * i)),
+                vox_offset: reader.i64(168).max(0) as u64,
+                scl_slope: reader.f64(176),
+                scl_inter: reader.f64(184),
+                qform_code: reader.i32(344),
+                sform_code: reader.i32(348),
+                quatern: std::array::from_fn(|i| reader.f64(352 + 8 * i)),
+                srow: std::array::from_fn(|row| std::array::from_fn(|i| reader.f64(400 + 32 * row + 8 * i))),
+                single_file: &bytes[4..7] == b"n+2",
+            }
+        };
+
+        if header.dim[0] < 3 || header.dim[1..4].iter().any(|size| *size < 1) {
+            bail!("NIfTI file needs at least three spatial dimensions, found dim = {:?}", header.dim);
+        }
+        Ok(header)
+    }
+
+    pub fn dimensions(&self) -> UVec3 {
+        UVec3::new(self.dim[1] as u32, self.dim[2] as u32, self.dim[3] as u32)
+    }
+
+    /// Amount of volumes stored along the time and further dimensions
+    pub fn volume_count(&self) -> usize {
+        let used = (self.dim[0] as usize).min(7);
+        self.dim[4..=used].iter().map(|size| (*size).max(1) as usize).product()
+    }
+
+    /// Whether `scl_slope` and `scl_inter` change the stored values
+    pub fn is_scaled(&self) -> bool {
+        self.scl_slope.is_finite() && self.scl_slope != 0.0 && (self.scl_slope != 1.0 || self.scl_inter != 0.0)
+    }
+
+    /// Returns the world space direction of each voxel axis, scaled by the voxel size.
+    /// The sform is preferred over the qform, without either there is no orientation.
+    pub fn axes(&self) -> Option<[Vec3; 3]> {
+        if self.sform_code > 0 {
+            let column = |i: usize| Vec3::new(self.srow[0][i] as f32, self.srow[1][i] as f32, self.srow[2][i] as f32);
+            return Some([column(0), column(1), column(2)]);
+        }
+        if self.qform_code > 0 {
+            let [b, c, d] = self.quatern.map(|v| v as f32);
+            let a = (1.0 - (b * b + c * c + d * d)).max(0.0).sqrt();
+            let rotation = Mat3::from_quat(Quat::from_xyzw(b, c, d, a));
+            let qfac = if self.pixdim[0] < 0.0 { -1.0 } else { 1.0 };
+            let spacing = self.pixdim_spacing();
+            return Some([
+                rotation.x_axis * spacing.x,
+                rotation.y_axis * spacing.y,
+                rotation.z_axis * spacing.z * qfac,
+            ]);
+        }
+        None
+    }
+
+    /// Voxel size from `pixdim`, invalid sizes fall back to 1
+    fn pixdim_spacing(&self) -> Vec3 {
+        Vec3::from_array(std::array::from_fn(|i| {
+            let size = self.pixdim[i + 1].abs() as f32;
+            if size.is_finite() && size > 0.0 { size } else { 1.0 }
+        }))
+    }
+
+    /// Size of a voxel along each axis, taken from the sform if present and from `pixdim` otherwise
+    pub fn spacing(&self) -> Vec3 {
+        match self.axes() {
+            Some(axes) if self.sform_code > 0 && axes.iter().all(|axis| axis.length() > 0.0) => {
+                Vec3::new(axes[0].length(), axes[1].length(), axes[2].length())
+            },
+            _ => self.pixdim_spacing(),
+        }
+    }
+}
+
+/// Reads a file, decompressing it if it is gzip compressed
+fn read_file(path: &Path, progress: &LoadProgress) -> Result<Vec<u8>> {
+    let bytes = progress.read_file(path).with_context(|| format!("Could not open NIfTI file '{}'", path.display()))?;
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+        let mut decompressed = vec![];
+        MultiGzDecoder::new(&bytes[..]).read_to_end(&mut decompressed)
+            .with_context(|| format!("Could not decompress NIfTI file '{}'", path.display()))?;
+        return Ok(decompressed);
+    }
+    Ok(bytes)
+}
+
+/// Returns the `.img` file belonging to a `.hdr` file of a header and image pair
+fn image_file_path(path: &Path) -> Result<PathBuf> {
+    let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+    let lower = file_name.to_ascii_lowercase();
+    let stem_len = lower.find(".hdr").ok_or_else(|| anyhow!("NIfTI header '{}' has no .hdr extension", path.display()))?;
+    let stem = &file_name[..stem_len];
+    [".img", ".img.gz", ".IMG"].iter()
+        .map(|extension| path.with_file_name(format!("{}{}", stem, extension)))
+        .find(|image| image.exists())
+        .ok_or_else(|| anyhow!("Could not find the image file belonging to NIfTI header '{}'", path.display()))
+}
+
+/// Reads a NIfTI-1 or NIfTI-2 file into a volume.
+/// Values are scaled by `scl_slope` and `scl_inter` and the volume is reoriented into right-anterior-superior space.
+/// Files with several volumes, e.g. along time, only load the first one.
+pub fn read_volume(path: &str, progress: &LoadProgress) -> Result<VolumeData> {
+    let bytes = read_file(Path::new(path), progress)?;
+    let header = NiftiHeader::parse(&bytes).with_context(|| format!("Could not parse NIfTI header '{}'", path))?;
+
+    let image_bytes;
+    let data = if header.single_file {
+        bytes.get(header.vox_offset as usize..).ok_or_else(|| anyhow!("NIfTI data offset {} lies behind the end of the file", header.vox_offset))?
+    } else {
+        let image_path = image_file_path(Path::new(path))?;
+        image_bytes = read_file(&image_path, progress)?;
+        image_bytes.get(header.vox_offset as usize..).ok_or_else(|| anyhow!("NIfTI data offset {} lies behind the end of '{}'", header.vox_offset, image_path.display()))?
+    };
+
+    if header.volume_count() > 1 {
+        progress.notice(format!("The NIfTI-{} file '{}' contains {} volumes, only the first one is loaded", header.version, path, header.volume_count()));
+    }
+
+    let dimensions = header.dimensions();
+    let value_count = dimensions.x as usize * dimensions.y as usize * dimensions.z as usize * header.components;
+    let byte_len = value_count * header.scalar_type.size();
+    if data.len() < byte_len {
+        bail!("NIfTI file '{}' contains {} bytes of data, but the header describes {}", path, data.len(), byte_len);
+    }
+
+    let mut values = decode_scalars(&data[..byte_len], header.scalar_type, header.byte_order);
+    let value_range = if header.is_scaled() && header.components == 1 {
+        let (slope, inter) = (header.scl_slope as f32, header.scl_inter as f32);
+        values.iter_mut().for_each(|value| *value = *value * slope + inter);
+        data_range(&values)
+    } else {
+        normalization_range(header.scalar_type, &values)
+    };
+    // Scaled values are fractional even if they are stored as integers
+    let scalar_type = if header.is_scaled() && header.components == 1 { ScalarType::F32 } else { header.scalar_type };
+    let mut volume = volume_from_values(dimensions, header.components, &values, value_range, scalar_type)?;
+    volume.spacing = header.spacing();
+    if let Some(axes) = header.axes() {
+        volume.reorient(axes);
+    }
+    Ok(volume)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes the fields of a header into a buffer in either byte order
+    struct HeaderWriter {
+        bytes: Vec<u8>,
+        big_endian: bool,
+    }
+
+    impl HeaderWriter {
+        fn new(size: usize, big_endian: bool) -> Self {
+            Self { bytes: vec![0; size], big_endian }
+        }
+
+        fn write<const N: usize>(&mut self, offset: usize, mut value: [u8; N]) {
+            if self.big_endian {
+                value.reverse();
+            }
+            self.bytes[offset..offset + N].copy_from_slice(&value);
+        }
+
+        fn i16(&mut self, offset: usize, value: i16) {
+            self.write(offset, value.to_le_bytes());
+        }
+
+        fn i32(&mut self, offset: usize, value: i32) {
+            self.write(offset, value.to_le_bytes());
+        }
+
+        fn i64(&mut self, offset: usize, value: i64) {
+            self.write(offset, value.to_le_bytes());
+        }
+
+        fn f32(&mut self, offset: usize, value: f32) {
+            self.write(offset, value.to_le_bytes());
+        }
+
+        fn f64(&mut self, offset: usize, value: f64) {
+            self.write(offset, value.to_le_bytes());
+        }
+    }
+
+    /// 4 x 5 x 6 unsigned 16-bit volume with voxels of 2 x 3 x 4
+    fn nifti1(big_endian: bool) -> HeaderWriter {
+        let mut header = HeaderWriter::new(352, big_endian);
+        header.i32(0, NIFTI1_HEADER_SIZE);
+        for (i, size) in [3, 4, 5, 6].into_iter().enumerate() {
+            header.i16(40 + 2 * i, size);
+        }
+        header.i16(70, 512);
+        for (i, size) in [1.0, 2.0, 3.0, 4.0].into_iter().enumerate() {
+            header.f32(76 + 4 * i, size);
+        }
+        header.f32(108, 352.0);
+        header.bytes[344..348].copy_from_slice(b"n+1\0");
+        header
+    }
+
+    fn nifti2() -> HeaderWriter {
+        let mut header = HeaderWriter::new(544, false);
+        header.i32(0, NIFTI2_HEADER_SIZE);
+        header.bytes[4..12].copy_from_slice(b"n+2\0\r\n\x1a\n");
+        header.i16(12, 16);
+        for (i, size) in [4, 7, 8, 9, 2].into_iter().enumerate() {
+            header.i64(16 + 8 * i, size);
+        }
+        for (i, size) in [-1.0, 0.5, 0.5, 1.5].into_iter().enumerate() {
+            header.f64(104 + 8 * i, size);
+        }
+        header.i64(168, 544);
+        header.f64(176, 2.0);
+        header.f64(184, 1.0);
+        header
+    }
+
+    #[test]
+    fn parses_nifti1_in_both_byte_orders() {
+        for big_endian in [false, true] {
+            let header = NiftiHeader::parse(&nifti1(big_endian).bytes).unwrap();
+            assert_eq!(header.version, 1);
+            assert_eq!(header.byte_order, if big_endian { ByteOrder::BigEndian } else { ByteOrder::LittleEndian });
+            assert_eq!(header.dimensions(), UVec3::new(4, 5, 6));
+            assert_eq!(header.scalar_type, ScalarType::U16);
+            assert_eq!(header.spacing(), Vec3::new(2.0, 3.0, 4.0));
+            assert_eq!(header.vox_offset, 352);
+            assert!(header.single_file);
+            assert!(header.axes().is_none());
+        }
+    }
+
+    #[test]
+    fn parses_nifti2() {
+        let header = NiftiHeader::parse(&nifti2().bytes).unwrap();
+        assert_eq!(header.version, 2);
+        assert_eq!(header.dimensions(), UVec3::new(7, 8, 9));
+        assert_eq!(header.volume_count(), 2);
+        assert_eq!(header.scalar_type, ScalarType::F32);
+        assert_eq!(header.spacing(), Vec3::new(0.5, 0.5, 1.5));
+        assert_eq!(header.vox_offset, 544);
+        assert!(header.is_scaled());
+        assert!(header.single_file);
+    }
+
+    #[test]
+    fn orients_by_qform() {
+        // Rotation by 90° around z with a negative qfac, which flips the z axis
+        let mut writer = nifti1(false);
+        writer.i16(252, 1);
+        writer.f32(264, std::f32::consts::FRAC_1_SQRT_2);
+        writer.f32(76, -1.0);
+        let axes = NiftiHeader::parse(&writer.bytes).unwrap().axes().unwrap();
+        assert!(axes[0].abs_diff_eq(Vec3::new(0.0, 2.0, 0.0), 1e-5));
+        assert!(axes[1].abs_diff_eq(Vec3::new(-3.0, 0.0, 0.0), 1e-5));
+        assert!(axes[2].abs_diff_eq(Vec3::new(0.0, 0.0, -4.0), 1e-5));
+
+        // The sform takes precedence
+        writer.i16(254, 1);
+        for (row, values) in [[0.0, 0.0, 5.0, 0.0], [1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0]].into_iter().enumerate() {
+            for (i, value) in values.into_iter().enumerate() {
+                writer.f32(280 + 16 * row + 4 * i, value);
+            }
+        }
+        let header = NiftiHeader::parse(&writer.bytes).unwrap();
+        assert_eq!(header.axes().unwrap(), [Vec3::Y, Vec3::Z, Vec3::new(5.0, 0.0, 0.0)]);
+        assert_eq!(header.spacing(), Vec3::new(1.0, 1.0, 5.0));
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        assert!(NiftiHeader::parse(&[0; 3]).is_err());
+        assert!(NiftiHeader::parse(&[0; 352]).is_err());
+        assert!(NiftiHeader::parse(&nifti1(false).bytes[..300]).is_err());
+        let mut writer = nifti1(false);
+        writer.i16(40, 2);
+        assert!(NiftiHeader::parse(&writer.bytes).is_err());
+    }
+}
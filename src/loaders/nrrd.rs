@@ -0,0 +1,470 @@
+use std::{fs, io::{Read, Write}, path::{Path, PathBuf}};
+
+use anyhow::{anyhow, bail, Context, Result};
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use glam::{UVec3, Vec3};
+
+use crate::voxel::{grid::VoxelGrid, volume::VolumeData};
+
+use super::{expand_file_pattern, progress::LoadProgress, scalar::{decode_scalars, normalization_range, volume_from_values, ByteOrder, ExportedVoxels, ScalarType}};
+
+/// How the data of a NRRD file is stored, given by the `encoding` field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NrrdEncoding {
+    Raw,
+    Gzip,
+    Ascii,
+}
+
+/// Parsed contents of a NRRD header
+#[derive(Debug, Clone)]
+pub struct NrrdHeader {
+    pub scalar_type: ScalarType,
+    pub sizes: Vec<usize>,
+    pub encoding: NrrdEncoding,
+    pub byte_order: ByteOrder,
+    /// Kind of every axis, empty if the header has no `kinds` field
+    pub kinds: Vec<String>,
+    /// Name of the world space, e.g. `left-posterior-superior`
+    pub space: Option<String>,
+    /// Direction of every axis in world space, `None` for axes without a direction such as color components
+    pub space_directions: Vec<Option<Vec3>>,
+    /// Position of the first voxel in world space
+    pub space_origin: Option<Vec3>,
+    /// Spacing of every axis, NaN where it is unknown
+    pub spacings: Vec<f32>,
+    /// Detached data files in reading order, empty if the data is attached to the header
+    pub data_files: Vec<PathBuf>,
+    pub line_skip: usize,
+    /// Amount of bytes to skip before the data, -1 means that the data is at the end of the file
+    pub byte_skip: i64,
+}
+
+impl NrrdEncoding {
+    fn parse(value: &str) -> Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "raw" => Ok(Self::Raw),
+            "gzip" | "gz" => Ok(Self::Gzip),
+            "ascii" | "text" | "txt" => Ok(Self::Ascii),
+            "bzip2" | "bz2" | "hex" => bail!("NRRD encoding '{}' is not supported, expected raw, gzip or ascii", value),
+            _ => bail!("Unknown NRRD encoding '{}'", value),
+        }
+    }
+}
+
+/// Parses the value of the `type` field
+fn parse_scalar_type(value: &str) -> Result<ScalarType> {
+    let scalar_type = match value.to_ascii_lowercase().as_str() {
+        "signed char" | "int8" | "int8_t" => ScalarType::I8,
+        "uchar" | "unsigned char" | "uint8" | "uint8_t" => ScalarType::U8,
+        "short" | "short int" | "signed short" | "signed short int" | "int16" | "int16_t" => ScalarType::I16,
+        "ushort" | "unsigned short" | "unsigned short int" | "uint16" | "uint16_t" => ScalarType::U16,
+        "int" | "signed int" | "int32" | "int32_t" => ScalarType::I32,
+        "uint" | "unsigned int" | "uint32" | "uint32_t" => ScalarType::U32,
+        "longlong" | "long long" | "long long int" | "signed long long" | "signed long long int" | "int64" | "int64_t" => ScalarType::I64,
+        "ulonglong" | "unsigned long long" | "unsigned long long int" | "uint64" | "uint64_t" => ScalarType::U64,
+        "float" => ScalarType::F32,
+        "double" => ScalarType::F64,
+        _ => bail!("Unsupported NRRD type '{}'", value),
+    };
+    Ok(scalar_type)
+}
+
+/// Parses a vector like `(1.5,0,0)`, or returns `None` for `none`
+fn parse_vector(value: &str) -> Result<Option<Vec3>> {
+    if value.eq_ignore_ascii_case("none") {
+        return Ok(None);
+    }
+    let components = value.trim_start_matches('(')
+        .trim_end_matches(')')
+        .split(',')
+        .map(|v| v.trim().parse::<f32>())
+        .collect::<std::result::Result<Vec<f32>, _>>()
+        .with_context(|| format!("Could not parse NRRD vector '{}'", value))?;
+    match components[..] {
+        [x, y, z] => Ok(Some(Vec3::new(x, y, z))),
+        _ => bail!("Only vectors in a three dimensional space are supported, found '{}'", value),
+    }
+}
+
+/// Parses a whitespace separated list of vectors, e.g. the value of `space directions`
+fn parse_vectors(value: &str) -> Result<Vec<Option<Vec3>>> {
+    // Vectors may contain spaces after the commas, so they are split at their closing brackets
+    let mut vectors = vec![];
+    let mut rest = value.trim();
+    while !rest.is_empty() {
+        let end = if rest.starts_with('(') {
+            rest.find(')').map(|i| i + 1).ok_or_else(|| anyhow!("Unterminated NRRD vector in '{}'", value))?
+        } else {
+            rest.find(char::is_whitespace).unwrap_or(rest.len())
+        };
+        vectors.push(parse_vector(&rest[..end])?);
+        rest = rest[end..].trim_start();
+    }
+    Ok(vectors)
+}
+
+/// Parses a whitespace separated list of values
+fn parse_list<T: std::str::FromStr>(value: &str, field: &str) -> Result<Vec<T>>
+where T::Err: std::error::Error + Send + Sync + 'static {
+    value.split_whitespace()
+        .map(|v| v.parse::<T>())
+        .collect::<std::result::Result<Vec<T>, _>>()
+        .with_context(|| format!("Could not parse NRRD field '{}' value '{}'", field, value))
+}
+
+/// Finds the end of the header, which is terminated by an empty line.
+/// Returns the header text and the offset of the attached data.
+fn split_header(bytes: &[u8]) -> Result<(&str, usize)> {
+    let mut start = 0;
+    let mut end = bytes.len();
+    let mut data_offset = bytes.len();
+    for (i, byte) in bytes.iter().enumerate() {
+        if *byte != b'\n' {
+            continue;
+        }
+        let line = &bytes[start..i];
+        if line.is_empty() || line == b"\r" {
+            end = start;
+            data_offset = i + 1;
+            break;
+        }
+        start = i + 1;
+    }
+    let header = std::str::from_utf8(&bytes[..end]).context("NRRD header is not valid text")?;
+    Ok((header, data_offset))
+}
+
+impl NrrdHeader {
+    /// Parses the header text of a NRRD file.
+    /// Comments and `key:=value` pairs are ignored, relative data files are resolved against `directory`.
+    pub fn parse(header: &str, directory: &Path) -> Result<Self> {
+        let mut lines = header.lines();
+        let magic = lines.next().unwrap_or("").trim();
+        if !magic.starts_with("NRRD000") {
+            bail!("File does not start with the NRRD magic, found '{}'", magic);
+        }
+
+        let mut scalar_type = None;
+        let mut sizes: Option<Vec<usize>> = None;
+        let mut encoding = None;
+        let mut byte_order = ByteOrder::LittleEndian;
+        let mut kinds = vec![];
+        let mut space = None;
+        let mut space_directions = vec![];
+        let mut space_origin = None;
+        let mut spacings = vec![];
+        let mut data_files = vec![];
+        let mut line_skip = 0;
+        let mut byte_skip = 0;
+
+        while let Some(line) = lines.next() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let field_separator = line.find(": ");
+            if let Some(key_separator) = line.find(":=") {
+                // Key/value pairs whose `:=` comes before any `: `
+                if !matches!(field_separator, Some(field_separator) if field_separator < key_separator) {
+                    continue;
+                }
+            }
+            let (field, value) = field_separator
+                .map(|i| (&line[..i], line[i + 2..].trim()))
+                .ok_or_else(|| anyhow!("Malformed NRRD header line '{}'", line))?;
+
+            match field.trim().to_ascii_lowercase().as_str() {
+                "type" => scalar_type = Some(parse_scalar_type(value)?),
+                "dimension" => {
+                    value.parse::<usize>().with_context(|| format!("Could not parse NRRD dimension '{}'", value))?;
+                },
+                "sizes" => sizes = Some(parse_list(value, "sizes")?),
+                "encoding" => encoding = Some(NrrdEncoding::parse(value)?),
+                "endian" => {
+                    byte_order = match value.to_ascii_lowercase().as_str() {
+                        "little" => ByteOrder::LittleEndian,
+                        "big" => ByteOrder::BigEndian,
+                        _ => bail!("Unknown NRRD endian '{}'", value),
+                    }
+                },
+                "kinds" => kinds = value.split_whitespace().map(|kind| kind.to_string()).collect(),
+                "space" => space = Some(value.to_ascii_lowercase()),
+                "space dimension" => {
+                    if value != "3" {
+                        bail!("Only three dimensional spaces are supported, found space dimension {}", value);
+                    }
+                },
+                "space directions" => space_directions = parse_vectors(value)?,
+                "space origin" => space_origin = parse_vector(value)?,
+                "spacings" => {
+                    spacings = value.split_whitespace()
+                        .map(|v| if v.eq_ignore_ascii_case("nan") { Ok(f32::NAN) } else { v.parse::<f32>() })
+                        .collect::<std::result::Result<Vec<f32>, _>>()
+                        .with_context(|| format!("Could not parse NRRD spacings '{}'", value))?;
+                },
+                "line skip" | "lineskip" => line_skip = value.parse().with_context(|| format!("Could not parse NRRD line skip '{}'", value))?,
+                "byte skip" | "byteskip" => byte_skip = value.parse().with_context(|| format!("Could not parse NRRD byte skip '{}'", value))?,
+                "data file" | "datafile" => {
+                    let parts: Vec<&str> = value.split_whitespace().collect();
+                    if parts.first().is_some_and(|part| part.eq_ignore_ascii_case("list")) {
+                        // All remaining lines of the header are file names
+                        data_files = lines.by_ref()
+                            .map(|line| line.trim())
+                            .filter(|line| !line.is_empty())
+                            .map(|line| directory.join(line))
+                            .collect();
+                    } else if parts.len() >= 4 && parts[0].contains('%') {
+                        let [min, max, step] = [parts[1], parts[2], parts[3]]
+                            .map(|v| v.parse::<i64>().with_context(|| format!("Could not parse NRRD data file range '{}'", value)));
+                        data_files = expand_file_pattern(parts[0], min?, max?, step?, directory)?;
+                    } else {
+                        data_files = vec![directory.join(value)];
+                    }
+                },
+                _ => continue
+            }
+        }
+
+        Ok(Self {
+            scalar_type: scalar_type.ok_or_else(|| anyhow!("NRRD header is missing the 'type' field"))?,
+            sizes: sizes.ok_or_else(|| anyhow!("NRRD header is missing the 'sizes' field"))?,
+            encoding: encoding.ok_or_else(|| anyhow!("NRRD header is missing the 'encoding' field"))?,
+            byte_order,
+            kinds,
+            space,
+            space_directions,
+            space_origin,
+            spacings,
+            data_files,
+            line_skip,
+            byte_skip,
+        })
+    }
+
+    /// Index of the axis holding the components of each voxel, if there is one.
+    /// Four dimensional files have their components along the first axis, unless its kind says otherwise.
+    pub fn component_axis(&self) -> Option<usize> {
+        if self.sizes.len() != 4 {
+            return None;
+        }
+        let kind = self.kinds.first().map(|kind| kind.to_ascii_lowercase());
+        match kind.as_deref() {
+            Some("domain" | "space" | "time") => None,
+            _ => Some(0),
+        }
+    }
+
+    /// Amount of scalars the data contains
+    pub fn value_count(&self) -> usize {
+        self.sizes.iter().product()
+    }
+}
+
+/// Reads a NRRD-File with attached or detached data into a volume
+pub fn read_volume(path: &str, progress: &LoadProgress) -> Result<VolumeData> {
+    let bytes = progress.read_file(path).with_context(|| format!("Could not open NRRD file '{}'", path))?;
+    let (header_text, data_offset) = split_header(&bytes)?;
+    let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+    let header = NrrdHeader::parse(header_text, directory)
+        .with_context(|| format!("Could not parse NRRD header '{}'", path))?;
+
+    let component_axis = header.component_axis();
+    if header.sizes.len() != 3 && component_axis.is_none() {
+        bail!("NRRD file '{}' has {} axes, only 3D volumes with an optional component axis are supported", path, header.sizes.len());
+    }
+    let components = component_axis.map_or(1, |axis| header.sizes[axis]);
+    if components > 4 {
+        bail!("NRRD file '{}' has {} components per voxel, at most 4 are supported", path, components);
+    }
+    let spatial_axes = if component_axis.is_some() { 1..4 } else { 0..3 };
+    let dimensions: Vec<u32> = header.sizes[spatial_axes.clone()].iter().map(|size| *size as u32).collect();
+    let dimensions = UVec3::from_slice(&dimensions);
+
+    // Detached files are read one after another, the skips apply to each of them
+    let value_count = header.value_count();
+    let mut values = Vec::with_capacity(value_count);
+    if header.data_files.is_empty() {
+        values.extend(read_values(&bytes[data_offset..], &header, value_count)?);
+    } else {
+        let values_per_file = value_count / header.data_files.len();
+        for data_file in &header.data_files {
+            let data = progress.read_file(data_file).with_context(|| format!("Could not open NRRD data file '{}'", data_file.display()))?;
+            values.extend(read_values(&data, &header, values_per_file)
+                .with_context(|| format!("Could not read NRRD data file '{}'", data_file.display()))?);
+        }
+    }
+    if values.len() < value_count {
+        bail!("NRRD file '{}' contains {} values, but the header describes {}", path, values.len(), value_count);
+    }
+
+    let values = &values[..value_count];
+    let mut volume = volume_from_values(dimensions, components, values, normalization_range(header.scalar_type, values), header.scalar_type)?;
+
+    // Axis directions and spacings come from the space directions if present, otherwise from the spacings
+    let mut directions = [Vec3::X, Vec3::Y, Vec3::Z];
+    let mut spacing = Vec3::ONE;
+    for (i, axis) in spatial_axes.enumerate() {
+        if let Some(Some(direction)) = header.space_directions.get(axis) {
+            spacing[i] = direction.length();
+            directions[i] = to_ras(*direction, header.space.as_deref());
+        } else if let Some(value) = header.spacings.get(axis).filter(|value| value.is_finite() && **value > 0.0) {
+            spacing[i] = *value;
+        }
+    }
+    volume.spacing = spacing;
+    volume.origin = header.space_origin.map_or(Vec3::ZERO, |origin| to_ras(origin, header.space.as_deref()));
+    volume.reorient(directions);
+    Ok(volume)
+}
+
+/// Converts a direction or position from the anatomical space of the file into right-anterior-superior space
+fn to_ras(direction: Vec3, space: Option<&str>) -> Vec3 {
+    match space {
+        Some("left-posterior-superior" | "lps") => direction * Vec3::new(-1.0, -1.0, 1.0),
+        Some("left-anterior-superior" | "las") => direction * Vec3::new(-1.0, 1.0, 1.0),
+        _ => direction,
+    }
+}
+
+/// Decodes the values of a single data file or of the attached data
+fn read_values(data: &[u8], header: &NrrdHeader, value_count: usize) -> Result<Vec<f32>> {
+    let mut data = data;
+    for _ in 0..header.line_skip {
+        let end = data.iter().position(|byte| *byte == b'\n').ok_or_else(|| anyhow!("Data ends before the skipped lines"))?;
+        data = &data[end + 1..];
+    }
+
+    if header.encoding == NrrdEncoding::Ascii {
+        let text = std::str::from_utf8(data).context("ASCII encoded data is not valid text")?;
+        return text.split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|value| !value.is_empty())
+            .take(value_count)
+            .map(|value| value.parse::<f32>().with_context(|| format!("Could not parse ASCII value '{}'", value)))
+            .collect();
+    }
+
+    let decompressed;
+    if header.encoding == NrrdEncoding::Gzip {
+        let mut bytes = vec![];
+        MultiGzDecoder::new(data).read_to_end(&mut bytes).context("Could not decompress gzip encoded data")?;
+        decompressed = bytes;
+        data = &decompressed;
+    }
+
+    let byte_len = value_count * header.scalar_type.size();
+    let data = if header.byte_skip < 0 {
+        let start = data.len().checked_sub(byte_len)
+            .ok_or_else(|| anyhow!("Data contains {} bytes, but {} are needed", data.len(), byte_len))?;
+        &data[start..]
+    } else {
+        data.get(header.byte_skip as usize..).ok_or_else(|| anyhow!("Data ends before the skipped bytes"))?
+    };
+    if data.len() < byte_len {
+        bail!("Data contains {} bytes, but {} are needed", data.len(), byte_len);
+    }
+    Ok(decode_scalars(&data[..byte_len], header.scalar_type, header.byte_order))
+}
+
+/// Writes the voxels of a Voxel Grid into a gzip encoded NRRD-File at the precision of the grid.
+/// Scalar and grayscale volumes are written as scalars, all others as RGBA colors.
+/// A `.nhdr` path writes a detached header next to a `.raw.gz` data file.
+pub fn write_voxel_grid(path: &str, grid: &VoxelGrid) -> Result<()> {
+    let ExportedVoxels { scalar_type, components, bytes: data } = ExportedVoxels::new(grid)?;
+    let type_name = match scalar_type {
+        ScalarType::U16 => "ushort",
+        ScalarType::F32 => "float",
+        _ => "uint8",
+    };
+
+    let [x, y, z] = grid.dimensions.to_array();
+    let [sx, sy, sz] = grid.spacing.to_array();
+    let mut header = format!("NRRD0004\n# Written by volume-renderer\ntype: {}\n", type_name);
+    if components == 1 {
+        header += &format!("dimension: 3\nsizes: {} {} {}\nkinds: domain domain domain\n", x, y, z);
+        header += &format!("space: right-anterior-superior\nspace directions: ({},0,0) (0,{},0) (0,0,{})\n", sx, sy, sz);
+    } else {
+        header += &format!("dimension: 4\nsizes: 4 {} {} {}\nkinds: RGBA-color domain domain domain\n", x, y, z);
+        header += &format!("space: right-anterior-superior\nspace directions: none ({},0,0) (0,{},0) (0,0,{})\n", sx, sy, sz);
+    }
+    let [ox, oy, oz] = grid.origin.to_array();
+    header += &format!("space origin: ({},{},{})\nendian: little\nencoding: gzip\n", ox, oy, oz);
+
+    let mut encoder = GzEncoder::new(vec![], Compression::default());
+    encoder.write_all(&data)?;
+    let compressed = encoder.finish()?;
+
+    let path = Path::new(path);
+    let detached = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("nhdr"));
+    if detached {
+        let data_path = path.with_extension("raw.gz");
+        let data_file_name = data_path.file_name().and_then(|name| name.to_str()).unwrap_or("volume.raw.gz");
+        header += &format!("data file: {}\n", data_file_name);
+        fs::write(&data_path, &compressed).with_context(|| format!("Could not write NRRD data file '{}'", data_path.display()))?;
+        fs::write(path, header).with_context(|| format!("Could not write NRRD header '{}'", path.display()))?;
+    } else {
+        let mut file = fs::File::create(path).with_context(|| format!("Could not create NRRD file '{}'", path.display()))?;
+        file.write_all(header.as_bytes())?;
+        file.write_all(b"\n")?;
+        file.write_all(&compressed)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_header(fields: &str) -> NrrdHeader {
+        NrrdHeader::parse(&format!("NRRD0004\n# comment\n{}", fields), Path::new("")).unwrap()
+    }
+
+    #[test]
+    fn parses_header_fields() {
+        let header = parse_header("type: short\ndimension: 4\nsizes: 3 4 5 6\nkinds: RGB-color domain domain domain\nendian: big\nencoding: raw\nspace: left-posterior-superior\nspace directions: none (0.5, 0,0) (0,0.5,0) (0,0,2)\nspace origin: (-10, 20.5,3)\nkey:=value\n");
+        assert_eq!(header.scalar_type, ScalarType::I16);
+        assert_eq!(header.sizes, vec![3, 4, 5, 6]);
+        assert_eq!(header.byte_order, ByteOrder::BigEndian);
+        assert_eq!(header.component_axis(), Some(0));
+        assert_eq!(header.space_directions, vec![None, Some(Vec3::new(0.5, 0.0, 0.0)), Some(Vec3::new(0.0, 0.5, 0.0)), Some(Vec3::new(0.0, 0.0, 2.0))]);
+        assert_eq!(header.space_origin, Some(Vec3::new(-10.0, 20.5, 3.0)));
+        assert_eq!(to_ras(header.space_origin.unwrap(), header.space.as_deref()), Vec3::new(10.0, -20.5, 3.0));
+        assert_eq!(header.value_count(), 360);
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        assert!(NrrdHeader::parse("NRRX0004\ntype: uchar\nsizes: 1 1 1\nencoding: raw", Path::new("")).is_err());
+        assert!(NrrdHeader::parse("NRRD0004\ntype: uchar\nsizes: 1 1 1\nencoding: bzip2", Path::new("")).is_err());
+        assert!(NrrdHeader::parse("NRRD0004\ntype: uchar\nsizes: 1 1 1", Path::new("")).is_err());
+    }
+
+    #[test]
+    fn splits_attached_data() {
+        let bytes = b"NRRD0004\r\ntype: uchar\r\n\r\n\x01\x02";
+        let (text, offset) = split_header(bytes).unwrap();
+        assert!(text.ends_with("type: uchar\r\n"));
+        assert_eq!(&bytes[offset..], b"\x01\x02");
+    }
+
+    #[test]
+    fn decodes_byte_orders() {
+        let big = parse_header("type: ushort\nsizes: 2 1 1\nendian: big\nencoding: raw\n");
+        assert_eq!(read_values(&[0x01, 0x02, 0x00, 0xff], &big, 2).unwrap(), vec![258.0, 255.0]);
+        let little = parse_header("type: ushort\nsizes: 2 1 1\nendian: little\nencoding: raw\n");
+        assert_eq!(read_values(&[0x01, 0x02, 0x00, 0xff], &little, 2).unwrap(), vec![513.0, 65280.0]);
+    }
+
+    #[test]
+    fn decodes_gzip_with_skips() {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(&[9, 9, 1, 2, 3]).unwrap();
+        let data = encoder.finish().unwrap();
+        let header = parse_header("type: uchar\nsizes: 3 1 1\nencoding: gzip\nbyte skip: -1\n");
+        assert_eq!(read_values(&data, &header, 3).unwrap(), vec![1.0, 2.0, 3.0]);
+
+        let header = parse_header("type: float\nsizes: 2 1 1\nencoding: ascii\nline skip: 1\n");
+        assert_eq!(read_values(b"skipped line\n1.5, -2\n", &header, 2).unwrap(), vec![1.5, -2.0]);
+    }
+}
@@ -0,0 +1,104 @@
+use std::{fs::File, io::Read, path::Path, sync::{atomic::{AtomicBool, AtomicU64, Ordering}, Mutex}};
+
+use anyhow::{bail, Result};
+
+/// Amount of bytes read at once, between two checks for cancellation
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// Progress of a volume that is read on a worker thread, shared with the GUI.
+/// Loaders report the bytes they read and the slices they converted and stop once the load is cancelled.
+#[derive(Debug, Default)]
+pub struct LoadProgress {
+    bytes_read: AtomicU64,
+    total_bytes: AtomicU64,
+    slices_done: AtomicU64,
+    total_slices: AtomicU64,
+    cancelled: AtomicBool,
+    /// Messages about parts of the file that were skipped or changed, shown to the user once the volume is loaded
+    notices: Mutex<Vec<String>>,
+}
+
+impl LoadProgress {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Returns an error once the load was cancelled, so that loaders can stop with `?`
+    pub fn check_cancelled(&self) -> Result<()> {
+        if self.is_cancelled() {
+            bail!("Loading was cancelled");
+        }
+        Ok(())
+    }
+
+    /// Tells the user about a part of the file that was skipped or changed once the volume is loaded
+    pub fn notice(&self, message: String) {
+        self.notices.lock().unwrap().push(message);
+    }
+
+    /// Returns the notices that were reported since the last call
+    pub fn take_notices(&self) -> Vec<String> {
+        std::mem::take(&mut self.notices.lock().unwrap())
+    }
+
+    /// Reads a whole file in chunks, counting the read bytes.
+    /// Reading several files adds up their sizes.
+    pub fn read_file(&self, path: impl AsRef<Path>) -> Result<Vec<u8>> {
+        let path = path.as_ref();
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+        self.total_bytes.fetch_add(len, Ordering::Relaxed);
+
+        let mut bytes = Vec::with_capacity(len as usize);
+        let mut chunk = vec![0u8; CHUNK_SIZE];
+        loop {
+            self.check_cancelled()?;
+            let read = file.read(&mut chunk)?;
+            if read == 0 {
+                break;
+            }
+            bytes.extend_from_slice(&chunk[..read]);
+            self.bytes_read.fetch_add(read as u64, Ordering::Relaxed);
+        }
+        Ok(bytes)
+    }
+
+    /// Starts counting the slices of a volume, replacing the slices of a previous step
+    pub fn start_slices(&self, count: usize) {
+        self.slices_done.store(0, Ordering::Relaxed);
+        self.total_slices.store(count as u64, Ordering::Relaxed);
+    }
+
+    /// Counts a finished slice and stops the loader if the load was cancelled
+    pub fn finish_slice(&self) -> Result<()> {
+        self.finish_slices(1)
+    }
+
+    /// Counts several finished slices at once, e.g. a slab that was read in one go
+    pub fn finish_slices(&self, count: usize) -> Result<()> {
+        self.slices_done.fetch_add(count as u64, Ordering::Relaxed);
+        self.check_cancelled()
+    }
+
+    /// Completed fraction of the current step, if it is known, and a description of it for the GUI
+    pub fn status(&self) -> (Option<f32>, String) {
+        let total_slices = self.total_slices.load(Ordering::Relaxed);
+        if total_slices > 0 {
+            let done = self.slices_done.load(Ordering::Relaxed).min(total_slices);
+            return (Some(done as f32 / total_slices as f32), format!("{} of {} slices", done, total_slices));
+        }
+
+        let total_bytes = self.total_bytes.load(Ordering::Relaxed);
+        if total_bytes > 0 {
+            let read = self.bytes_read.load(Ordering::Relaxed).min(total_bytes);
+            let megabytes = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+            return (Some(read as f32 / total_bytes as f32), format!("{:.1} of {:.1} MB read", megabytes(read), megabytes(total_bytes)));
+        }
+
+        (None, "Loading…".to_string())
+    }
+}
@@ -0,0 +1,152 @@
+use std::{fs::File, io::{BufReader, Read, Seek}};
+
+use anyhow::{bail, Context, Result};
+use glam::{UVec3, Vec3};
+
+use crate::voxel::volume::VolumeData;
+
+use super::{progress::LoadProgress, scalar::{data_range, decode_scalars, normalization_range, to_bytes, volume_from_values, ByteOrder, ScalarType}};
+
+/// Order in which the axes are stored in a raw file, from the fastest to the slowest changing axis
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AxisOrder {
+    Xyz,
+    Xzy,
+    Yxz,
+    Yzx,
+    Zxy,
+    Zyx,
+}
+
+impl AxisOrder {
+    pub const ALL: [AxisOrder; 6] = [Self::Xyz, Self::Xzy, Self::Yxz, Self::Yzx, Self::Zxy, Self::Zyx];
+
+    /// Volume axes from the fastest to the slowest changing one
+    pub fn axes(&self) -> [usize; 3] {
+        match self {
+            Self::Xyz => [0, 1, 2],
+            Self::Xzy => [0, 2, 1],
+            Self::Yxz => [1, 0, 2],
+            Self::Yzx => [1, 2, 0],
+            Self::Zxy => [2, 0, 1],
+            Self::Zyx => [2, 1, 0],
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Xyz => "X Y Z",
+            Self::Xzy => "X Z Y",
+            Self::Yxz => "Y X Z",
+            Self::Yzx => "Y Z X",
+            Self::Zxy => "Z X Y",
+            Self::Zyx => "Z Y X",
+        }
+    }
+}
+
+/// Layout of a raw file without a header describing it, entered by the user
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawLayout {
+    /// Size of the volume along its x, y and z axis
+    pub dimensions: UVec3,
+    pub scalar_type: ScalarType,
+    pub byte_order: ByteOrder,
+    /// Amount of bytes before the voxel data
+    pub header_skip: u64,
+    pub axis_order: AxisOrder,
+    pub spacing: Vec3,
+}
+
+impl Default for RawLayout {
+    fn default() -> Self {
+        Self {
+            dimensions: UVec3::splat(256),
+            scalar_type: ScalarType::U8,
+            byte_order: ByteOrder::LittleEndian,
+            header_skip: 0,
+            axis_order: AxisOrder::Xyz,
+            spacing: Vec3::ONE,
+        }
+    }
+}
+
+impl RawLayout {
+    pub fn voxel_count(&self) -> u64 {
+        self.dimensions.x as u64 * self.dimensions.y as u64 * self.dimensions.z as u64
+    }
+
+    /// Amount of bytes a file with this layout has to contain, including the skipped header
+    pub fn expected_file_len(&self) -> u64 {
+        self.header_skip + self.voxel_count() * self.scalar_type.size() as u64
+    }
+
+    /// Checks that the file is exactly as large as the layout describes
+    pub fn check_file_len(&self, file_len: u64) -> Result<()> {
+        if self.voxel_count() == 0 {
+            bail!("Dimensions must not contain zero sizes");
+        }
+        let expected_len = self.expected_file_len();
+        if file_len != expected_len {
+            bail!("File contains {} bytes, but the layout describes {} bytes ({:+})", file_len, expected_len, file_len as i64 - expected_len as i64);
+        }
+        Ok(())
+    }
+
+    /// Index of a voxel inside the file
+    fn file_index(&self, position: UVec3) -> u64 {
+        let [fast, middle, slow] = self.axis_order.axes();
+        let dimensions = self.dimensions.as_u64vec3();
+        let position = position.as_u64vec3();
+        position[fast] + dimensions[fast] * (position[middle] + dimensions[middle] * position[slow])
+    }
+}
+
+/// Reads a raw file with the given layout into a volume.
+/// 8-bit values are kept, all other types are normalized by the range of the data.
+pub fn read_volume(path: &str, layout: &RawLayout, progress: &LoadProgress) -> Result<VolumeData> {
+    let bytes = progress.read_file(path).with_context(|| format!("Could not open raw file '{}'", path))?;
+    layout.check_file_len(bytes.len() as u64)?;
+
+    let file_values = decode_scalars(&bytes[layout.header_skip as usize..], layout.scalar_type, layout.byte_order);
+    let values = if layout.axis_order == AxisOrder::Xyz {
+        file_values
+    } else {
+        let dimensions = layout.dimensions;
+        let mut values = Vec::with_capacity(file_values.len());
+        for z in 0..dimensions.z {
+            for y in 0..dimensions.y {
+                for x in 0..dimensions.x {
+                    values.push(file_values[layout.file_index(UVec3::new(x, y, z)) as usize]);
+                }
+            }
+        }
+        values
+    };
+
+    let mut volume = volume_from_values(layout.dimensions, 1, &values, normalization_range(layout.scalar_type, &values), layout.scalar_type)?;
+    volume.spacing = layout.spacing;
+    Ok(volume)
+}
+
+/// Reads the slice at `z` as 8-bit values normalized by the range of the slice, rows ordered by increasing y.
+/// Only the voxels of the slice are read, so it stays fast for large files.
+pub fn read_slice(path: &str, layout: &RawLayout, z: u32) -> Result<Vec<u8>> {
+    let file = File::open(path).with_context(|| format!("Could not open raw file '{}'", path))?;
+    layout.check_file_len(file.metadata()?.len())?;
+
+    let size = layout.scalar_type.size();
+    let mut reader = BufReader::new(file);
+    let mut bytes = vec![0u8; layout.dimensions.x as usize * layout.dimensions.y as usize * size];
+    for (i, voxel) in bytes.chunks_exact_mut(size).enumerate() {
+        let position = UVec3::new(i as u32 % layout.dimensions.x, i as u32 / layout.dimensions.x, z);
+        let offset = layout.header_skip + layout.file_index(position) * size as u64;
+        // Relative seeks keep the buffer when neighbouring voxels are read
+        let current = reader.stream_position()?;
+        reader.seek_relative(offset as i64 - current as i64)?;
+        reader.read_exact(voxel)?;
+    }
+
+    let values = decode_scalars(&bytes, layout.scalar_type, layout.byte_order);
+    Ok(to_bytes(&values, data_range(&values)))
+}
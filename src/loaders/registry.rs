@@ -0,0 +1,318 @@
+use std::{fs::File, io::Read, path::Path};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::voxel::volume::VolumeData;
+
+use super::{cvol, dat, dicom, metaimage, netcdf, nifti, nrrd, progress::LoadProgress, stream::SlabSink, vtk};
+
+/// Amount of bytes at the start of a file that are handed to [`VolumeLoader::detect`]
+const HEADER_LEN: usize = 1024;
+
+/// A file format that volumes can be read from.
+/// Implement this for custom formats and add them to a [`LoaderRegistry`].
+pub trait VolumeLoader: Send + Sync {
+    /// Name of the format, shown in file dialogs
+    fn name(&self) -> &str;
+
+    /// File extensions without the leading dot, e.g. `nii.gz`
+    fn extensions(&self) -> &[&str];
+
+    /// Whether the first bytes of a file identify it as this format.
+    /// Formats without a signature keep the default and are only chosen by their extension.
+    fn detect(&self, header: &[u8]) -> bool {
+        let _ = header;
+        false
+    }
+
+    /// Reads a volume, reporting the read bytes or converted slices to `progress` and stopping once it is cancelled
+    fn read_volume(&self, path: &str, progress: &LoadProgress) -> Result<VolumeData>;
+
+    /// Whether the file is too large to be read at once and is read with [`VolumeLoader::stream_volume`] instead
+    fn streams(&self, path: &str) -> bool {
+        let _ = path;
+        false
+    }
+
+    /// Reads a volume slab by slab into `sink`, so that memory use stays bounded regardless of the size of the volume
+    fn stream_volume(&self, path: &str, progress: &LoadProgress, sink: &mut dyn SlabSink) -> Result<()> {
+        let _ = (progress, sink);
+        bail!("{} files like '{}' can't be streamed", self.name(), path)
+    }
+}
+
+/// Loaders that volume files are opened with.
+/// The loader of a file is chosen by the signature at its start and, if no loader recognizes it, by its extension.
+/// Loaders registered later take precedence, so custom loaders can replace the built-in ones.
+pub struct LoaderRegistry {
+    loaders: Vec<Box<dyn VolumeLoader>>,
+}
+
+impl Default for LoaderRegistry {
+    /// Registry with all built-in formats that can be read without further user input
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register(NetcdfLoader);
+        registry.register(DatLoader);
+        registry.register(NiftiLoader);
+        registry.register(NrrdLoader);
+        registry.register(MetaImageLoader);
+        registry.register(VtkLoader);
+        registry.register(CvolLoader);
+        registry.register(DicomLoader);
+        registry
+    }
+}
+
+impl LoaderRegistry {
+    pub fn empty() -> Self {
+        Self { loaders: vec![] }
+    }
+
+    pub fn register(&mut self, loader: impl VolumeLoader + 'static) {
+        self.loaders.push(Box::new(loader));
+    }
+
+    /// Registered loaders, starting with the one that takes precedence
+    pub fn loaders(&self) -> impl Iterator<Item = &dyn VolumeLoader> {
+        self.loaders.iter().rev().map(|loader| loader.as_ref())
+    }
+
+    /// Extensions for filtering file dialogs. Dialogs only match the last part of an extension, so `nii.gz` becomes `gz`.
+    pub fn dialog_extensions(&self) -> Vec<&str> {
+        let mut extensions: Vec<&str> = self.loaders()
+            .flat_map(|loader| loader.extensions().iter())
+            .map(|extension| extension.rsplit('.').next().unwrap_or(extension))
+            .collect();
+        extensions.sort();
+        extensions.dedup();
+        extensions
+    }
+
+    /// Chooses the loader for a file
+    pub fn find(&self, path: &str) -> Result<&dyn VolumeLoader> {
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        File::open(path).with_context(|| format!("Could not open file '{}'", path))?
+            .take(HEADER_LEN as u64)
+            .read_to_end(&mut header)?;
+        if let Some(loader) = self.loaders().find(|loader| loader.detect(&header)) {
+            return Ok(loader);
+        }
+
+        // The longest matching extension wins, so that `.nii.gz` is preferred over `.gz`
+        let file_name = Path::new(path).file_name()
+            .map(|name| name.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        self.loaders()
+            .filter_map(|loader| {
+                loader.extensions().iter()
+                    .filter(|extension| file_name.ends_with(&format!(".{}", extension.to_ascii_lowercase())))
+                    .map(|extension| extension.len())
+                    .max()
+                    .map(|len| (len, loader))
+            })
+            // Ties are won by the loader that takes precedence
+            .fold(None, |best: Option<(usize, &dyn VolumeLoader)>, (len, loader)| match best {
+                Some((best_len, _)) if best_len >= len => best,
+                _ => Some((len, loader)),
+            })
+            .map(|(_, loader)| loader)
+            .ok_or_else(|| anyhow!("No loader found for file '{}'", path))
+    }
+
+    /// Reads a volume file with the loader chosen for it
+    pub fn read_volume(&self, path: &str, progress: &LoadProgress) -> Result<VolumeData> {
+        let loader = self.find(path)?;
+        loader.read_volume(path, progress).with_context(|| format!("Could not read '{}' as {}", path, loader.name()))
+    }
+
+    /// Whether the loader chosen for a file streams it instead of reading it at once
+    pub fn streams(&self, path: &str) -> bool {
+        self.find(path).is_ok_and(|loader| loader.streams(path))
+    }
+
+    /// Streams a volume file slab by slab with the loader chosen for it
+    pub fn stream_volume(&self, path: &str, progress: &LoadProgress, sink: &mut dyn SlabSink) -> Result<()> {
+        let loader = self.find(path)?;
+        loader.stream_volume(path, progress, sink).with_context(|| format!("Could not stream '{}' as {}", path, loader.name()))
+    }
+}
+
+struct NetcdfLoader;
+
+impl VolumeLoader for NetcdfLoader {
+    fn name(&self) -> &str {
+        "NetCDF"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["nc"]
+    }
+
+    fn detect(&self, header: &[u8]) -> bool {
+        // Classic NetCDF files start with `CDF`, NetCDF-4 files are HDF5 files
+        header.starts_with(b"CDF") || header.starts_with(b"\x89HDF\r\n\x1a\n")
+    }
+
+    fn read_volume(&self, path: &str, progress: &LoadProgress) -> Result<VolumeData> {
+        netcdf::read_default_variable(path, progress)
+    }
+}
+
+struct DatLoader;
+
+impl VolumeLoader for DatLoader {
+    fn name(&self) -> &str {
+        "DAT"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["dat"]
+    }
+
+    fn read_volume(&self, path: &str, progress: &LoadProgress) -> Result<VolumeData> {
+        dat::read_volume(path, progress)
+    }
+
+    fn streams(&self, path: &str) -> bool {
+        dat::read_header(path).is_ok_and(|header| header.needs_streaming())
+    }
+
+    fn stream_volume(&self, path: &str, progress: &LoadProgress, sink: &mut dyn SlabSink) -> Result<()> {
+        dat::stream_volume(path, progress, sink)
+    }
+}
+
+struct NiftiLoader;
+
+impl VolumeLoader for NiftiLoader {
+    fn name(&self) -> &str {
+        "NIfTI"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["nii", "nii.gz", "hdr", "hdr.gz"]
+    }
+
+    fn detect(&self, header: &[u8]) -> bool {
+        // NIfTI-1 keeps its magic at the end of the 348 byte header, NIfTI-2 right after the header size
+        let nifti1 = header.get(344..347).is_some_and(|magic| magic == b"n+1" || magic == b"ni1");
+        let nifti2 = header.get(4..7).is_some_and(|magic| magic == b"n+2" || magic == b"ni2");
+        nifti1 || nifti2
+    }
+
+    fn read_volume(&self, path: &str, progress: &LoadProgress) -> Result<VolumeData> {
+        nifti::read_volume(path, progress)
+    }
+}
+
+struct NrrdLoader;
+
+impl VolumeLoader for NrrdLoader {
+    fn name(&self) -> &str {
+        "NRRD"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["nrrd", "nhdr"]
+    }
+
+    fn detect(&self, header: &[u8]) -> bool {
+        header.starts_with(b"NRRD")
+    }
+
+    fn read_volume(&self, path: &str, progress: &LoadProgress) -> Result<VolumeData> {
+        nrrd::read_volume(path, progress)
+    }
+}
+
+struct MetaImageLoader;
+
+impl VolumeLoader for MetaImageLoader {
+    fn name(&self) -> &str {
+        "MetaImage"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["mhd", "mha"]
+    }
+
+    fn detect(&self, header: &[u8]) -> bool {
+        header.starts_with(b"ObjectType")
+    }
+
+    fn read_volume(&self, path: &str, progress: &LoadProgress) -> Result<VolumeData> {
+        metaimage::read_volume(path, progress)
+    }
+}
+
+struct VtkLoader;
+
+impl VolumeLoader for VtkLoader {
+    fn name(&self) -> &str {
+        "VTK"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["vti", "vtk"]
+    }
+
+    fn detect(&self, header: &[u8]) -> bool {
+        let text = String::from_utf8_lossy(header);
+        text.starts_with("# vtk DataFile") || (text.contains("<VTKFile") && text.contains("ImageData"))
+    }
+
+    fn read_volume(&self, path: &str, progress: &LoadProgress) -> Result<VolumeData> {
+        vtk::read_volume(path, progress)
+    }
+}
+
+struct CvolLoader;
+
+impl VolumeLoader for CvolLoader {
+    fn name(&self) -> &str {
+        "DiffDVR Volume"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["cvol"]
+    }
+
+    fn detect(&self, header: &[u8]) -> bool {
+        header.get(..4).is_some_and(|magic| magic.eq_ignore_ascii_case(b"CVOL"))
+    }
+
+    fn read_volume(&self, path: &str, progress: &LoadProgress) -> Result<VolumeData> {
+        cvol::read_volume(path, progress)
+    }
+}
+
+/// Loads the series a single DICOM file belongs to, taking the other slices from the same folder
+struct DicomLoader;
+
+impl VolumeLoader for DicomLoader {
+    fn name(&self) -> &str {
+        "DICOM"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["dcm"]
+    }
+
+    fn detect(&self, header: &[u8]) -> bool {
+        header.get(128..132).is_some_and(|magic| magic == b"DICM")
+    }
+
+    fn read_volume(&self, path: &str, progress: &LoadProgress) -> Result<VolumeData> {
+        let directory = Path::new(path).parent()
+            .and_then(|directory| directory.to_str())
+            .filter(|directory| !directory.is_empty())
+            .unwrap_or(".");
+        // Files of a series are listed relative to the scanned directory
+        let file = Path::new(directory).join(Path::new(path).file_name().unwrap_or_default());
+        let series = dicom::list_series(directory, progress)?.into_iter()
+            .find(|series| series.files.contains(&file))
+            .ok_or_else(|| anyhow!("'{}' does not belong to a DICOM series", path))?;
+        dicom::read_series(&series, true, progress)
+    }
+}
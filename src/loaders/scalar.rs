@@ -0,0 +1,213 @@
+use anyhow::{bail, Result};
+use glam::UVec3;
+
+use crate::voxel::{format::{PreciseVoxels, VoxelFormat}, grid::VoxelGrid, volume::VolumeData};
+
+/// Scalar type of a single voxel component in a binary file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScalarType {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+    I64,
+    F32,
+    F64,
+}
+
+/// Byte order of multi-byte scalars in a binary file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    LittleEndian,
+    BigEndian,
+}
+
+impl ScalarType {
+    pub fn size(&self) -> usize {
+        match self {
+            Self::U8 | Self::I8 => 1,
+            Self::U16 | Self::I16 => 2,
+            Self::U32 | Self::I32 | Self::F32 => 4,
+            Self::U64 | Self::I64 | Self::F64 => 8,
+        }
+    }
+
+    /// Format that keeps the precision of the type, or `None` for 8-bit data that the 8-bit colors already hold.
+    /// 16-bit intensities stay normalized integers, everything else becomes float.
+    pub fn precise_format(&self, components: usize) -> Option<VoxelFormat> {
+        match (self, components) {
+            (Self::U8 | Self::I8, _) => None,
+            (Self::U16 | Self::I16, 1) => Some(VoxelFormat::R16Unorm),
+            (_, 1) => Some(VoxelFormat::R32Float),
+            _ => Some(VoxelFormat::Rgba32Float),
+        }
+    }
+
+    /// Format of a single channel texture that keeps the precision of the type
+    pub fn scalar_format(&self) -> VoxelFormat {
+        self.precise_format(1).unwrap_or(VoxelFormat::R8Unorm)
+    }
+
+    /// Decodes a single scalar from exactly `size()` bytes
+    pub fn decode(&self, bytes: &[u8], byte_order: ByteOrder) -> f64 {
+        macro_rules! decode {
+            ($type:ty) => {{
+                let bytes = bytes.try_into().expect("Slice has to match the scalar size");
+                match byte_order {
+                    ByteOrder::LittleEndian => <$type>::from_le_bytes(bytes) as f64,
+                    ByteOrder::BigEndian => <$type>::from_be_bytes(bytes) as f64,
+                }
+            }};
+        }
+
+        match self {
+            Self::U8 => bytes[0] as f64,
+            Self::I8 => bytes[0] as i8 as f64,
+            Self::U16 => decode!(u16),
+            Self::I16 => decode!(i16),
+            Self::U32 => decode!(u32),
+            Self::I32 => decode!(i32),
+            Self::U64 => decode!(u64),
+            Self::I64 => decode!(i64),
+            Self::F32 => decode!(f32),
+            Self::F64 => decode!(f64),
+        }
+    }
+}
+
+impl ByteOrder {
+    /// Byte order of the machine the renderer runs on
+    pub fn native() -> Self {
+        if cfg!(target_endian = "big") { Self::BigEndian } else { Self::LittleEndian }
+    }
+}
+
+/// Decodes tightly packed scalars. Trailing bytes that don't form a whole scalar are ignored.
+pub fn decode_scalars(bytes: &[u8], scalar_type: ScalarType, byte_order: ByteOrder) -> Vec<f32> {
+    bytes.chunks_exact(scalar_type.size())
+        .map(|chunk| scalar_type.decode(chunk, byte_order) as f32)
+        .collect()
+}
+
+/// Returns the smallest and largest finite value, or 0..1 if there is none
+pub fn data_range(values: &[f32]) -> (f32, f32) {
+    let range = values.iter()
+        .filter(|v| v.is_finite())
+        .fold((f32::MAX, f32::MIN), |(min, max), v| (min.min(*v), max.max(*v)));
+    if range.0 > range.1 { (0.0, 1.0) } else { range }
+}
+
+/// Range that is mapped to 8-bit values: 8-bit data keeps its values, everything else is normalized by the range of the data
+pub fn normalization_range(scalar_type: ScalarType, values: &[f32]) -> (f32, f32) {
+    match scalar_type {
+        ScalarType::U8 => (0.0, u8::MAX as f32),
+        _ => data_range(values),
+    }
+}
+
+/// Maps values from a range to 8-bit values, non-finite values become zero
+pub fn to_bytes(values: &[f32], range: (f32, f32)) -> Vec<u8> {
+    let scale = if range.1 > range.0 { 1.0 / (range.1 - range.0) } else { 1.0 };
+    values.iter()
+        .map(|v| if v.is_finite() { (((v - range.0) * scale).clamp(0.0, 1.0) * 255.0) as u8 } else { 0 })
+        .collect()
+}
+
+/// Maps values from a range to 0..1 without quantizing them, non-finite values become zero
+pub fn normalize(values: &[f32], range: (f32, f32)) -> Vec<f32> {
+    let scale = if range.1 > range.0 { 1.0 / (range.1 - range.0) } else { 1.0 };
+    values.iter()
+        .map(|v| if v.is_finite() { ((v - range.0) * scale).clamp(0.0, 1.0) } else { 0.0 })
+        .collect()
+}
+
+/// Converts the components of a single voxel into a color.
+/// Intensities are used for every channel, two components are treated as luminance and alpha
+/// and colors without alpha use their brightest channel as density.
+pub fn components_to_color(components: &[u8]) -> [u8; 4] {
+    match components {
+        [i] => [*i; 4],
+        [l, a] => [*l, *l, *l, *a],
+        [r, g, b] => [*r, *g, *b, *r.max(g).max(b)],
+        [r, g, b, a, ..] => [*r, *g, *b, *a],
+        [] => [0; 4],
+    }
+}
+
+/// Converts the normalized components of a single voxel into a color like [`components_to_color`]
+pub fn components_to_color_f32(components: &[f32]) -> [f32; 4] {
+    match components {
+        [i] => [*i; 4],
+        [l, a] => [*l, *l, *l, *a],
+        [r, g, b] => [*r, *g, *b, r.max(*g).max(*b)],
+        [r, g, b, a, ..] => [*r, *g, *b, *a],
+        [] => [0.0; 4],
+    }
+}
+
+/// Builds a volume from values that are normalized by a range, where the components of each voxel are interleaved.
+/// Values of types wider than 8 bits are additionally kept at full precision.
+pub fn volume_from_values(dimensions: UVec3, components: usize, values: &[f32], range: (f32, f32), scalar_type: ScalarType) -> Result<VolumeData> {
+    let mut volume = volume_from_components(dimensions, components, &to_bytes(values, range))?;
+    if let Some(format) = scalar_type.precise_format(components) {
+        let voxel_count = volume.voxels.len();
+        let values = normalize(&values[..voxel_count * components], range);
+        let values = match format.channels() {
+            1 => values,
+            _ => values.chunks_exact(components).flat_map(components_to_color_f32).collect(),
+        };
+        volume.precise = Some(PreciseVoxels { format, values });
+    }
+    Ok(volume)
+}
+
+/// Builds a volume from 8-bit values where the components of each voxel are interleaved and x changes fastest.
+/// Volumes with a single component are scalar volumes.
+pub fn volume_from_components(dimensions: UVec3, components: usize, values: &[u8]) -> Result<VolumeData> {
+    let voxel_count = dimensions.x as usize * dimensions.y as usize * dimensions.z as usize;
+    if components == 0 || values.len() < voxel_count * components {
+        bail!("Expected {} values for a volume of size {} with {} components, found {}", voxel_count * components, dimensions, components, values.len());
+    }
+
+    let mut volume = VolumeData::new(dimensions);
+    for (voxel, components) in volume.voxels.iter_mut().zip(values.chunks_exact(components)) {
+        voxel.set_color(components_to_color(components));
+    }
+    volume.scalar = components == 1;
+    Ok(volume)
+}
+
+/// Voxels of a Voxel Grid as they are written by the NRRD and VTK exporters, little endian with x changing fastest
+pub struct ExportedVoxels {
+    pub scalar_type: ScalarType,
+    /// Components per voxel, 1 for scalar and grayscale volumes, 4 for RGBA colors
+    pub components: usize,
+    pub bytes: Vec<u8>,
+}
+
+impl ExportedVoxels {
+    /// Takes the voxels at the precision of the grid: 16-bit scalars stay `u16`, other precise volumes become `f32` between 0 and 1,
+    /// and 8-bit volumes stay `u8`, where grayscale colors are written as a single component.
+    pub fn new(grid: &VoxelGrid) -> Result<Self> {
+        let voxels = grid.voxels()?;
+        let Some(precise) = grid.precise_voxels() else {
+            let grayscale = voxels.iter().all(|voxel| voxel.color.iter().all(|value| *value == voxel.color[0]));
+            let bytes = if grayscale {
+                voxels.iter().map(|voxel| voxel.color[0]).collect()
+            } else {
+                bytemuck::cast_slice(voxels).to_vec()
+            };
+            return Ok(Self { scalar_type: ScalarType::U8, components: if grayscale { 1 } else { 4 }, bytes });
+        };
+
+        let components = precise.format.channels();
+        let (scalar_type, bytes) = match precise.format {
+            VoxelFormat::R16Unorm => (ScalarType::U16, precise.values.iter().flat_map(|value| ((value.clamp(0.0, 1.0) * 65535.0).round() as u16).to_le_bytes()).collect()),
+            _ => (ScalarType::F32, precise.values.iter().flat_map(|value| value.to_le_bytes()).collect()),
+        };
+        Ok(Self { scalar_type, components, bytes })
+    }
+}
@@ -0,0 +1,142 @@
+use std::borrow::Cow;
+
+use anyhow::Result;
+use glam::{UVec3, Vec3};
+
+use crate::voxel::{format::VoxelFormat, voxel::Voxel};
+
+/// Volumes whose voxels take more bytes than this are streamed slab by slab instead of being read at once
+pub const STREAMING_THRESHOLD: u64 = 1 << 30;
+
+/// Upper bound for the bytes of voxels in a single slab
+pub const SLAB_BYTES: u64 = 64 << 20;
+
+/// Voxels of consecutive z slices of a streamed volume
+#[derive(Debug, Clone)]
+pub enum Slab {
+    /// 8-bit RGBA colors
+    Colors(Vec<Voxel>),
+    /// A single density per voxel, normalized to 0..1
+    Scalars(Vec<f32>),
+}
+
+impl Slab {
+    pub fn voxel_count(&self) -> usize {
+        match self {
+            Self::Colors(voxels) => voxels.len(),
+            Self::Scalars(values) => values.len(),
+        }
+    }
+
+    /// Values of the voxels normalized to 0..1, `channels` per voxel. Colors keep their alpha as single channel.
+    pub fn values(&self, channels: usize) -> Vec<f32> {
+        match (self, channels) {
+            (Self::Colors(voxels), 1) => voxels.iter().map(|voxel| voxel.color[3] as f32 / 255.0).collect(),
+            (Self::Colors(voxels), _) => bytemuck::cast_slice::<Voxel, u8>(voxels).iter().map(|value| *value as f32 / 255.0).collect(),
+            (Self::Scalars(values), 1) => values.clone(),
+            (Self::Scalars(values), _) => values.iter().flat_map(|value| [*value; 4]).collect(),
+        }
+    }
+
+    /// Encodes the voxels in a texture format
+    pub fn encode(&self, format: VoxelFormat) -> Cow<'_, [u8]> {
+        match self {
+            Self::Colors(voxels) if format == VoxelFormat::Rgba8 => Cow::Borrowed(bytemuck::cast_slice(voxels)),
+            _ => Cow::Owned(format.encode(&self.values(format.channels()))),
+        }
+    }
+}
+
+/// Receives a volume slab by slab while it is read, so that the whole volume never has to be kept in memory
+pub trait SlabSink {
+    /// Called once with the size of the volume and the format it is meant to be stored in before the first slab.
+    /// Single channel formats receive [`Slab::Scalars`], all others [`Slab::Colors`].
+    fn start(&mut self, dimensions: UVec3, spacing: Vec3, format: VoxelFormat) -> Result<()>;
+
+    /// Receives the voxels of consecutive z slices, starting with slice `z`
+    fn write_slab(&mut self, z: u32, slab: Slab) -> Result<()>;
+}
+
+/// Amount of z slices that make up a slab of a volume with the given size
+pub fn slab_depth(dimensions: UVec3) -> u32 {
+    let slice_bytes = dimensions.x as u64 * dimensions.y as u64 * std::mem::size_of::<Voxel>() as u64;
+    (SLAB_BYTES / slice_bytes.max(1)).clamp(1, dimensions.z.max(1) as u64) as u32
+}
+
+/// Averages blocks of `factor`³ voxels while the slices of a streamed volume arrive one after another,
+/// so that a volume fits into a texture with at most `max_dimension` voxels per side
+pub struct SliceDownsampler {
+    factor: u32,
+    dimensions: UVec3,
+    output_dimensions: UVec3,
+    /// Sums of the channels of every voxel of the output slice that is being filled
+    sums: Vec<f32>,
+    summed_slices: u32,
+    slices_read: u32,
+}
+
+impl SliceDownsampler {
+    pub fn new(dimensions: UVec3, max_dimension: u32) -> Self {
+        let factor = dimensions.max_element().div_ceil(max_dimension.max(1)).max(1);
+        let output_dimensions = (dimensions + factor - 1) / factor;
+        Self {
+            factor,
+            dimensions,
+            output_dimensions,
+            sums: vec![],
+            summed_slices: 0,
+            slices_read: 0,
+        }
+    }
+
+    pub fn factor(&self) -> u32 {
+        self.factor
+    }
+
+    pub fn output_dimensions(&self) -> UVec3 {
+        self.output_dimensions
+    }
+
+    /// Adds the voxels of consecutive slices and returns the output slices they completed
+    pub fn add_slab(&mut self, slab: &Slab) -> Slab {
+        match slab {
+            Slab::Colors(voxels) => {
+                let values: Vec<f32> = bytemuck::cast_slice::<Voxel, u8>(voxels).iter().map(|value| *value as f32).collect();
+                let averages = self.add_values(&values, 4);
+                Slab::Colors(averages.chunks_exact(4).map(|color| Voxel { color: std::array::from_fn(|channel| color[channel].round() as u8) }).collect())
+            },
+            Slab::Scalars(values) => Slab::Scalars(self.add_values(values, 1)),
+        }
+    }
+
+    fn add_values(&mut self, values: &[f32], channels: usize) -> Vec<f32> {
+        let [width, height, depth] = self.dimensions.to_array();
+        let output_width = self.output_dimensions.x;
+        self.sums.resize(output_width as usize * self.output_dimensions.y as usize * channels, 0.0);
+        let mut output = vec![];
+        for slice in values.chunks_exact(width as usize * height as usize * channels) {
+            for (i, voxel) in slice.chunks_exact(channels).enumerate() {
+                let (x, y) = (i as u32 % width, i as u32 / width);
+                let output_index = (x / self.factor + output_width * (y / self.factor)) as usize;
+                for (sum, value) in self.sums[output_index * channels..(output_index + 1) * channels].iter_mut().zip(voxel) {
+                    *sum += value;
+                }
+            }
+            self.summed_slices += 1;
+            self.slices_read += 1;
+
+            if self.summed_slices == self.factor || self.slices_read == depth {
+                // Blocks at the far edges of the volume may hold fewer voxels
+                let block_len = |output: u32, len: u32| self.factor.min(len - output * self.factor);
+                for (i, sums) in self.sums.chunks_exact_mut(channels).enumerate() {
+                    let (x, y) = (i as u32 % output_width, i as u32 / output_width);
+                    let count = (block_len(x, width) * block_len(y, height) * self.summed_slices) as f32;
+                    output.extend(sums.iter().map(|sum| sum / count));
+                    sums.fill(0.0);
+                }
+                self.summed_slices = 0;
+            }
+        }
+        output
+    }
+}
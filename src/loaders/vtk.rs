@@ -0,0 +1,708 @@
+use std::{collections::HashMap, fs, io::{Read, Write}, path::Path};
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use flate2::read::ZlibDecoder;
+use glam::{UVec3, Vec3};
+
+use crate::voxel::{grid::VoxelGrid, volume::VolumeData};
+
+use super::{progress::LoadProgress, scalar::{data_range, decode_scalars, normalization_range, volume_from_values, ByteOrder, ExportedVoxels, ScalarType}};
+
+/// A point-data array of a VTK image
+#[derive(Debug, Clone)]
+pub struct VtkArray {
+    pub name: String,
+    pub scalar_type: ScalarType,
+    pub components: usize,
+    /// Values of all points with their components interleaved and x changing fastest
+    pub values: Vec<f32>,
+}
+
+/// Regular grid of a `.vti` file or a legacy STRUCTURED_POINTS dataset together with its point-data arrays
+#[derive(Debug, Clone)]
+pub struct VtkImage {
+    pub dimensions: UVec3,
+    /// Position of the first point in world space
+    pub origin: Vec3,
+    pub spacing: Vec3,
+    /// World space direction of each axis, if the file contains a direction matrix
+    pub directions: Option<[Vec3; 3]>,
+    pub arrays: Vec<VtkArray>,
+    /// Index of the array marked as active scalars in the file
+    pub active_array: Option<usize>,
+}
+
+impl VtkArray {
+    /// Builds a volume from the array.
+    /// 8-bit arrays with several components are treated as colors, other multi-component arrays as vectors of which the magnitude is shown.
+    fn to_volume(&self, dimensions: UVec3) -> Result<VolumeData> {
+        let point_count = dimensions.x as usize * dimensions.y as usize * dimensions.z as usize;
+        if self.values.len() < point_count * self.components {
+            bail!("Array '{}' contains {} values, but the grid needs {}", self.name, self.values.len(), point_count * self.components);
+        }
+
+        if self.components == 1 || (self.scalar_type == ScalarType::U8 && self.components <= 4) {
+            let values = &self.values[..point_count * self.components];
+            return volume_from_values(dimensions, self.components, values, normalization_range(self.scalar_type, values), self.scalar_type);
+        }
+
+        let magnitudes: Vec<f32> = self.values.chunks_exact(self.components)
+            .take(point_count)
+            .map(|vector| vector.iter().map(|component| component * component).sum::<f32>().sqrt())
+            .collect();
+        volume_from_values(dimensions, 1, &magnitudes, data_range(&magnitudes), ScalarType::F32)
+    }
+}
+
+impl VtkImage {
+    /// Builds a volume from one of the point-data arrays
+    pub fn to_volume(&self, array: usize) -> Result<VolumeData> {
+        let array = self.arrays.get(array).ok_or_else(|| anyhow!("VTK image has no point-data array {}", array))?;
+        let mut volume = array.to_volume(self.dimensions)?;
+        volume.spacing = self.spacing.abs();
+        volume.origin = self.origin;
+        if let Some(directions) = self.directions {
+            // Negative spacings flip their axis
+            volume.reorient([0, 1, 2].map(|axis| directions[axis] * self.spacing[axis].signum()));
+        } else if self.spacing.min_element() < 0.0 {
+            volume.reorient([Vec3::X * self.spacing.x.signum(), Vec3::Y * self.spacing.y.signum(), Vec3::Z * self.spacing.z.signum()]);
+        }
+        Ok(volume)
+    }
+
+    /// Index of the array that is shown by default: the active scalars or the first array
+    pub fn default_array(&self) -> usize {
+        self.active_array.unwrap_or(0)
+    }
+}
+
+/// Maps the type names of XML and legacy VTK files to scalar types
+fn parse_scalar_type(value: &str) -> Result<ScalarType> {
+    let scalar_type = match value.to_ascii_lowercase().as_str() {
+        "int8" | "char" | "signed_char" => ScalarType::I8,
+        "uint8" | "unsigned_char" => ScalarType::U8,
+        "int16" | "short" => ScalarType::I16,
+        "uint16" | "unsigned_short" => ScalarType::U16,
+        "int32" | "int" => ScalarType::I32,
+        "uint32" | "unsigned_int" => ScalarType::U32,
+        "int64" | "long" | "vtkidtype" | "vtktypeint64" => ScalarType::I64,
+        "uint64" | "unsigned_long" | "vtktypeuint64" => ScalarType::U64,
+        "float32" | "float" => ScalarType::F32,
+        "float64" | "double" => ScalarType::F64,
+        _ => bail!("Unsupported VTK data type '{}'", value),
+    };
+    Ok(scalar_type)
+}
+
+/// Reads a `.vti` or legacy `.vtk` file, choosing the parser by the file's extension
+pub fn read_image(path: &str, progress: &LoadProgress) -> Result<VtkImage> {
+    let bytes = progress.read_file(path).with_context(|| format!("Could not open VTK file '{}'", path))?;
+    let is_xml = Path::new(path).extension().is_some_and(|extension| extension.eq_ignore_ascii_case("vti"));
+    let image = if is_xml { parse_xml_image(&bytes) } else { parse_legacy_image(&bytes) };
+    let image = image.with_context(|| format!("Could not parse VTK file '{}'", path))?;
+    if image.arrays.is_empty() {
+        bail!("VTK file '{}' contains no point-data arrays", path);
+    }
+    Ok(image)
+}
+
+/// Reads the active point-data array of a VTK-File into a volume
+pub fn read_volume(path: &str, progress: &LoadProgress) -> Result<VolumeData> {
+    let image = read_image(path, progress)?;
+    image.to_volume(image.default_array())
+}
+
+/// Start tag of an XML element with its attributes
+struct XmlTag<'a> {
+    name: &'a str,
+    attributes: HashMap<&'a str, &'a str>,
+    closing: bool,
+    self_closing: bool,
+    /// Offset of the first character after the tag
+    end: usize,
+}
+
+/// Finds the next tag at or after `from`, skipping comments and declarations
+fn next_tag(text: &str, from: usize) -> Option<XmlTag<'_>> {
+    let mut start = from;
+    loop {
+        start += text[start..].find('<')?;
+        if text[start..].starts_with("<!--") {
+            start += text[start..].find("-->")? + 3;
+            continue;
+        }
+        if text[start..].starts_with("<?") || text[start..].starts_with("<!") {
+            start += text[start..].find('>')? + 1;
+            continue;
+        }
+        break;
+    }
+
+    let end = start + text[start..].find('>')?;
+    let inner = &text[start + 1..end];
+    let closing = inner.starts_with('/');
+    let self_closing = inner.ends_with('/');
+    let inner = inner.trim_start_matches('/').trim_end_matches('/');
+    let name_end = inner.find(char::is_whitespace).unwrap_or(inner.len());
+
+    let mut attributes = HashMap::new();
+    let mut rest = &inner[name_end..];
+    while let Some(equals) = rest.find('=') {
+        let key = rest[..equals].trim();
+        let value_start = rest[equals + 1..].trim_start();
+        let Some(quote) = value_start.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            break;
+        };
+        let Some(value_end) = value_start[1..].find(quote) else {
+            break;
+        };
+        attributes.insert(key, &value_start[1..value_end + 1]);
+        rest = &value_start[value_end + 2..];
+    }
+
+    Some(XmlTag { name: &inner[..name_end], attributes, closing, self_closing, end: end + 1 })
+}
+
+/// Parses whitespace separated floats of an attribute such as `Origin`
+fn parse_floats(value: &str) -> Result<Vec<f32>> {
+    value.split_whitespace()
+        .map(|v| v.parse::<f32>().with_context(|| format!("Could not parse VTK value '{}'", v)))
+        .collect()
+}
+
+/// Encoding settings of the binary data blocks of an XML file
+struct BinaryLayout {
+    byte_order: ByteOrder,
+    /// Size of the integers in the block headers
+    header_size: usize,
+    compressed: bool,
+}
+
+impl BinaryLayout {
+    fn read_header_value(&self, bytes: &[u8], index: usize) -> Result<usize> {
+        let start = index * self.header_size;
+        let bytes = bytes.get(start..start + self.header_size).ok_or_else(|| anyhow!("Binary block header is truncated"))?;
+        let value = match self.header_size {
+            4 => ScalarType::U32.decode(bytes, self.byte_order),
+            _ => ScalarType::U64.decode(bytes, self.byte_order),
+        };
+        Ok(value as usize)
+    }
+
+    /// Length of the block header in bytes, which depends on the amount of compressed blocks
+    fn header_len(&self, bytes: &[u8]) -> Result<usize> {
+        if self.compressed {
+            Ok(self.header_size * (3 + self.read_header_value(bytes, 0)?))
+        } else {
+            Ok(self.header_size)
+        }
+    }
+
+    /// Reads the data of a block given as raw bytes, starting with its header
+    fn read_raw(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        let header_len = self.header_len(bytes)?;
+        self.read_data(&bytes[..header_len.min(bytes.len())], &bytes[header_len.min(bytes.len())..])
+    }
+
+    /// Reads the data of a base64 encoded block.
+    /// Compressed blocks encode their header separately from the data.
+    fn read_base64(&self, text: &str) -> Result<Vec<u8>> {
+        let text: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+        if !self.compressed {
+            let bytes = STANDARD.decode(&text).context("Could not decode base64 data")?;
+            return self.read_raw(&bytes);
+        }
+
+        let first_chars = base64_len(self.header_size).min(text.len());
+        let first = STANDARD.decode(&text[..first_chars]).context("Could not decode base64 block header")?;
+        let header_chars = base64_len(self.header_len(&first)?).min(text.len());
+        let header = STANDARD.decode(&text[..header_chars]).context("Could not decode base64 block header")?;
+        let data = STANDARD.decode(&text[header_chars..]).context("Could not decode base64 data")?;
+        self.read_data(&header, &data)
+    }
+
+    fn read_data(&self, header: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+        if !self.compressed {
+            let len = self.read_header_value(header, 0)?;
+            return data.get(..len).map(|data| data.to_vec()).ok_or_else(|| anyhow!("Binary block is truncated"));
+        }
+
+        let block_count = self.read_header_value(header, 0)?;
+        let mut decompressed = vec![];
+        let mut offset = 0;
+        for block in 0..block_count {
+            let compressed_len = self.read_header_value(header, 3 + block)?;
+            let compressed = data.get(offset..offset + compressed_len).ok_or_else(|| anyhow!("Compressed block {} is truncated", block))?;
+            ZlibDecoder::new(compressed).read_to_end(&mut decompressed).context("Could not decompress zlib block")?;
+            offset += compressed_len;
+        }
+        Ok(decompressed)
+    }
+}
+
+/// Amount of base64 characters that encode the given amount of bytes
+fn base64_len(bytes: usize) -> usize {
+    bytes.div_ceil(3) * 4
+}
+
+/// Parses a `.vti` file with ascii, inline binary or appended raw/base64 data arrays
+fn parse_xml_image(bytes: &[u8]) -> Result<VtkImage> {
+    // Appended raw data is binary, so only the part before it is treated as text
+    let appended_tag = find_bytes(bytes, b"<AppendedData");
+    let text_end = appended_tag.unwrap_or(bytes.len());
+    let text = std::str::from_utf8(&bytes[..text_end]).context("VTK XML file is not valid text")?;
+
+    let mut layout = BinaryLayout { byte_order: ByteOrder::LittleEndian, header_size: 4, compressed: false };
+    let mut extent: Option<Vec<i64>> = None;
+    let mut origin = Vec3::ZERO;
+    let mut spacing = Vec3::ONE;
+    let mut directions = None;
+    let mut active_scalars = None;
+    let mut in_point_data = false;
+    // Arrays are decoded once the appended data is known
+    let mut array_tags = vec![];
+
+    let mut position = 0;
+    while let Some(tag) = next_tag(text, position) {
+        position = tag.end;
+        match (tag.name, tag.closing) {
+            ("VTKFile", false) => {
+                if let Some(file_type) = tag.attributes.get("type").filter(|file_type| **file_type != "ImageData") {
+                    bail!("Only VTK ImageData files are supported, found '{}'", file_type);
+                }
+                if tag.attributes.get("byte_order") == Some(&"BigEndian") {
+                    layout.byte_order = ByteOrder::BigEndian;
+                }
+                if tag.attributes.get("header_type") == Some(&"UInt64") {
+                    layout.header_size = 8;
+                }
+                if let Some(compressor) = tag.attributes.get("compressor").filter(|compressor| !compressor.is_empty()) {
+                    if *compressor != "vtkZLibDataCompressor" {
+                        bail!("Unsupported VTK compressor '{}', only zlib is supported", compressor);
+                    }
+                    layout.compressed = true;
+                }
+            },
+            ("ImageData", false) => {
+                if let Some(value) = tag.attributes.get("WholeExtent") {
+                    extent = Some(value.split_whitespace().map(|v| v.parse::<i64>()).collect::<std::result::Result<_, _>>()?);
+                }
+                if let Some(value) = tag.attributes.get("Origin") {
+                    origin = Vec3::from_slice(&parse_floats(value)?);
+                }
+                if let Some(value) = tag.attributes.get("Spacing") {
+                    spacing = Vec3::from_slice(&parse_floats(value)?);
+                }
+                if let Some(value) = tag.attributes.get("Direction") {
+                    // The direction matrix is stored row by row, its columns are the directions of the axes
+                    let matrix = parse_floats(value)?;
+                    if matrix.len() == 9 {
+                        directions = Some([0, 1, 2].map(|axis| Vec3::new(matrix[axis], matrix[3 + axis], matrix[6 + axis])));
+                    }
+                }
+            },
+            ("Piece", false) => {
+                if let Some(value) = tag.attributes.get("Extent") {
+                    extent = Some(value.split_whitespace().map(|v| v.parse::<i64>()).collect::<std::result::Result<_, _>>()?);
+                }
+            },
+            ("PointData", false) => {
+                in_point_data = !tag.self_closing;
+                active_scalars = tag.attributes.get("Scalars").or(tag.attributes.get("Vectors")).map(|name| name.to_string());
+            },
+            ("PointData", true) => in_point_data = false,
+            ("DataArray", false) if in_point_data => {
+                let content = if tag.self_closing {
+                    ""
+                } else {
+                    let content_end = text[tag.end..].find("</DataArray").map_or(text.len(), |i| tag.end + i);
+                    position = content_end;
+                    &text[tag.end..content_end]
+                };
+                array_tags.push((tag.attributes, content));
+            },
+            _ => continue
+        }
+    }
+
+    let extent = extent.filter(|extent| extent.len() == 6).ok_or_else(|| anyhow!("VTK image has no valid extent"))?;
+    let dimensions = UVec3::new(
+        (extent[1] - extent[0] + 1) as u32,
+        (extent[3] - extent[2] + 1) as u32,
+        (extent[5] - extent[4] + 1) as u32,
+    );
+
+    // The appended data starts after the underscore that follows its start tag
+    let appended = match appended_tag {
+        Some(start) => {
+            let header_end = start + find_bytes(&bytes[start..], b">").ok_or_else(|| anyhow!("Unterminated AppendedData tag"))?;
+            let header = std::str::from_utf8(&bytes[start..=header_end]).context("AppendedData tag is not valid text")?;
+            let encoding = next_tag(header, 0).and_then(|tag| tag.attributes.get("encoding").map(|encoding| encoding.to_string()));
+            let data_start = header_end + 1 + find_bytes(&bytes[header_end + 1..], b"_").ok_or_else(|| anyhow!("AppendedData is missing its '_' marker"))? + 1;
+            Some((encoding.unwrap_or("raw".to_string()), &bytes[data_start..]))
+        },
+        None => None,
+    };
+
+    let mut arrays = vec![];
+    for (attributes, content) in array_tags {
+        let name = attributes.get("Name").unwrap_or(&"").to_string();
+        let scalar_type = parse_scalar_type(attributes.get("type").ok_or_else(|| anyhow!("DataArray '{}' has no type", name))?)?;
+        let components = attributes.get("NumberOfComponents").map_or(Ok(1), |value| value.parse::<usize>())?;
+        let format = attributes.get("format").unwrap_or(&"ascii");
+
+        let values = match *format {
+            "ascii" => parse_floats(content)?,
+            "binary" => decode_scalars(&layout.read_base64(content)?, scalar_type, layout.byte_order),
+            "appended" => {
+                let (encoding, data) = appended.as_ref().ok_or_else(|| anyhow!("DataArray '{}' refers to missing appended data", name))?;
+                let offset = attributes.get("offset").map_or(Ok(0), |value| value.parse::<usize>())?;
+                let data = data.get(offset..).ok_or_else(|| anyhow!("Offset of DataArray '{}' lies behind the appended data", name))?;
+                let bytes = if encoding == "base64" {
+                    let len = data.iter().position(|byte| !(byte.is_ascii_alphanumeric() || matches!(byte, b'+' | b'/' | b'='))).unwrap_or(data.len());
+                    layout.read_base64(std::str::from_utf8(&data[..len])?)?
+                } else {
+                    layout.read_raw(data)?
+                };
+                decode_scalars(&bytes, scalar_type, layout.byte_order)
+            },
+            _ => bail!("Unsupported DataArray format '{}'", format),
+        };
+        arrays.push(VtkArray { name, scalar_type, components, values });
+    }
+
+    let active_array = active_scalars.and_then(|active| arrays.iter().position(|array| array.name == active));
+    Ok(VtkImage { dimensions, origin, spacing, directions, arrays, active_array })
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Reads the tokens and binary blocks of a legacy VTK file
+struct LegacyReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    binary: bool,
+}
+
+impl<'a> LegacyReader<'a> {
+    fn line(&mut self) -> Result<&'a str> {
+        let start = self.position;
+        let end = self.bytes[start..].iter().position(|byte| *byte == b'\n').map_or(self.bytes.len(), |i| start + i);
+        self.position = (end + 1).min(self.bytes.len());
+        Ok(std::str::from_utf8(&self.bytes[start..end])?.trim())
+    }
+
+    fn token(&mut self) -> Option<&'a str> {
+        while self.position < self.bytes.len() && self.bytes[self.position].is_ascii_whitespace() {
+            self.position += 1;
+        }
+        let start = self.position;
+        while self.position < self.bytes.len() && !self.bytes[self.position].is_ascii_whitespace() {
+            self.position += 1;
+        }
+        if start == self.position {
+            return None;
+        }
+        std::str::from_utf8(&self.bytes[start..self.position]).ok()
+    }
+
+    fn peek_token(&mut self) -> Option<&'a str> {
+        let position = self.position;
+        let token = self.token();
+        self.position = position;
+        token
+    }
+
+    fn expect_token(&mut self) -> Result<&'a str> {
+        self.token().ok_or_else(|| anyhow!("Unexpected end of VTK file"))
+    }
+
+    fn parse<T: std::str::FromStr>(&mut self) -> Result<T> {
+        let token = self.expect_token()?;
+        token.parse::<T>().map_err(|_| anyhow!("Could not parse VTK value '{}'", token))
+    }
+
+    fn vec3(&mut self) -> Result<Vec3> {
+        Ok(Vec3::new(self.parse()?, self.parse()?, self.parse()?))
+    }
+
+    /// Reads `count` values, binary data is big endian and starts on the line after the array header
+    fn values(&mut self, count: usize, scalar_type: ScalarType) -> Result<Vec<f32>> {
+        if !self.binary {
+            return (0..count).map(|_| self.parse::<f32>()).collect();
+        }
+
+        self.line()?;
+        let len = count * scalar_type.size();
+        let bytes = self.bytes.get(self.position..self.position + len).ok_or_else(|| anyhow!("Binary VTK data is truncated"))?;
+        self.position += len;
+        Ok(decode_scalars(bytes, scalar_type, ByteOrder::BigEndian))
+    }
+}
+
+/// Parses a legacy VTK file holding a STRUCTURED_POINTS dataset
+fn parse_legacy_image(bytes: &[u8]) -> Result<VtkImage> {
+    let mut reader = LegacyReader { bytes, position: 0, binary: false };
+    let version = reader.line()?;
+    if !version.starts_with("# vtk DataFile") {
+        bail!("File does not start with a VTK legacy header, found '{}'", version);
+    }
+    reader.line()?;
+    reader.binary = match reader.line()?.to_ascii_uppercase().as_str() {
+        "ASCII" => false,
+        "BINARY" => true,
+        format => bail!("Unknown VTK file format '{}'", format),
+    };
+
+    let mut dimensions = None;
+    let mut origin = Vec3::ZERO;
+    let mut spacing = Vec3::ONE;
+    let mut arrays = vec![];
+    let mut active_array = None;
+    let mut in_point_data = false;
+    let mut count = 0;
+
+    while let Some(keyword) = reader.token() {
+        match keyword.to_ascii_uppercase().as_str() {
+            "DATASET" => {
+                let dataset = reader.expect_token()?;
+                if !dataset.eq_ignore_ascii_case("STRUCTURED_POINTS") {
+                    bail!("Only STRUCTURED_POINTS datasets are supported, found '{}'", dataset);
+                }
+            },
+            "DIMENSIONS" => dimensions = Some(UVec3::new(reader.parse()?, reader.parse()?, reader.parse()?)),
+            "ORIGIN" => origin = reader.vec3()?,
+            "SPACING" | "ASPECT_RATIO" => spacing = reader.vec3()?,
+            "POINT_DATA" => {
+                count = reader.parse()?;
+                in_point_data = true;
+            },
+            "CELL_DATA" => {
+                count = reader.parse()?;
+                in_point_data = false;
+            },
+            "SCALARS" => {
+                let name = reader.expect_token()?.to_string();
+                let scalar_type = parse_scalar_type(reader.expect_token()?)?;
+                let components = match reader.peek_token() {
+                    Some(token) if token.parse::<usize>().is_ok() => reader.parse()?,
+                    _ => 1,
+                };
+                if reader.peek_token().is_some_and(|token| token.eq_ignore_ascii_case("LOOKUP_TABLE")) {
+                    reader.token();
+                    reader.token();
+                }
+                let values = reader.values(count * components, scalar_type)?;
+                if in_point_data {
+                    active_array.get_or_insert(arrays.len());
+                    arrays.push(VtkArray { name, scalar_type, components, values });
+                }
+            },
+            "COLOR_SCALARS" => {
+                let name = reader.expect_token()?.to_string();
+                let components = reader.parse()?;
+                // Colors are floats between 0 and 1 in ascii files and bytes in binary files
+                let values = if reader.binary {
+                    reader.values(count * components, ScalarType::U8)?
+                } else {
+                    reader.values(count * components, ScalarType::F32)?.iter().map(|value| value * 255.0).collect()
+                };
+                if in_point_data {
+                    arrays.push(VtkArray { name, scalar_type: ScalarType::U8, components, values });
+                }
+            },
+            "VECTORS" | "NORMALS" | "TENSORS" | "TEXTURE_COORDINATES" => {
+                let name = reader.expect_token()?.to_string();
+                let components = match keyword.to_ascii_uppercase().as_str() {
+                    "TENSORS" => 9,
+                    "TEXTURE_COORDINATES" => reader.parse()?,
+                    _ => 3,
+                };
+                let scalar_type = parse_scalar_type(reader.expect_token()?)?;
+                let values = reader.values(count * components, scalar_type)?;
+                if in_point_data {
+                    arrays.push(VtkArray { name, scalar_type, components, values });
+                }
+            },
+            "FIELD" => {
+                reader.expect_token()?;
+                let array_count: usize = reader.parse()?;
+                for _ in 0..array_count {
+                    let name = reader.expect_token()?.to_string();
+                    let components = reader.parse()?;
+                    let tuples: usize = reader.parse()?;
+                    let scalar_type = parse_scalar_type(reader.expect_token()?)?;
+                    let values = reader.values(tuples * components, scalar_type)?;
+                    if in_point_data && tuples == count {
+                        arrays.push(VtkArray { name, scalar_type, components, values });
+                    }
+                }
+            },
+            "LOOKUP_TABLE" => {
+                reader.expect_token()?;
+                let size: usize = reader.parse()?;
+                let scalar_type = if reader.binary { ScalarType::U8 } else { ScalarType::F32 };
+                reader.values(size * 4, scalar_type)?;
+            },
+            "METADATA" => {
+                // Metadata blocks end with an empty line
+                reader.line()?;
+                while reader.position < bytes.len() && !reader.line()?.is_empty() {}
+            },
+            _ => bail!("Unexpected keyword '{}' in VTK file", keyword),
+        }
+    }
+
+    Ok(VtkImage {
+        dimensions: dimensions.ok_or_else(|| anyhow!("VTK file is missing the DIMENSIONS keyword"))?,
+        origin,
+        spacing,
+        directions: None,
+        arrays,
+        active_array,
+    })
+}
+
+/// Writes the voxels of a Voxel Grid into a `.vti` file with appended raw data at the precision of the grid.
+/// Scalar and grayscale volumes are written as a single `intensity` array, all others as an RGBA `color` array.
+pub fn write_voxel_grid(path: &str, grid: &VoxelGrid) -> Result<()> {
+    let ExportedVoxels { scalar_type, components, bytes: data } = ExportedVoxels::new(grid)?;
+    let name = if components == 1 { "intensity" } else { "color" };
+    let data_type = match scalar_type {
+        ScalarType::U16 => "UInt16",
+        ScalarType::F32 => "Float32",
+        _ => "UInt8",
+    };
+
+    let [x, y, z] = grid.dimensions.to_array();
+    let [sx, sy, sz] = grid.spacing.to_array();
+    let [ox, oy, oz] = grid.origin.to_array();
+    let extent = format!("0 {} 0 {} 0 {}", x - 1, y - 1, z - 1);
+    let header = format!(
+        "<?xml version=\"1.0\"?>\n\
+        <VTKFile type=\"ImageData\" version=\"1.0\" byte_order=\"LittleEndian\" header_type=\"UInt64\">\n\
+        \x20 <ImageData WholeExtent=\"{extent}\" Origin=\"{ox} {oy} {oz}\" Spacing=\"{sx} {sy} {sz}\" Direction=\"1 0 0 0 1 0 0 0 1\">\n\
+        \x20   <Piece Extent=\"{extent}\">\n\
+        \x20     <PointData Scalars=\"{name}\">\n\
+        \x20       <DataArray type=\"{data_type}\" Name=\"{name}\" NumberOfComponents=\"{components}\" format=\"appended\" offset=\"0\"/>\n\
+        \x20     </PointData>\n\
+        \x20     <CellData/>\n\
+        \x20   </Piece>\n\
+        \x20 </ImageData>\n\
+        \x20 <AppendedData encoding=\"raw\">\n_"
+    );
+
+    let mut file = fs::File::create(path).with_context(|| format!("Could not create VTK file '{}'", path))?;
+    file.write_all(header.as_bytes())?;
+    file.write_all(&(data.len() as u64).to_le_bytes())?;
+    file.write_all(&data)?;
+    file.write_all(b"\n  </AppendedData>\n</VTKFile>\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use super::*;
+
+    /// Encodes data like vtkZLibDataCompressor with blocks of `block_size` bytes and a header of `header_size` byte integers
+    fn compressed_base64(data: &[u8], block_size: usize, header_size: usize) -> String {
+        let blocks: Vec<Vec<u8>> = data.chunks(block_size).map(|block| {
+            let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+            encoder.write_all(block).unwrap();
+            encoder.finish().unwrap()
+        }).collect();
+        let last_size = data.len() - (blocks.len() - 1) * block_size;
+        let mut header = vec![];
+        for value in [blocks.len(), block_size, last_size].into_iter().chain(blocks.iter().map(|block| block.len())) {
+            header.extend_from_slice(&(value as u64).to_le_bytes()[..header_size]);
+        }
+        STANDARD.encode(header) + &STANDARD.encode(blocks.concat())
+    }
+
+    #[test]
+    fn reads_compressed_base64_blocks() {
+        let data: Vec<u8> = (0..100).collect();
+        for header_size in [4, 8] {
+            let layout = BinaryLayout { byte_order: ByteOrder::LittleEndian, header_size, compressed: true };
+            assert_eq!(layout.read_base64(&compressed_base64(&data, 32, header_size)).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn reads_uncompressed_blocks() {
+        let layout = BinaryLayout { byte_order: ByteOrder::BigEndian, header_size: 8, compressed: false };
+        let mut block = 3u64.to_be_bytes().to_vec();
+        block.extend_from_slice(&[7, 8, 9, 10]);
+        assert_eq!(layout.read_raw(&block).unwrap(), vec![7, 8, 9]);
+        assert_eq!(layout.read_base64(&STANDARD.encode(&block)).unwrap(), vec![7, 8, 9]);
+        assert!(layout.read_raw(&block[..9]).is_err());
+    }
+
+    #[test]
+    fn parses_xml_image() {
+        let values: Vec<u8> = [1.5f32, -2.0].iter().flat_map(|value| value.to_le_bytes()).collect();
+        let vti = format!(
+            "<?xml version=\"1.0\"?>\n\
+            <VTKFile type=\"ImageData\" byte_order=\"LittleEndian\" header_type=\"UInt64\" compressor=\"vtkZLibDataCompressor\">\n\
+            <!-- comment with <tags> -->\n\
+            <ImageData WholeExtent=\"0 1 0 0 0 0\" Origin=\"5 5 5\" Spacing=\"1 2 -3\">\n\
+            <Piece Extent=\"0 1 0 0 0 0\"><PointData Scalars=\"density\">\n\
+            <DataArray type=\"UInt8\" Name=\"labels\" format=\"ascii\">3 4</DataArray>\n\
+            <DataArray type=\"Float32\" Name=\"density\" format=\"binary\">\n  {}\n</DataArray>\n\
+            </PointData></Piece></ImageData></VTKFile>\n",
+            compressed_base64(&values, 32768, 8),
+        );
+        let image = parse_xml_image(vti.as_bytes()).unwrap();
+        assert_eq!(image.dimensions, UVec3::new(2, 1, 1));
+        assert_eq!(image.origin, Vec3::splat(5.0));
+        assert_eq!(image.spacing, Vec3::new(1.0, 2.0, -3.0));
+        assert_eq!(image.arrays.len(), 2);
+        assert_eq!(image.arrays[0].values, vec![3.0, 4.0]);
+        assert_eq!(image.arrays[1].values, vec![1.5, -2.0]);
+        assert_eq!(image.default_array(), 1);
+    }
+
+    #[test]
+    fn parses_appended_raw_data() {
+        let mut vti = b"<VTKFile type=\"ImageData\" header_type=\"UInt32\"><ImageData WholeExtent=\"0 0 0 0 0 1\"><Piece><PointData>\
+            <DataArray type=\"UInt16\" Name=\"a\" format=\"appended\" offset=\"0\"/></PointData></Piece></ImageData>\
+            <AppendedData encoding=\"raw\">\n _".to_vec();
+        vti.extend_from_slice(&4u32.to_le_bytes());
+        vti.extend_from_slice(&[0x01, 0x02, 0xff, 0x00]);
+        vti.extend_from_slice(b"\n</AppendedData></VTKFile>");
+        let image = parse_xml_image(&vti).unwrap();
+        assert_eq!(image.arrays[0].values, vec![513.0, 255.0]);
+        assert_eq!(image.default_array(), 0);
+    }
+
+    #[test]
+    fn parses_legacy_binary_image() {
+        let mut vtk = b"# vtk DataFile Version 3.0\ntitle\nBINARY\nDATASET STRUCTURED_POINTS\nDIMENSIONS 2 1 1\nORIGIN 1 2 3\nSPACING 1 1 2\nPOINT_DATA 2\nSCALARS values short 1\nLOOKUP_TABLE default\n".to_vec();
+        vtk.extend_from_slice(&[0x01, 0x02, 0xff, 0xfe]);
+        vtk.extend_from_slice(b"\n");
+        let image = parse_legacy_image(&vtk).unwrap();
+        assert_eq!(image.dimensions, UVec3::new(2, 1, 1));
+        assert_eq!(image.origin, Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(image.spacing, Vec3::new(1.0, 1.0, 2.0));
+        assert_eq!(image.arrays[0].scalar_type, ScalarType::I16);
+        assert_eq!(image.arrays[0].values, vec![258.0, -2.0]);
+
+        assert!(parse_legacy_image(b"# vtk DataFile Version 3.0\ntitle\nASCII\nDATASET POLYDATA\n").is_err());
+    }
+
+    #[test]
+    fn flipped_axes_move_the_origin() {
+        let array = VtkArray { name: "density".to_string(), scalar_type: ScalarType::U8, components: 1, values: vec![0.0, 0.0, 0.0, 255.0] };
+        let image = VtkImage { dimensions: UVec3::new(4, 1, 1), origin: Vec3::new(5.0, 0.0, 1.0), spacing: Vec3::new(-2.0, 1.0, 1.0), directions: None, arrays: vec![array], active_array: None };
+        let volume = image.to_volume(0).unwrap();
+        // The last point along x comes first after flipping
+        assert_eq!(volume.origin, Vec3::new(-1.0, 0.0, 1.0));
+        assert_eq!(volume.spacing, Vec3::new(2.0, 1.0, 1.0));
+        assert_eq!(volume.voxels[0].color[3], 255);
+    }
+}
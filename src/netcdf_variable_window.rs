@@ -0,0 +1,141 @@
+use anyhow::Result;
+use egui::Context;
+
+use crate::loaders::netcdf::{list_volume_variables, VariableInfo, VariableSelection};
+
+/// What the user chose to load in the window
+pub enum NetcdfVariableRequest {
+    /// A single volume
+    Volume(VariableSelection),
+    /// A time series along one of the extra dimensions
+    TimeSeries {
+        selection: VariableSelection,
+        time_dimension: usize,
+        frame_count: usize,
+    },
+}
+
+/// GUI window for choosing the variable, the slice of extra dimensions and the value range
+/// that is loaded from a NetCDF-File
+pub struct NetcdfVariableWindow {
+    path: String,
+    variables: Vec<VariableInfo>,
+    selected: usize,
+    slice_indices: Vec<usize>,
+    auto_range: bool,
+    value_range: (f32, f32),
+    time_dimension: Option<usize>,
+}
+
+impl NetcdfVariableWindow {
+    pub fn new(path: &str) -> Result<Self> {
+        let variables = list_volume_variables(path)?;
+        let mut window = Self {
+            path: path.to_string(),
+            variables,
+            selected: 0,
+            slice_indices: vec![],
+            auto_range: true,
+            value_range: (0.0, 1.0),
+            time_dimension: None,
+        };
+        window.select(0);
+        Ok(window)
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    fn select(&mut self, index: usize) {
+        self.selected = index;
+        let dimension_count = self.variables.get(index).map(|var| var.dimensions.len()).unwrap_or(0);
+        self.slice_indices = vec![0; dimension_count];
+
+        // Prefer a dimension named like a time axis for playback
+        let extra_dimensions = self.variables.get(index).map(|var| var.extra_dimensions()).unwrap_or_default();
+        self.time_dimension = extra_dimensions.iter()
+            .copied()
+            .find(|i| matches!(self.variables[index].dimensions[*i].name.to_ascii_lowercase().as_str(), "time" | "t" | "epoch"))
+            .or(extra_dimensions.first().copied());
+    }
+
+    /// Draws the window and returns the request once one of the load buttons is pressed
+    pub fn show(&mut self, ctx: &Context, open: &mut bool) -> Option<NetcdfVariableRequest> {
+        let mut request = None;
+
+        egui::Window::new("Open NetCDF Variable").open(open).show(ctx, |ui| {
+            ui.label(&self.path);
+            if self.variables.is_empty() {
+                ui.label("The file contains no variable with at least three dimensions");
+                return;
+            }
+
+            let mut selected = self.selected;
+            egui::ComboBox::from_label("Variable")
+                .selected_text(&self.variables[selected].name)
+                .show_ui(ui, |ui| {
+                    for (i, var) in self.variables.iter().enumerate() {
+                        ui.selectable_value(&mut selected, i, &var.name);
+                    }
+                });
+            if selected != self.selected {
+                self.select(selected);
+            }
+
+            let var = &self.variables[self.selected];
+            let dimensions = var.dimensions.iter()
+                .map(|dim| format!("{}: {}", dim.name, dim.len))
+                .collect::<Vec<String>>()
+                .join(", ");
+            ui.label(format!("Dimensions: ({})", dimensions));
+
+            // Extra dimensions such as time or ensemble members are sliced at a single index
+            for i in var.extra_dimensions() {
+                let dim = &var.dimensions[i];
+                let slider = egui::Slider::new(&mut self.slice_indices[i], 0..=dim.len.saturating_sub(1)).text(&dim.name);
+                ui.add(slider);
+            }
+
+            ui.checkbox(&mut self.auto_range, "Detect value range");
+            if !self.auto_range {
+                ui.horizontal(|ui| {
+                    ui.label("Min");
+                    ui.add(egui::DragValue::new(&mut self.value_range.0).speed(0.01));
+                    ui.label("Max");
+                    ui.add(egui::DragValue::new(&mut self.value_range.1).speed(0.01));
+                });
+            }
+
+            let variable_selection = VariableSelection {
+                variable: var.name.clone(),
+                slice_indices: self.slice_indices.clone(),
+                value_range: if self.auto_range { None } else { Some(self.value_range) },
+            };
+
+            if ui.button("Load").clicked() {
+                request = Some(NetcdfVariableRequest::Volume(variable_selection.clone()));
+            }
+
+            if let Some(time_dimension) = self.time_dimension {
+                ui.separator();
+                egui::ComboBox::from_label("Time dimension")
+                    .selected_text(&var.dimensions[time_dimension].name)
+                    .show_ui(ui, |ui| {
+                        for i in var.extra_dimensions() {
+                            ui.selectable_value(&mut self.time_dimension, Some(i), &var.dimensions[i].name);
+                        }
+                    });
+                if ui.button("Load as Time Series").clicked() {
+                    request = Some(NetcdfVariableRequest::TimeSeries {
+                        selection: variable_selection,
+                        time_dimension,
+                        frame_count: var.dimensions[time_dimension].len,
+                    });
+                }
+            }
+        });
+
+        request
+    }
+}
@@ -0,0 +1,130 @@
+use egui::{ColorImage, Context, TextureHandle, TextureOptions};
+
+use crate::loaders::{raw::{read_slice, AxisOrder, RawLayout}, scalar::{ByteOrder, ScalarType}};
+
+/// Scalar types that can be chosen for raw files
+const SCALAR_TYPES: [(ScalarType, &str); 6] = [
+    (ScalarType::U8, "u8"),
+    (ScalarType::U16, "u16"),
+    (ScalarType::I16, "i16"),
+    (ScalarType::U32, "u32"),
+    (ScalarType::F32, "f32"),
+    (ScalarType::F64, "f64"),
+];
+
+/// GUI window for entering the layout of a raw file without a header
+pub struct RawImportWindow {
+    path: String,
+    file_len: u64,
+    pub layout: RawLayout,
+    /// Layout the preview was created for
+    preview_layout: Option<RawLayout>,
+    preview: Result<TextureHandle, String>,
+}
+
+impl RawImportWindow {
+    pub fn new(path: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            path: path.to_string(),
+            file_len: std::fs::metadata(path)?.len(),
+            layout: RawLayout::default(),
+            preview_layout: None,
+            preview: Err(String::new()),
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Reads the middle slice again once the layout changed
+    fn update_preview(&mut self, ctx: &Context) {
+        if self.preview_layout.as_ref() == Some(&self.layout) {
+            return;
+        }
+        self.preview_layout = Some(self.layout.clone());
+
+        let dimensions = self.layout.dimensions;
+        self.preview = read_slice(&self.path, &self.layout, dimensions.z / 2)
+            .map(|values| {
+                // Images are drawn top to bottom, so rows are flipped to show y pointing up
+                let pixels: Vec<u8> = values.chunks_exact(dimensions.x as usize).rev().flatten().copied().collect();
+                let image = ColorImage::from_gray([dimensions.x as usize, dimensions.y as usize], &pixels);
+                ctx.load_texture("raw_preview", image, TextureOptions::NEAREST)
+            })
+            .map_err(|err| err.to_string());
+    }
+
+    /// Draws the window and returns true once the load button is pressed
+    pub fn show(&mut self, ctx: &Context, open: &mut bool) -> bool {
+        let mut request = false;
+        self.update_preview(ctx);
+
+        egui::Window::new("Open RAW").open(open).show(ctx, |ui| {
+            ui.label(&self.path);
+
+            ui.horizontal(|ui| {
+                ui.label("Dimensions");
+                ui.add(egui::DragValue::new(&mut self.layout.dimensions.x).clamp_range(1..=u16::MAX as u32));
+                ui.add(egui::DragValue::new(&mut self.layout.dimensions.y).clamp_range(1..=u16::MAX as u32));
+                ui.add(egui::DragValue::new(&mut self.layout.dimensions.z).clamp_range(1..=u16::MAX as u32));
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Spacing");
+                ui.add(egui::DragValue::new(&mut self.layout.spacing.x).speed(0.01).clamp_range(0.001..=f32::MAX));
+                ui.add(egui::DragValue::new(&mut self.layout.spacing.y).speed(0.01).clamp_range(0.001..=f32::MAX));
+                ui.add(egui::DragValue::new(&mut self.layout.spacing.z).speed(0.01).clamp_range(0.001..=f32::MAX));
+            });
+
+            let scalar_name = SCALAR_TYPES.iter()
+                .find(|(scalar_type, _)| *scalar_type == self.layout.scalar_type)
+                .map_or("", |(_, name)| name);
+            egui::ComboBox::from_label("Scalar Type").selected_text(scalar_name).show_ui(ui, |ui| {
+                for (scalar_type, name) in SCALAR_TYPES {
+                    ui.selectable_value(&mut self.layout.scalar_type, scalar_type, name);
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut self.layout.byte_order, ByteOrder::LittleEndian, "Little Endian");
+                ui.radio_value(&mut self.layout.byte_order, ByteOrder::BigEndian, "Big Endian");
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Header Bytes");
+                ui.add(egui::DragValue::new(&mut self.layout.header_skip).clamp_range(0..=self.file_len));
+            });
+
+            egui::ComboBox::from_label("Axis Order (fastest first)").selected_text(self.layout.axis_order.name()).show_ui(ui, |ui| {
+                for axis_order in AxisOrder::ALL {
+                    ui.selectable_value(&mut self.layout.axis_order, axis_order, axis_order.name());
+                }
+            });
+
+            let size_check = self.layout.check_file_len(self.file_len);
+            match &size_check {
+                Ok(()) => ui.label(format!("File size matches the layout ({} bytes)", self.file_len)),
+                Err(err) => ui.colored_label(egui::Color32::RED, err.to_string()),
+            };
+
+            match &self.preview {
+                Ok(texture) => {
+                    // Keep the aspect ratio of the slice while fitting it into a fixed size
+                    let size = texture.size_vec2() * egui::vec2(self.layout.spacing.x, self.layout.spacing.y);
+                    let scale = 256.0 / size.max_elem();
+                    ui.image((texture.id(), size * scale));
+                },
+                Err(_) => {
+                    ui.label("No preview available");
+                }
+            }
+
+            if ui.add_enabled(size_check.is_ok(), egui::Button::new("Load")).clicked() {
+                request = true;
+            }
+        });
+
+        request
+    }
+}
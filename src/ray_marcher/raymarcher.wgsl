@@ -1,4 +1,5 @@
 // Camera information holding the camera's position and it's (inverse) view-projection matrix.
+// The w component of the position is the size of a pixel at a distance of 1 from the camera.
 struct CameraUniform {
     position: vec4<f32>,
     view_proj: mat4x4<f32>,
@@ -16,14 +17,22 @@ struct VoxelGrid {
     box_min: vec4<f32>,
     box_size: vec4<f32>,
     // Buffer is only needed for WGSL byte alignment and is not used further,
-    buffer: vec4<f32>
+    buffer: vec4<f32>,
+    // Amount of bricks along each axis, w is 1 if the volume is bricked
+    bricks: vec4<u32>,
+    // Amount of brick slots along each axis of the atlas, w is the amount of voxels per side of a brick's core
+    atlas_slots: vec4<u32>,
+    // x is the amount of channels of the volume texture
+    format: vec4<u32>,
+    // x is the forced mip level or -1 if the level is picked from the ray footprint, y is the highest mip level
+    lod: vec4<f32>
 }
 
-struct TransferFunctionColors {
-    color_a: vec4<f32>,
-    color_b: vec4<f32>,
-    color_c: vec4<f32>,
-    use_transfer_function: vec4<bool>
+// x is true if RGBA volumes are colored by the transfer function instead of their own colors, y is true if the 2D transfer function is used
+struct TransferFunctionSettings {
+    use_transfer_function: vec4<bool>,
+    // x is the gradient magnitude at the top of the gradient axis of the 2D transfer function
+    gradient_range: vec4<f32>
 }
 
 struct VertexInput {
@@ -77,10 +86,19 @@ fn vs_main(
 @group(0) @binding(0)
 var<uniform> camera: CameraUniform;
 
-// Obsolete
-// TODO: Remove this as it wastes a lot of VRAM and is already stored in the voxel texture
 @group(1) @binding(1)
-var<uniform> transform_function_colors: TransferFunctionColors;
+var<uniform> transfer_function_settings: TransferFunctionSettings;
+
+// Colors and opacities of the densities from 0 to 1, evenly spaced
+@group(1) @binding(2)
+var transfer_function_texture: texture_1d<f32>;
+
+// Colors and opacities over density (u) and normalized gradient magnitude (v)
+@group(1) @binding(3)
+var transfer_function_2d_texture: texture_2d<f32>;
+
+@group(1) @binding(4)
+var transfer_function_2d_sampler: sampler;
 
 // VoxelGrid information that tells us how large the volume is
 @group(1) @binding(0)
@@ -93,6 +111,10 @@ var voxel_texture: texture_3d<f32>;
 @group(2) @binding(1)
 var voxel_texture_sampler: sampler;
 
+// Atlas entry of every brick of a bricked volume: 0 if the brick isn't resident, 1 if it is empty, otherwise its slot plus 2
+@group(2) @binding(2)
+var<storage, read> page_table: array<u32>;
+
 const MAX_STEP_AMOUNT: i32 = 5000;
 
 @fragment
@@ -139,22 +161,30 @@ fn raymarch(ro: vec3<f32>, rd: vec3<f32>) -> RayMarchOutput {
     for(var i = 0; i < MAX_STEP_AMOUNT; i += 1) {
         // Calculate next position & then sample the scene at that point
         let p: vec3<f32> = ro + rd * dt;
-        let hitInfo = scene(p);
-
-        // Transfer Function Lerp
-        let color_ab_mix = mix(transform_function_colors.color_a.rgb, transform_function_colors.color_b.rgb, remap(hitInfo.alpha, 0.0, 0.5, 0.0, 1.0));
-        let color_bc_mix = mix(transform_function_colors.color_b.rgb, transform_function_colors.color_c.rgb, remap(hitInfo.alpha, 0.5, 1.0, 0.0, 1.0));
-        let transfer_function_color = mix(color_ab_mix, color_bc_mix, step(0.5, hitInfo.alpha));
+        // Coarser mip levels are sampled with larger steps
+        let lod = level_of_detail(dt, step_size);
+        let lod_step_size = step_size * exp2(lod);
+        let hitInfo = scene(p, lod);
+
+        // Scalar volumes have no colors of their own and are always colored by the transfer function,
+        // which maps the density to a color and an opacity
+        var color_src = hitInfo.color;
+        var density = hitInfo.alpha;
+        if transfer_function_settings.use_transfer_function[0] || voxel_grid.format.x == 1u {
+            var transfer_function_sample: vec4<f32>;
+            if transfer_function_settings.use_transfer_function[1] {
+                let rel_p = (p - voxel_grid.box_min.xyz) / voxel_grid.box_size.xyz;
+                transfer_function_sample = transfer_function_2d(hitInfo.alpha, gradient_magnitude(rel_p, lod));
+            } else {
+                transfer_function_sample = transfer_function(hitInfo.alpha);
+            }
+            color_src = transfer_function_sample.rgb;
+            density = transfer_function_sample.a;
+        }
 
         // Use front-to-back alpha blending
-        var alpha_src = 1.0 - exp(-hitInfo.alpha * step_size * voxel_grid.buffer[0]);
+        var alpha_src = 1.0 - exp(-density * lod_step_size * voxel_grid.buffer[0]);
         // var alpha_src = hitInfo.alpha / 2000.0;
-        var color_src = vec3<f32>(0.0);
-        if transform_function_colors.use_transfer_function[0] {
-            color_src = transfer_function_color;
-        } else {
-            color_src = hitInfo.color;
-        }
         color = color + (1.0 - alpha) * alpha_src * color_src;
         alpha = alpha + (1.0 - alpha) * alpha_src;
 
@@ -178,7 +208,7 @@ fn raymarch(ro: vec3<f32>, rd: vec3<f32>) -> RayMarchOutput {
         }
 
         // Increase distance for the next sampling step
-        dt += step_size;
+        dt += lod_step_size;
         output.steps = output.steps + 1;
 
         if dt >= aabb_intersection.t_max {
@@ -195,8 +225,19 @@ fn raymarch(ro: vec3<f32>, rd: vec3<f32>) -> RayMarchOutput {
     return output;
 }
 
-// Samples the scene at a specific point in space
-fn scene(p: vec3<f32>) -> HitInfo {
+// Picks the mip level whose voxels are about as large as the footprint of a pixel at distance t or the step size, whichever is larger
+fn level_of_detail(t: f32, step_size: f32) -> f32 {
+    if voxel_grid.lod.x >= 0.0 {
+        return voxel_grid.lod.x;
+    }
+    let voxel_sizes = voxel_grid.box_size.xyz / vec3<f32>(voxel_grid.dimensions.xyz);
+    let voxel_size = min(voxel_sizes.x, min(voxel_sizes.y, voxel_sizes.z));
+    let footprint = max(t * camera.position.w, step_size);
+    return clamp(log2(footprint / voxel_size), 0.0, voxel_grid.lod.y);
+}
+
+// Samples the scene at a specific point in space from mip level lod
+fn scene(p: vec3<f32>, lod: f32) -> HitInfo {
     var output: HitInfo = HitInfo();
     let box_min = voxel_grid.box_min.xyz;
     let box_max = voxel_grid.box_min.xyz + voxel_grid.box_size.xyz;
@@ -224,7 +265,7 @@ fn scene(p: vec3<f32>) -> HitInfo {
 
     // Get relative coordinates inside the box and sample the volume texture
     // let texture_coords = p_r / vec3<f32>(dimensions);
-    let sample_result = textureSample(voxel_texture, voxel_texture_sampler, rel_p);
+    let sample_result = sample_volume(rel_p, lod);
 
     // Get relative color relative to a 1x1x1 grid
     // var sample_result = vec3<f32>(rel_p);
@@ -237,6 +278,71 @@ fn scene(p: vec3<f32>) -> HitInfo {
     return output;
 }
 
+// Samples the volume at relative coordinates, scalar volumes use their single channel as density
+fn sample_volume(rel_p: vec3<f32>, lod: f32) -> vec4<f32> {
+    var sample_result: vec4<f32>;
+    if voxel_grid.bricks.w != 0u {
+        sample_result = sample_bricks(rel_p);
+    } else {
+        sample_result = textureSampleLevel(voxel_texture, voxel_texture_sampler, rel_p, lod);
+    }
+    if voxel_grid.format.x == 1u {
+        sample_result = vec4<f32>(sample_result.r);
+    }
+    return sample_result;
+}
+
+// Magnitude of the density gradient from central differences in density per voxel, normalized to the gradient axis of the 2D transfer function.
+// Coarser mip levels take the differences between neighbors of their own level.
+fn gradient_magnitude(rel_p: vec3<f32>, lod: f32) -> f32 {
+    let spacing = exp2(lod);
+    let offset = spacing / vec3<f32>(voxel_grid.dimensions.xyz);
+    let gradient = vec3<f32>(
+        sample_volume(rel_p + vec3<f32>(offset.x, 0.0, 0.0), lod).a - sample_volume(rel_p - vec3<f32>(offset.x, 0.0, 0.0), lod).a,
+        sample_volume(rel_p + vec3<f32>(0.0, offset.y, 0.0), lod).a - sample_volume(rel_p - vec3<f32>(0.0, offset.y, 0.0), lod).a,
+        sample_volume(rel_p + vec3<f32>(0.0, 0.0, offset.z), lod).a - sample_volume(rel_p - vec3<f32>(0.0, 0.0, offset.z), lod).a
+    ) / (2.0 * spacing);
+    return length(gradient) / transfer_function_settings.gradient_range.x;
+}
+
+// Samples a bricked volume at relative coordinates through the page table.
+// Bricks that are empty or haven't been streamed into the atlas yet are treated as transparent.
+fn sample_bricks(rel_p: vec3<f32>) -> vec4<f32> {
+    let core = f32(voxel_grid.atlas_slots.w);
+    let voxel_position = rel_p * vec3<f32>(voxel_grid.dimensions.xyz);
+    let brick = min(vec3<u32>(voxel_position / core), voxel_grid.bricks.xyz - vec3<u32>(1u));
+    let entry = page_table[brick.x + voxel_grid.bricks.x * (brick.y + voxel_grid.bricks.y * brick.z)];
+    if entry < 2u {
+        return vec4<f32>(0.0);
+    }
+
+    let slots = voxel_grid.atlas_slots.xyz;
+    let slot_index = entry - 2u;
+    let slot = vec3<u32>(slot_index % slots.x, (slot_index / slots.x) % slots.y, slot_index / (slots.x * slots.y));
+    // Bricks are stored with a border of one voxel around their core
+    let brick_size = core + 2.0;
+    let local_position = voxel_position - vec3<f32>(brick) * core + 1.0;
+    let atlas_coords = (vec3<f32>(slot) * brick_size + local_position) / (vec3<f32>(slots) * brick_size);
+    return textureSampleLevel(voxel_texture, voxel_texture_sampler, atlas_coords, 0.0);
+}
+
+// Interpolates linearly between the two entries of the transfer function texture around a density
+fn transfer_function(density: f32) -> vec4<f32> {
+    let size = textureDimensions(transfer_function_texture);
+    let position = clamp(density, 0.0, 1.0) * f32(size - 1u);
+    let index = min(u32(position), size - 1u);
+    let next = min(index + 1u, size - 1u);
+    return mix(textureLoad(transfer_function_texture, index, 0), textureLoad(transfer_function_texture, next, 0), fract(position));
+}
+
+// Samples the 2D transfer function, whose entries lie at evenly spaced densities and gradient magnitudes from 0 to 1
+fn transfer_function_2d(density: f32, gradient_magnitude: f32) -> vec4<f32> {
+    let size = vec2<f32>(textureDimensions(transfer_function_2d_texture));
+    let position = clamp(vec2<f32>(density, gradient_magnitude), vec2<f32>(0.0), vec2<f32>(1.0));
+    let coords = (position * (size - 1.0) + 0.5) / size;
+    return textureSampleLevel(transfer_function_2d_texture, transfer_function_2d_sampler, coords, 0.0);
+}
+
 fn remap(value: f32, min1: f32, max1: f32, min2: f32, max2: f32) -> f32 {
     return min2 + (value - min1) * (max2 - min2) / (max1 - min1);
 }
@@ -20,8 +20,12 @@ impl SphereScreenshotManager {
         }
     }
 
-    pub fn start_screenshotting(&mut self, csp: &mut CameraSphereController, camera: &mut Camera) {
+    /// Starts moving the camera around the sphere. The box of the volume is exported alongside the camera positions.
+    pub fn start_screenshotting(&mut self, csp: &mut CameraSphereController, camera: &mut Camera, box_min: Vec3, box_size: Vec3) {
         self.is_screenshotting = true;
+        self.screenshot_info.look_at = csp.origin.to_array();
+        self.screenshot_info.box_min = box_min.to_array();
+        self.screenshot_info.box_size = box_size.to_array();
         csp.current_index_x = 0;
         csp.current_index_y = 1;
         self.screenshot_info.positions.clear();
@@ -88,6 +92,8 @@ struct CameraPositions {
 #[derive(Serialize)]
 struct ScreenshotInformation {
     look_at: [f32;3],
+    box_min: [f32;3],
+    box_size: [f32;3],
     positions: Vec<CameraPositions>,
 
 }
@@ -96,6 +102,8 @@ impl ScreenshotInformation {
     pub fn new(size: usize, center: Vec3) -> Self {
         Self {
             look_at: center.to_array(),
+            box_min: [-0.5; 3],
+            box_size: [1.0; 3],
             positions: Vec::with_capacity(size)
         }
     }
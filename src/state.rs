@@ -1,11 +1,11 @@
-use std::{rc::Rc, time::Duration};
+use std::{path::Path, rc::Rc, sync::Arc, time::Duration};
 use egui::menu;
 use egui_wgpu::ScreenDescriptor;
 use glam::Vec3;
 use rfd::AsyncFileDialog;
 use wgpu::{util::DeviceExt, Color};
 use winit::{dpi::PhysicalSize, event::WindowEvent, window::Window};
-use crate::{camera::{Camera, CameraUniform}, camera_controller::CameraController, camera_sphere_controller::CameraSphereController, gui::EguiRenderer, ray_marcher::RayMarcher, screenshot::Screenshotter, sphere_screenshot_manager::SphereScreenshotManager};
+use crate::{camera::{Camera, CameraUniform}, camera_controller::CameraController, camera_sphere_controller::CameraSphereController, dicom_series_window::DicomSeriesWindow, gpu_options::GpuOptions, gui::EguiRenderer, image_stack_window::ImageStackWindow, loaders::{image_stack::SliceAxis, netcdf::NetcdfExportOptions, registry::LoaderRegistry}, ray_marcher::RayMarcher, raw_import_window::RawImportWindow, screenshot::Screenshotter, netcdf_variable_window::{NetcdfVariableRequest, NetcdfVariableWindow}, sphere_screenshot_manager::SphereScreenshotManager, time_series::{TimeSeries, TimeSeriesSource}, transfer_function_preset::{self, TransferFunctionPreset}, transfer_function_window::{TransferFunctionRequest, TransferFunctionWindow}, voxel::{format::PRECISION_FEATURES, grid::VoxelGrid, mips::{level_size, MipFilter}}, volume_load::{LoadResult, VolumeLoad}, vtk_array_window::VtkArrayWindow};
 
 /// Handles and stores the state of the application. 
 /// Additionally holds data needed for rendering, but this should be moved into it's own struct in the future.
@@ -26,6 +26,20 @@ pub struct State<'a> {
     egui_renderer: EguiRenderer,
     screenshotter: Screenshotter,
     sphere_screenshot_manager: SphereScreenshotManager,
+    netcdf_variable_window: Option<NetcdfVariableWindow>,
+    dicom_series_window: Option<DicomSeriesWindow>,
+    image_stack_window: Option<ImageStackWindow>,
+    raw_import_window: Option<RawImportWindow>,
+    vtk_array_window: Option<VtkArrayWindow>,
+    transfer_function_window: Option<TransferFunctionWindow>,
+    time_series: Option<TimeSeries>,
+    /// Volume that is currently read on a worker thread
+    volume_load: Option<VolumeLoad>,
+    /// Title and text of a message shown in a dialog until it is dismissed, e.g. the error of a failed load or export
+    dialog: Option<(&'static str, String)>,
+    /// Loaders that files opened from the menu, dropped onto the window or played as time series are read with
+    loaders: Arc<LoaderRegistry>,
+    netcdf_export_options: NetcdfExportOptions,
     frametime: Duration,
     should_screenshot: bool,
     free_move: bool,
@@ -34,39 +48,31 @@ pub struct State<'a> {
 impl<'a> State<'a> {
 
     /// Creates a new state and initializes a WebGPU Instance for the given window.
-    pub async fn new(window: &'a Window) -> State<'a> {
+    /// Fails if no adapter matches the options or the device can't be created.
+    pub async fn new(window: &'a Window, loaders: LoaderRegistry, gpu_options: &GpuOptions) -> anyhow::Result<State<'a>> {
         let size = window.inner_size();
 
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::PRIMARY,
+            backends: gpu_options.backends,
             ..Default::default()
         });
 
-        let surface = instance.create_surface(window).unwrap();
+        let surface = instance.create_surface(window)?;
+        let adapter = gpu_options.request_adapter(&instance, &surface).await?;
 
-        let adapter = instance.request_adapter(
-            &wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-
-            },
-        ).await.unwrap();
-
-        let mut limits = wgpu::Limits::default();
-        limits.max_buffer_size = 18446744073709551615;
-        // limits.max_storage_buffer_binding_size = 2147483648;
-        limits.max_texture_dimension_3d = 4096;
-        
+        // Request everything the adapter supports, large volumes need the largest 3D textures and buffers available
+        let limits = adapter.limits();
+        let info = adapter.get_info();
+        println!("Using {} ({:?}) with a 3D texture limit of {} and a buffer limit of {} bytes", info.name, info.backend, limits.max_texture_dimension_3d, limits.max_buffer_size);
 
         let (device, queue) = adapter.request_device(
             &wgpu::DeviceDescriptor {
-                required_features: wgpu::Features::empty(),
+                required_features: adapter.features() & PRECISION_FEATURES,
                 required_limits: limits,
                 label: None
             },
             None
-        ).await.unwrap();
+        ).await?;
 
         let surface_caps = surface.get_capabilities(&adapter);
 
@@ -101,7 +107,7 @@ impl<'a> State<'a> {
 
         surface.configure(&device, &config);
 
-        let mut camera = Camera::new(config.width as f32 / config.height as f32);
+        let mut camera = Camera::new(config.width, config.height);
         let camera_controller = CameraController::new(20.0);
 
         let mut camera_uniform = CameraUniform::new();
@@ -161,7 +167,7 @@ impl<'a> State<'a> {
 
         let sphere_screenshot_manager = SphereScreenshotManager::new(&camera_sphere_controller);
 
-        Self {
+        Ok(Self {
             window,
             surface,
             config,
@@ -178,10 +184,21 @@ impl<'a> State<'a> {
             egui_renderer,
             screenshotter,
             sphere_screenshot_manager,
+            netcdf_variable_window: None,
+            dicom_series_window: None,
+            image_stack_window: None,
+            raw_import_window: None,
+            vtk_array_window: None,
+            transfer_function_window: None,
+            volume_load: None,
+            dialog: None,
+            time_series: None,
+            loaders: Arc::new(loaders),
+            netcdf_export_options: NetcdfExportOptions::default(),
             should_screenshot: false,
             frametime: Duration::ZERO,
             free_move: true,
-        }
+        })
     }
 
     pub fn window(&self) -> &Window {
@@ -200,7 +217,7 @@ impl<'a> State<'a> {
             self.config.width = new_size.width;
             self.config.height = new_size.height;
             self.surface.configure(&self.device, &self.config);
-            self.camera.set_aspect_ratio(new_size.width as f32 / new_size.height as f32);
+            self.camera.set_viewport_size(new_size.width, new_size.height);
 
             // Screenshotter has to be recreated after resizing the window
             self.screenshotter = Screenshotter::new(&self.device, &self.config);
@@ -218,6 +235,10 @@ impl<'a> State<'a> {
 
                 return true;
             }
+            WindowEvent::DroppedFile(path) => {
+                self.open_volume(&path.to_string_lossy());
+                return true;
+            }
             _ => {}
         }
 
@@ -232,10 +253,32 @@ impl<'a> State<'a> {
         } else {
             self.should_screenshot = self.sphere_screenshot_manager.update_camera(&mut self.camera_sphere_controller,&mut self.camera);
         }
+        if let Some(result) = self.volume_load.as_mut().and_then(|volume_load| volume_load.poll(&self.device, &self.queue)) {
+            if let Some(volume_load) = self.volume_load.take() {
+                self.finish_load(volume_load, result);
+            }
+        }
+        if let Some(time_series) = &mut self.time_series {
+            // Mip levels are only rebuilt once playback pauses, every frame would rebuild them on the CPU
+            let result = match time_series.update(self.frametime) {
+                Some(frame) => self.ray_marcher.voxel_grid.set_voxels(&frame, time_series.playing, &self.queue),
+                None if !time_series.playing => {
+                    self.ray_marcher.voxel_grid.update_deferred_mips(&self.queue);
+                    Ok(())
+                },
+                None => Ok(()),
+            };
+            if let Err(err) = result {
+                self.dialog = Some(("Error", format!("{:#}", err)));
+                self.time_series = None;
+            }
+        }
         // self.camera.transform.look_to(Vec3::ONE * 16.0, Vec3::NEG_Y);
         // self.camera.look_dir = self.camera.transform.position - Vec3::ONE * 16.0;
         self.camera_uniform.update_view_proj(&mut self.camera);
         self.queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
+        let view_proj = self.camera.build_view_projection_matrix();
+        self.ray_marcher.voxel_grid.update_bricks(view_proj, self.camera.transform.position, &self.queue);
     }
 
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
@@ -255,6 +298,11 @@ impl<'a> State<'a> {
             pixels_per_point: self.window.scale_factor() as f32,
         };
 
+        // Time series are opened after the GUI is drawn, as they replace the whole volume
+        let mut time_series_source = None;
+        let mut open_path = None;
+        let mut new_load = None;
+
         self.egui_renderer.draw(
                 &self.device,
                 &self.queue,
@@ -267,33 +315,128 @@ impl<'a> State<'a> {
                     egui::TopBottomPanel::top("my_panel").show(&ctx, |ui| {
                         menu::bar(ui, |ui| {
                             ui.menu_button("File", |ui| {
-                                if ui.button("Open NetCDF").clicked() {
+                                if ui.button("Open…").clicked() {
+                                    let file_path = open_file_menu("Volumes", &self.loaders.dialog_extensions()).unwrap();
+                                    if let Some(file_path) = file_path {
+                                        open_path = Some(file_path);
+                                    }
+                                }
+
+                                if ui.button("Open NetCDF Variable").clicked() {
                                     let file_path = open_file_menu("NetCDF", &["nc"]).unwrap();
                                     if let Some(file_path) = file_path {
-                                        crate::loaders::netcdf::open_voxel_grid(&file_path, &mut self.ray_marcher.voxel_grid, &self.device, &self.queue).unwrap();
-                                        self.window.set_title(&file_path);
+                                        match NetcdfVariableWindow::new(&file_path) {
+                                            Ok(window) => self.netcdf_variable_window = Some(window),
+                                            Err(err) => self.dialog = Some(("Error", format!("{:#}", err))),
+                                        }
                                     }
                                 }
 
-                                if ui.button("Open DAT").clicked() {
-                                    let file_path = open_file_menu("DAT", &["dat"]).unwrap();
+                                if ui.button("Open RAW").clicked() {
+                                    let file_path = open_file_menu("Raw Volume", &["raw", "bin", "vol"]).unwrap();
                                     if let Some(file_path) = file_path {
-                                        crate::loaders::dat::open_voxel_grid(&file_path, &mut self.ray_marcher.voxel_grid, &self.device, &self.queue).unwrap();
-                                        self.window.set_title(&file_path);
+                                        match RawImportWindow::new(&file_path) {
+                                            Ok(window) => self.raw_import_window = Some(window),
+                                            Err(err) => self.dialog = Some(("Error", format!("{:#}", err))),
+                                        }
                                     }
+                                }
 
+                                if ui.button("Open DICOM Folder").clicked() {
+                                    let directory = open_folder_menu().unwrap();
+                                    if let Some(directory) = directory {
+                                        new_load = Some(VolumeLoad::scan_dicom(&directory));
+                                    }
+                                }
+
+                                if ui.button("Open Image Stack").clicked() {
+                                    let directory = open_folder_menu().unwrap();
+                                    if let Some(directory) = directory {
+                                        self.image_stack_window = Some(ImageStackWindow::new(&directory));
+                                    }
+                                }
+
+                                if ui.button("Open VTK").clicked() {
+                                    let file_path = open_file_menu("VTK", &["vti", "vtk"]).unwrap();
+                                    if let Some(file_path) = file_path {
+                                        new_load = Some(VolumeLoad::scan_vtk(&file_path));
+                                    }
+                                }
+
+                                if ui.button("Open Time Series Files").clicked() {
+                                    let file_paths = open_files_menu("Volumes", &self.loaders.dialog_extensions()).unwrap();
+                                    if !file_paths.is_empty() {
+                                        time_series_source = Some(TimeSeriesSource::Files(file_paths));
+                                    }
                                 }
 
                                 if ui.button("Export NetCDF").clicked() {
-                                    crate::loaders::netcdf::write_voxel_grid("test.nc", &self.ray_marcher.voxel_grid).unwrap();
+                                    let file_path = save_file_menu("NetCDF", &["nc"], "volume.nc").unwrap();
+                                    if let Some(file_path) = file_path {
+                                        match crate::loaders::netcdf::write_voxel_grid(&file_path, &self.ray_marcher.voxel_grid, &self.netcdf_export_options) {
+                                            Ok(()) => println!("Exported volume to {}", file_path),
+                                            Err(err) => self.dialog = Some(("Error", format!("{:#}", err))),
+                                        }
+                                    }
                                 }
+
+                                if ui.button("Export NRRD").clicked() {
+                                    let file_path = save_file_menu("NRRD", &["nrrd", "nhdr"], "volume.nrrd").unwrap();
+                                    if let Some(file_path) = file_path {
+                                        match crate::loaders::nrrd::write_voxel_grid(&file_path, &self.ray_marcher.voxel_grid) {
+                                            Ok(()) => println!("Exported volume to {}", file_path),
+                                            Err(err) => self.dialog = Some(("Error", format!("{:#}", err))),
+                                        }
+                                    }
+                                }
+
+                                if ui.button("Export VTI").clicked() {
+                                    let file_path = save_file_menu("VTK Image Data", &["vti"], "volume.vti").unwrap();
+                                    if let Some(file_path) = file_path {
+                                        match crate::loaders::vtk::write_voxel_grid(&file_path, &self.ray_marcher.voxel_grid) {
+                                            Ok(()) => println!("Exported volume to {}", file_path),
+                                            Err(err) => self.dialog = Some(("Error", format!("{:#}", err))),
+                                        }
+                                    }
+                                }
+
+                                if ui.button("Export CVOL").clicked() {
+                                    let file_path = save_file_menu("DiffDVR Volume", &["cvol"], "volume.cvol").unwrap();
+                                    if let Some(file_path) = file_path {
+                                        match crate::loaders::cvol::write_voxel_grid(&file_path, &self.ray_marcher.voxel_grid) {
+                                            Ok(()) => println!("Exported volume to {}", file_path),
+                                            Err(err) => self.dialog = Some(("Error", format!("{:#}", err))),
+                                        }
+                                    }
+                                }
+
+                                ui.menu_button("Export PNG Slices", |ui| {
+                                    for (label, axis) in [("Z Slices", SliceAxis::Z), ("Y Slices", SliceAxis::Y), ("X Slices", SliceAxis::X)] {
+                                        if ui.button(label).clicked() {
+                                            let directory = open_folder_menu().unwrap();
+                                            if let Some(directory) = directory {
+                                                match crate::loaders::image_stack::write_slices(&directory, &self.ray_marcher.voxel_grid, axis) {
+                                                    Ok(()) => println!("Exported slices to {}", directory),
+                                                    Err(err) => self.dialog = Some(("Error", format!("{:#}", err))),
+                                                }
+                                            }
+                                        }
+                                    }
+                                });
+
+                                ui.menu_button("NetCDF Export Settings", |ui| {
+                                    let slider = egui::Slider::new(&mut self.netcdf_export_options.deflate_level, 0..=9).text("Deflate Level (0 = off)");
+                                    ui.add(slider);
+                                    let slider = egui::Slider::new(&mut self.netcdf_export_options.chunk_size, 0..=256).text("Chunk Size (0 = contiguous)");
+                                    ui.add(slider);
+                                });
                             });
 
                             ui.menu_button("Compare", |ui| {
                                 if ui.button("Compare NetCDF to Ground-Truth").clicked() {
                                     let file_path = open_file_menu("NetCDF", &["nc"]).unwrap();
                                     if let Some(path) = file_path {
-                                        let color_function_active = self.ray_marcher.voxel_grid.transfer_function_colors.use_transfer_function_active();
+                                        let color_function_active = self.ray_marcher.voxel_grid.transfer_function_settings.use_transfer_function_active() || self.ray_marcher.voxel_grid.is_scalar();
                                         let result = crate::compare::netcdf::compare_to_netcdf_rmse(&path, &mut self.ray_marcher.voxel_grid, !color_function_active);
                                         println!("{:?}", result);
                                     }
@@ -303,11 +446,174 @@ impl<'a> State<'a> {
                         
                         });
 
+                    // Draw NetCDF variable selection
+                    if let Some(netcdf_variable_window) = &mut self.netcdf_variable_window {
+                        let mut open = true;
+                        let request = netcdf_variable_window.show(ctx, &mut open);
+                        let file_path = netcdf_variable_window.path().to_string();
+                        match request {
+                            Some(NetcdfVariableRequest::Volume(selection)) => {
+                                let title = format!("{} ({})", file_path, selection.variable);
+                                let path = file_path.clone();
+                                let mut volume_load = VolumeLoad::spawn(&title, Some(file_path), move |progress| crate::loaders::netcdf::read_variable(&path, &selection, progress));
+                                volume_load.restore_netcdf_settings = true;
+                                new_load = Some(volume_load);
+                                open = false;
+                            },
+                            Some(NetcdfVariableRequest::TimeSeries { selection, time_dimension, frame_count }) => {
+                                time_series_source = Some(TimeSeriesSource::NetcdfVariable { path: file_path, selection, time_dimension, frame_count });
+                                open = false;
+                            },
+                            None => {}
+                        }
+                        if !open {
+                            self.netcdf_variable_window = None;
+                        }
+                    }
+
+                    if let Some(transfer_function_window) = &mut self.transfer_function_window {
+                        let mut open = true;
+                        let grid = &mut self.ray_marcher.voxel_grid;
+                        match transfer_function_window.show(ctx, &mut open, grid) {
+                            Some(TransferFunctionRequest::Update) => grid.update_transfer_function_buffer(&self.queue),
+                            Some(TransferFunctionRequest::Load) => {
+                                if let Some(file_path) = open_file_menu("Transfer Functions", &["json", "xml"]).unwrap() {
+                                    match transfer_function_preset::load(Path::new(&file_path)) {
+                                        Ok(preset) => {
+                                            preset.apply(grid);
+                                            grid.update_transfer_function_buffer(&self.queue);
+                                            transfer_function_window.clear_selection();
+                                        },
+                                        Err(err) => self.dialog = Some(("Error", format!("{:#}", err))),
+                                    }
+                                }
+                            },
+                            Some(TransferFunctionRequest::Save(format)) => {
+                                let file_name = format!("transfer_function.{}", format.extension());
+                                if let Some(file_path) = save_file_menu(format.name(), &[format.extension()], &file_name).unwrap() {
+                                    if let Err(err) = transfer_function_preset::save(Path::new(&file_path), &TransferFunctionPreset::from_grid(grid), format) {
+                                        self.dialog = Some(("Error", format!("{:#}", err)));
+                                    }
+                                }
+                            },
+                            None => {}
+                        }
+                        if !open {
+                            self.transfer_function_window = None;
+                        }
+                    }
+
+                    if let Some(dicom_series_window) = &mut self.dicom_series_window {
+                        let mut open = true;
+                        if let Some(series) = dicom_series_window.show(ctx, &mut open) {
+                            let apply_window = dicom_series_window.apply_window;
+                            let directory = series.files.first()
+                                .and_then(|file| file.parent())
+                                .map(|directory| directory.display().to_string());
+                            new_load = Some(VolumeLoad::spawn(&series.name(), directory, move |progress| crate::loaders::dicom::read_series(&series, apply_window, progress)));
+                            open = false;
+                        }
+                        if !open {
+                            self.dicom_series_window = None;
+                        }
+                    }
+
+                    if let Some(image_stack_window) = &mut self.image_stack_window {
+                        let mut open = true;
+                        if let Some(pattern) = image_stack_window.show(ctx, &mut open) {
+                            let mode = image_stack_window.mode;
+                            let files_pattern = pattern.clone();
+                            new_load = Some(VolumeLoad::spawn(&pattern, Some(pattern.clone()), move |progress| {
+                                let files = crate::loaders::image_stack::list_images(&files_pattern)?;
+                                crate::loaders::image_stack::read_stack(&files, mode, progress)
+                            }));
+                            open = false;
+                        }
+                        if !open {
+                            self.image_stack_window = None;
+                        }
+                    }
+
+                    if let Some(raw_import_window) = &mut self.raw_import_window {
+                        let mut open = true;
+                        if raw_import_window.show(ctx, &mut open) {
+                            let file_path = raw_import_window.path().to_string();
+                            let layout = raw_import_window.layout.clone();
+                            let path = file_path.clone();
+                            new_load = Some(VolumeLoad::spawn(&file_path, Some(file_path.clone()), move |progress| crate::loaders::raw::read_volume(&path, &layout, progress)));
+                            open = false;
+                        }
+                        if !open {
+                            self.raw_import_window = None;
+                        }
+                    }
+
+                    if let Some(vtk_array_window) = &mut self.vtk_array_window {
+                        let mut open = true;
+                        if let Some(array) = vtk_array_window.show(ctx, &mut open) {
+                            if let Some(vtk_array_window) = self.vtk_array_window.take() {
+                                let file_path = vtk_array_window.path().to_string();
+                                let image = vtk_array_window.into_image();
+                                let title = format!("{} ({})", file_path, image.arrays[array].name);
+                                new_load = Some(VolumeLoad::spawn(&title, Some(file_path), move |_| image.to_volume(array)));
+                            }
+                            open = false;
+                        }
+                        if !open {
+                            self.vtk_array_window = None;
+                        }
+                    }
+
+                    // Draw progress of the volume that is read in the background
+                    if let Some(volume_load) = &self.volume_load {
+                        let (fraction, status) = volume_load.progress().status();
+                        egui::Window::new("Loading").collapsible(false).resizable(false).show(ctx, |ui| {
+                            ui.label(&volume_load.title);
+                            let progress_bar = match fraction {
+                                Some(fraction) => egui::ProgressBar::new(fraction).show_percentage(),
+                                None => egui::ProgressBar::new(0.0).animate(true),
+                            };
+                            ui.add(progress_bar);
+                            if volume_load.progress().is_cancelled() {
+                                ui.label("Cancelling…");
+                            } else {
+                                ui.label(status);
+                                if ui.button("Cancel").clicked() {
+                                    volume_load.cancel();
+                                }
+                            }
+                        });
+                    }
+
+                    if let Some((title, message)) = &self.dialog {
+                        let mut dismissed = false;
+                        egui::Window::new(*title).collapsible(false).resizable(false).show(ctx, |ui| {
+                            ui.label(message);
+                            if ui.button("OK").clicked() {
+                                dismissed = true;
+                            }
+                        });
+                        if dismissed {
+                            self.dialog = None;
+                        }
+                    }
+
                     // Draw Main Window UI
                     egui::Window::new("").default_open(true)
                     .show(&ctx, |ui| {
 
                         ui.label(format!("Frametime: {}ms", self.frametime.as_millis()));
+                        ui.label(format!("Format: {:?}", self.ray_marcher.voxel_grid.format));
+                        if let Some((resident, occupied)) = self.ray_marcher.voxel_grid.brick_residency() {
+                            ui.label(format!("Bricks: {} of {} resident", resident, occupied));
+                        }
+
+                        // Time Series Playback
+                        if let Some(time_series) = &mut self.time_series {
+                            ui.collapsing("Time Series", |ui| {
+                                time_series.show_controls(ui);
+                            });
+                        }
 
                         // Screenshotting 
 
@@ -317,7 +623,8 @@ impl<'a> State<'a> {
                         
                         if ui.button("Screenshot All").clicked() {
                             self.free_move = false;
-                            self.sphere_screenshot_manager.start_screenshotting(&mut self.camera_sphere_controller, &mut self.camera);
+                            let (box_min, box_size) = self.ray_marcher.voxel_grid.bounding_box();
+                            self.sphere_screenshot_manager.start_screenshotting(&mut self.camera_sphere_controller, &mut self.camera, box_min, box_size);
                             self.should_screenshot = true;
                         }
                         
@@ -367,17 +674,64 @@ impl<'a> State<'a> {
                             self.ray_marcher.voxel_grid.update_voxel_grid_buffer(&self.queue);
                         }
 
-                        let mut is_checked = self.ray_marcher.voxel_grid.transfer_function_colors.use_transfer_function[0] != 0;
-                        if ui.checkbox(&mut is_checked, "Use Transfer Function Colors").changed() {
-                            self.ray_marcher.voxel_grid.transfer_function_colors.set_transfer_function_active(is_checked);
+                        let scalar = self.ray_marcher.voxel_grid.is_scalar();
+                        let mut is_checked = scalar || self.ray_marcher.voxel_grid.transfer_function_settings.use_transfer_function_active();
+                        let checkbox = ui.add_enabled(!scalar, egui::Checkbox::new(&mut is_checked, "Use Transfer Function Colors"))
+                            .on_disabled_hover_text("Scalar volumes are always colored by the transfer function");
+                        if checkbox.changed() {
+                            self.ray_marcher.voxel_grid.transfer_function_settings.set_transfer_function_active(is_checked);
                             self.ray_marcher.voxel_grid.update_transfer_function_buffer(&self.queue);
                         }
+                        if ui.button("Edit Transfer Function").clicked() {
+                            self.transfer_function_window.get_or_insert_with(TransferFunctionWindow::default);
+                        }
+
+                        // Level of Detail
+                        let grid = &mut self.ray_marcher.voxel_grid;
+                        let mut forced_lod = grid.forced_lod;
+                        egui::ComboBox::from_label("Level of Detail")
+                            .selected_text(forced_lod.map_or("Automatic".to_string(), |level| format!("Level {}", level)))
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(&mut forced_lod, None, "Automatic");
+                                for level in 0..grid.mip_levels() {
+                                    ui.selectable_value(&mut forced_lod, Some(level), format!("Level {} ({})", level, level_size(grid.dimensions, level)));
+                                }
+                            });
+                        if forced_lod != grid.forced_lod {
+                            grid.forced_lod = forced_lod;
+                            grid.update_voxel_grid_buffer(&self.queue);
+                        }
+
+                        let mut mip_filter = grid.mip_filter();
+                        ui.add_enabled_ui(grid.mip_levels() > 1 && grid.voxels().is_ok(), |ui| {
+                            egui::ComboBox::from_label("Mip Filter")
+                                .selected_text(format!("{:?}", mip_filter))
+                                .show_ui(ui, |ui| {
+                                    ui.selectable_value(&mut mip_filter, MipFilter::Box, "Box");
+                                    ui.selectable_value(&mut mip_filter, MipFilter::Gaussian, "Gaussian");
+                                });
+                        }).response.on_disabled_hover_text("Only volumes that are kept in memory and fit into a single texture can be downsampled again");
+                        if mip_filter != grid.mip_filter() {
+                            if let Err(err) = grid.set_mip_filter(mip_filter, &self.queue) {
+                                self.dialog = Some(("Error", format!("{:#}", err)));
+                            }
+                        }
                     });
                 }
         );
 
         let gui_command = gui_encoder.finish();
 
+        if let Some(path) = open_path {
+            self.open_volume(&path);
+        }
+        if let Some(volume_load) = new_load {
+            self.start_load(volume_load);
+        }
+        if let Some(source) = time_series_source {
+            self.start_load(VolumeLoad::time_series(source, self.loaders.clone()));
+        }
+
         // Ensure that the screenshot is taken before the GUI is rendered
         let mut commands = vec![raymarch_command];
         if self.should_screenshot {
@@ -401,6 +755,127 @@ impl<'a> State<'a> {
     pub fn set_frametime(&mut self, frametime: Duration) {
         self.frametime = frametime;
     }
+
+    /// Replaces the current volume with a file, read by the loader registered for its format
+    fn open_volume(&mut self, path: &str) {
+        let loaders = self.loaders.clone();
+        let file_path = path.to_string();
+        let volume_load = if self.loaders.streams(path) {
+            VolumeLoad::stream(path, Some(path.to_string()), self.device.limits().max_texture_dimension_3d, move |progress, sink| loaders.stream_volume(&file_path, progress, sink))
+        } else {
+            VolumeLoad::spawn(path, Some(path.to_string()), move |progress| loaders.read_volume(&file_path, progress))
+        };
+        self.start_load(volume_load);
+    }
+
+    /// Starts reading a volume in the background, cancelling a load that is still running
+    fn start_load(&mut self, volume_load: VolumeLoad) {
+        if let Some(previous) = self.volume_load.replace(volume_load) {
+            previous.cancel();
+        }
+    }
+
+    /// Swaps in the volume of a finished load or shows why it failed
+    fn finish_load(&mut self, volume_load: VolumeLoad, result: anyhow::Result<LoadResult>) {
+        let (grid, time_series) = match result {
+            Ok(LoadResult::Grid(grid)) => (*grid, None),
+            Ok(LoadResult::TimeSeries { grid, source, first_frame }) => (*grid, Some(TimeSeries::new(source, first_frame, self.loaders.clone()))),
+            Ok(LoadResult::DicomSeries(series)) => {
+                self.dicom_series_window = Some(DicomSeriesWindow::new(&volume_load.title, series));
+                return;
+            },
+            Ok(LoadResult::VtkImage(image)) => {
+                self.vtk_array_window = Some(VtkArrayWindow::new(&volume_load.title, *image));
+                return;
+            },
+            Err(err) => {
+                if !volume_load.progress().is_cancelled() {
+                    self.dialog = Some(("Error", format!("{:#}", err)));
+                }
+                return;
+            }
+        };
+
+        self.replace_grid(grid);
+        self.ray_marcher.voxel_grid.source_path = volume_load.source_path.clone();
+        if let (true, Some(path)) = (volume_load.restore_netcdf_settings, &volume_load.source_path) {
+            if let Err(err) = crate::loaders::netcdf::apply_render_settings(path, &mut self.ray_marcher.voxel_grid, &self.queue) {
+                self.show_message("Error", format!("{:#}", err));
+            }
+        }
+        let notices = volume_load.progress().take_notices();
+        if !notices.is_empty() {
+            self.show_message("Notice", notices.join("\n\n"));
+        }
+        self.time_series = time_series;
+        self.window.set_title(&volume_load.title);
+        frame_volume(&self.ray_marcher.voxel_grid, &mut self.camera, &mut self.camera_sphere_controller);
+    }
+
+    /// Shows a message in the dialog, appending it to a message that wasn't dismissed yet so that neither is lost
+    fn show_message(&mut self, title: &'static str, message: String) {
+        match &mut self.dialog {
+            Some((_, text)) => {
+                text.push_str("\n\n");
+                text.push_str(&message);
+            },
+            None => self.dialog = Some((title, message)),
+        }
+    }
+
+    /// Renders another Voxel Grid, keeping the transfer function that was edited for the previous one
+    fn replace_grid(&mut self, mut grid: VoxelGrid) {
+        grid.transfer_function = self.ray_marcher.voxel_grid.transfer_function.clone();
+        grid.update_transfer_function_buffer(&self.queue);
+        self.ray_marcher.voxel_grid = grid;
+    }
+}
+
+/// Moves both the free camera and the camera sphere so that the whole box of the volume is in view
+fn frame_volume(grid: &VoxelGrid, camera: &mut Camera, csp: &mut CameraSphereController) {
+    let (box_min, box_size) = grid.bounding_box();
+    let center = box_min + box_size / 2.0;
+    let radius = box_size.length() / 2.0;
+    let distance = radius / f32::sin(f32::to_radians(camera.fovy()) / 2.0);
+
+    csp.origin = center;
+    csp.radius = distance;
+    camera.transform.position = center - camera.transform.forward() * distance;
+}
+
+/// Helper Function to open a File Dialog for selecting multiple files.
+/// The files are sorted so that numbered files are in ascending order.
+fn open_files_menu(filter_name: &str, extensions: &[&str]) -> anyhow::Result<Vec<String>> {
+    let files = pollster::block_on(AsyncFileDialog::new()
+        .add_filter(filter_name, extensions)
+        .set_directory(std::env::current_dir()?)
+        .pick_files());
+
+    let mut paths: Vec<String> = files.unwrap_or_default().iter()
+        .filter_map(|file_handle| file_handle.path().to_str().map(|path| path.to_string()))
+        .collect();
+    paths.sort_by(|a, b| crate::loaders::natural_cmp(a, b));
+    Ok(paths)
+}
+
+/// Helper Function to open a File Dialog for choosing where a file is saved
+fn save_file_menu(filter_name: &str, extensions: &[&str], file_name: &str) -> anyhow::Result<Option<String>> {
+    let file = pollster::block_on(AsyncFileDialog::new()
+        .add_filter(filter_name, extensions)
+        .set_directory(std::env::current_dir()?)
+        .set_file_name(file_name)
+        .save_file());
+
+    Ok(file.and_then(|file_handle| file_handle.path().to_str().map(|path| path.to_string())))
+}
+
+/// Opens a dialog for picking a folder, e.g. a DICOM series or an image stack, starting in the working directory
+fn open_folder_menu() -> anyhow::Result<Option<String>> {
+    let folder = pollster::block_on(AsyncFileDialog::new()
+        .set_directory(std::env::current_dir()?)
+        .pick_folder());
+
+    Ok(folder.and_then(|folder_handle| folder_handle.path().to_str().map(|path| path.to_string())))
 }
 
 /// Helper Function to easily open a File Dialog
@@ -24,8 +24,23 @@ impl Texture3D {
         queue: &wgpu::Queue,
         bytes: &[u8],
         dimensions: UVec3,
+        format: wgpu::TextureFormat,
+        mip_level_count: u32,
         label: Option<&str>
     ) -> Result<Self> {
+        let texture = Self::new(device, dimensions, format, mip_level_count, label);
+        texture.write_slab(queue, bytes, dimensions, 0, dimensions.z);
+        Ok(texture)
+    }
+
+    /// Creates an empty texture that is filled with [`Texture3D::write_slab`], its lower resolution mip levels with [`Texture3D::write_level`]
+    pub fn new(
+        device: &wgpu::Device,
+        dimensions: UVec3,
+        format: wgpu::TextureFormat,
+        mip_level_count: u32,
+        label: Option<&str>
+    ) -> Self {
 
         let size = wgpu::Extent3d {
             width: dimensions.x,
@@ -37,31 +52,15 @@ impl Texture3D {
             &wgpu::TextureDescriptor {
                 label,
                 size,
-                mip_level_count: 1,
+                mip_level_count,
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D3,
-                format: wgpu::TextureFormat::Rgba8Unorm,
+                format,
                 usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
                 view_formats: &[]
             }
         );
 
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                aspect: wgpu::TextureAspect::All,
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO
-            },
-            &bytes,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * dimensions.x),
-                rows_per_image: Some(dimensions.y),
-            },
-            size,
-        );
-
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = device.create_sampler(
             &wgpu::SamplerDescriptor {
@@ -75,6 +74,40 @@ impl Texture3D {
             }
         );
 
-        Ok(Self {texture, view, sampler})
+        Self {texture, view, sampler}
+    }
+
+    /// Uploads the texels of `depth` consecutive z slices, starting with slice `z`
+    pub fn write_slab(&self, queue: &wgpu::Queue, bytes: &[u8], dimensions: UVec3, z: u32, depth: u32) {
+        self.write_region(queue, bytes, UVec3::new(0, 0, z), UVec3::new(dimensions.x, dimensions.y, depth));
+    }
+
+    /// Uploads the tightly packed texels of a box of the given size at `origin`
+    pub fn write_region(&self, queue: &wgpu::Queue, bytes: &[u8], origin: UVec3, size: UVec3) {
+        self.write_level(queue, bytes, 0, origin, size);
+    }
+
+    /// Uploads the tightly packed texels of a box of a mip level
+    pub fn write_level(&self, queue: &wgpu::Queue, bytes: &[u8], mip_level: u32, origin: UVec3, size: UVec3) {
+        let texel_size = self.texture.format().block_copy_size(None).expect("Volume textures have a single aspect");
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &self.texture,
+                mip_level,
+                origin: wgpu::Origin3d { x: origin.x, y: origin.y, z: origin.z }
+            },
+            bytes,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(texel_size * size.x),
+                rows_per_image: Some(size.y),
+            },
+            wgpu::Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: size.z,
+            },
+        );
     }
 }
\ No newline at end of file
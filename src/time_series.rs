@@ -0,0 +1,273 @@
+use std::{collections::{HashMap, HashSet}, sync::Arc, time::Duration};
+
+use anyhow::{bail, Result};
+use egui::Ui;
+use glam::UVec3;
+
+use crate::{loaders::{self, netcdf::VariableSelection, progress::LoadProgress, registry::LoaderRegistry}, voxel::volume::VolumeData};
+
+/// Amount of frames after the current frame that are loaded in the background
+const PREFETCH_FRAMES: usize = 3;
+
+/// Where the frames of a time series are read from
+#[derive(Clone)]
+pub enum TimeSeriesSource {
+    /// A NetCDF variable, each frame is a slice along one of its extra dimensions
+    NetcdfVariable {
+        path: String,
+        selection: VariableSelection,
+        time_dimension: usize,
+        frame_count: usize,
+    },
+    /// Volume files that each hold a single frame, in playback order
+    Files(Vec<String>),
+}
+
+impl TimeSeriesSource {
+    pub fn frame_count(&self) -> usize {
+        match self {
+            Self::NetcdfVariable { frame_count, .. } => *frame_count,
+            Self::Files(paths) => paths.len(),
+        }
+    }
+
+    /// Path of the file a frame is read from
+    pub fn path(&self, index: usize) -> &str {
+        match self {
+            Self::NetcdfVariable { path, .. } => path,
+            Self::Files(paths) => &paths[index],
+        }
+    }
+
+    /// Human readable name of a frame, used for the window title
+    pub fn frame_name(&self, index: usize) -> String {
+        match self {
+            Self::NetcdfVariable { path, selection, .. } => format!("{} ({}, frame {})", path, selection.variable, index),
+            Self::Files(paths) => paths[index].clone(),
+        }
+    }
+
+    fn read_frame(&self, index: usize, file_loaders: &LoaderRegistry) -> Result<VolumeData> {
+        match self {
+            Self::NetcdfVariable { path, selection, time_dimension, .. } => {
+                let mut selection = selection.clone();
+                if selection.slice_indices.len() <= *time_dimension {
+                    selection.slice_indices.resize(*time_dimension + 1, 0);
+                }
+                selection.slice_indices[*time_dimension] = index;
+                loaders::netcdf::read_variable(path, &selection, &LoadProgress::default())
+            },
+            Self::Files(paths) => file_loaders.read_volume(&paths[index], &LoadProgress::default()),
+        }
+    }
+}
+
+/// Plays back a time-varying volume.
+/// Frames are read by a background thread, which prefetches the frames following the current one.
+pub struct TimeSeries {
+    source: TimeSeriesSource,
+    dimensions: UVec3,
+    frames: HashMap<usize, Arc<VolumeData>>,
+    requested: HashSet<usize>,
+    failed: HashSet<usize>,
+    request_sender: flume::Sender<usize>,
+    frame_receiver: flume::Receiver<(usize, Result<VolumeData>)>,
+    /// Position on the timeline, the fractional part is used for interpolating between frames
+    pub time: f32,
+    pub playing: bool,
+    pub looping: bool,
+    pub interpolate: bool,
+    pub frames_per_second: f32,
+    /// Frame, following frame and interpolation weight that were last handed out for rendering
+    displayed: Option<(usize, usize, u8)>,
+}
+
+impl TimeSeries {
+    /// Reads the first frame of a time series, files are read with the given loaders.
+    /// NetCDF sources keep the value range of the first frame, so that all frames are mapped the same way.
+    pub fn read_first_frame(source: &mut TimeSeriesSource, file_loaders: &LoaderRegistry, progress: &LoadProgress) -> Result<VolumeData> {
+        if source.frame_count() == 0 {
+            bail!("Time series does not contain any frames");
+        }
+
+        match source {
+            TimeSeriesSource::NetcdfVariable { path, selection, time_dimension, .. } => {
+                selection.slice_indices.resize(selection.slice_indices.len().max(*time_dimension + 1), 0);
+                selection.slice_indices[*time_dimension] = 0;
+                let (volume, value_range) = loaders::netcdf::read_variable_with_range(path, selection, progress)?;
+                selection.value_range = Some(value_range);
+                Ok(volume)
+            },
+            TimeSeriesSource::Files(paths) => file_loaders.read_volume(&paths[0], progress),
+        }
+    }
+
+    /// Starts the background loader for the frames after the first one, which was read with [`TimeSeries::read_first_frame`]
+    pub fn new(source: TimeSeriesSource, first_frame: Arc<VolumeData>, file_loaders: Arc<LoaderRegistry>) -> Self {
+        let (request_sender, request_receiver) = flume::unbounded::<usize>();
+        let (frame_sender, frame_receiver) = flume::unbounded();
+        let worker_source = source.clone();
+        std::thread::spawn(move || {
+            // Stops once the time series and with it the request sender is dropped
+            for index in request_receiver.iter() {
+                let frame = worker_source.read_frame(index, &file_loaders);
+                if frame_sender.send((index, frame)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let dimensions = first_frame.dimensions;
+        let mut frames = HashMap::new();
+        frames.insert(0, first_frame);
+
+        Self {
+            source,
+            dimensions,
+            frames,
+            requested: HashSet::new(),
+            failed: HashSet::new(),
+            request_sender,
+            frame_receiver,
+            time: 0.0,
+            playing: false,
+            looping: true,
+            interpolate: false,
+            frames_per_second: 10.0,
+            displayed: Some((0, 0, 0)),
+        }
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.source.frame_count()
+    }
+
+    pub fn current_frame(&self) -> usize {
+        (self.time.floor() as usize).min(self.frame_count() - 1)
+    }
+
+    /// Advances the playback and returns a new volume whenever the displayed frame changes.
+    /// Playback waits for frames that are still being loaded.
+    pub fn update(&mut self, delta: Duration) -> Option<Arc<VolumeData>> {
+        self.receive_frames();
+
+        if self.playing {
+            let next_time = self.wrap_time(self.time + delta.as_secs_f32() * self.frames_per_second);
+            let (frame, next_frame, _) = self.frames_at(next_time);
+            if self.frames.contains_key(&frame) && self.frames.contains_key(&next_frame) {
+                self.time = next_time;
+            }
+        }
+
+        self.prefetch();
+
+        let (frame, next_frame, weight) = self.frames_at(self.time);
+        // Interpolation weights are quantized so that a new volume is only built when it is visible
+        let quantized_weight = (weight * 255.0).round() as u8;
+        if self.displayed == Some((frame, next_frame, quantized_weight)) {
+            return None;
+        }
+
+        let current = self.frames.get(&frame)?;
+        let volume = if quantized_weight == 0 || frame == next_frame {
+            Arc::clone(current)
+        } else {
+            let next = self.frames.get(&next_frame)?;
+            Arc::new(current.lerp(next, weight))
+        };
+        self.displayed = Some((frame, next_frame, quantized_weight));
+        Some(volume)
+    }
+
+    /// Draws the timeline and playback controls
+    pub fn show_controls(&mut self, ui: &mut Ui) {
+        let last_frame = (self.frame_count() - 1) as f32;
+        ui.horizontal(|ui| {
+            let label = if self.playing { "Pause" } else { "Play" };
+            if ui.button(label).clicked() {
+                // Restart from the beginning when playback has stopped at the end
+                if !self.playing && !self.looping && self.time >= last_frame {
+                    self.time = 0.0;
+                }
+                self.playing = !self.playing;
+            }
+            ui.checkbox(&mut self.looping, "Loop");
+            ui.checkbox(&mut self.interpolate, "Interpolate");
+        });
+
+        let slider = egui::Slider::new(&mut self.time, 0.0..=last_frame).text("Frame");
+        let slider = if self.interpolate { slider } else { slider.step_by(1.0) };
+        ui.add(slider);
+
+        let slider = egui::Slider::new(&mut self.frames_per_second, 1.0..=60.0).text("Frames per second");
+        ui.add(slider);
+
+        let loaded = self.frames.len();
+        ui.label(format!("Frame {} of {} ({} loaded, {} loading)", self.current_frame() + 1, self.frame_count(), loaded, self.requested.len()));
+    }
+
+    fn receive_frames(&mut self) {
+        for (index, frame) in self.frame_receiver.try_iter() {
+            self.requested.remove(&index);
+            match frame {
+                Ok(frame) if frame.dimensions == self.dimensions => {
+                    self.frames.insert(index, Arc::new(frame));
+                },
+                Ok(frame) => {
+                    eprintln!("Frame {} has dimensions {} instead of {}", index, frame.dimensions, self.dimensions);
+                    self.failed.insert(index);
+                },
+                Err(err) => {
+                    eprintln!("Could not load frame {}: {:?}", index, err);
+                    self.failed.insert(index);
+                }
+            }
+        }
+    }
+
+    /// Requests the current and the following frames and drops all other frames from memory
+    fn prefetch(&mut self) {
+        let frame_count = self.frame_count();
+        let current = self.current_frame();
+        let wanted: Vec<usize> = (0..=PREFETCH_FRAMES)
+            .map(|offset| current + offset)
+            .filter_map(|index| if self.looping { Some(index % frame_count) } else if index < frame_count { Some(index) } else { None })
+            .collect();
+
+        // Keep the previous frame as well, it is still needed when scrubbing backwards
+        let previous = if current > 0 { current - 1 } else if self.looping { frame_count - 1 } else { 0 };
+        self.frames.retain(|index, _| wanted.contains(index) || *index == previous);
+
+        for index in wanted {
+            if !self.frames.contains_key(&index) && !self.requested.contains(&index) && !self.failed.contains(&index)
+                && self.request_sender.send(index).is_ok() {
+                self.requested.insert(index);
+            }
+        }
+    }
+
+    /// Wraps or clamps a time to the timeline and stops playback at the end when not looping
+    fn wrap_time(&mut self, time: f32) -> f32 {
+        let frame_count = self.frame_count() as f32;
+        if self.looping {
+            time.rem_euclid(frame_count)
+        } else if time >= frame_count - 1.0 {
+            self.playing = false;
+            frame_count - 1.0
+        } else {
+            time
+        }
+    }
+
+    /// Returns the frame, the following frame and the interpolation weight between both for a time
+    fn frames_at(&self, time: f32) -> (usize, usize, f32) {
+        let frame_count = self.frame_count();
+        let frame = (time.floor() as usize).min(frame_count - 1);
+        if !self.interpolate {
+            return (frame, frame, 0.0);
+        }
+
+        let next_frame = if frame + 1 < frame_count { frame + 1 } else if self.looping { 0 } else { frame };
+        (frame, next_frame, time - frame as f32)
+    }
+}
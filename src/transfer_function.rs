@@ -0,0 +1,148 @@
+use serde::{Deserialize, Serialize};
+use wgpu::{Device, Queue};
+
+use crate::voxel::format::VoxelFormat;
+
+/// Amount of entries of the texture a transfer function is baked into
+pub const TRANSFER_FUNCTION_RESOLUTION: u32 = 1024;
+
+/// Color at a value of the transfer function
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ColorPoint {
+    pub value: f32,
+    pub color: [f32; 3],
+}
+
+/// Opacity at a value of the transfer function
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct OpacityPoint {
+    pub value: f32,
+    pub opacity: f32,
+}
+
+/// Maps the normalized values of a volume to colors and opacities.
+/// Colors and opacities are interpolated linearly between their control points and held constant beyond the outermost ones.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransferFunction {
+    /// Color control points, sorted by value
+    pub colors: Vec<ColorPoint>,
+    /// Opacity control points, sorted by value
+    pub opacities: Vec<OpacityPoint>,
+}
+
+impl Default for TransferFunction {
+    /// Blends from blue over gray to red with an opacity that rises linearly with the value
+    fn default() -> Self {
+        Self::from_colors([[0.117, 0.188, 0.62], [0.7294, 0.7294, 0.7294], [0.5725, 0.0, 0.039]])
+    }
+}
+
+impl TransferFunction {
+    /// Spreads colors evenly over the value range with an opacity that rises linearly with the value
+    pub fn from_colors(colors: impl IntoIterator<Item = [f32; 3]>) -> Self {
+        let colors: Vec<[f32; 3]> = colors.into_iter().collect();
+        let last = colors.len().saturating_sub(1).max(1) as f32;
+        Self {
+            colors: colors.into_iter().enumerate().map(|(i, color)| ColorPoint { value: i as f32 / last, color }).collect(),
+            opacities: vec![OpacityPoint { value: 0.0, opacity: 0.0 }, OpacityPoint { value: 1.0, opacity: 1.0 }],
+        }
+    }
+
+    pub fn color(&self, value: f32) -> [f32; 3] {
+        let Some(first) = self.colors.first() else {
+            return [1.0; 3];
+        };
+        let mut color = first.color;
+        for pair in self.colors.windows(2) {
+            if value >= pair[0].value {
+                let t = interpolation_weight(value, pair[0].value, pair[1].value);
+                color = std::array::from_fn(|channel| pair[0].color[channel] + (pair[1].color[channel] - pair[0].color[channel]) * t);
+            }
+        }
+        color
+    }
+
+    pub fn opacity(&self, value: f32) -> f32 {
+        let Some(first) = self.opacities.first() else {
+            return value;
+        };
+        let mut opacity = first.opacity;
+        for pair in self.opacities.windows(2) {
+            if value >= pair[0].value {
+                opacity = pair[0].opacity + (pair[1].opacity - pair[0].opacity) * interpolation_weight(value, pair[0].value, pair[1].value);
+            }
+        }
+        opacity
+    }
+
+    /// Color and opacity at a value
+    pub fn sample(&self, value: f32) -> [f32; 4] {
+        let [r, g, b] = self.color(value);
+        [r, g, b, self.opacity(value)]
+    }
+
+    /// Sorts the control points by value after they were moved
+    pub fn sort(&mut self) {
+        self.colors.sort_by(|a, b| a.value.total_cmp(&b.value));
+        self.opacities.sort_by(|a, b| a.value.total_cmp(&b.value));
+    }
+
+    /// Samples the transfer function at evenly spaced values from 0 to 1
+    pub fn bake(&self, resolution: u32) -> Vec<[f32; 4]> {
+        let last = resolution.saturating_sub(1).max(1) as f32;
+        (0..resolution).map(|i| self.sample(i as f32 / last)).collect()
+    }
+}
+
+/// Position of a value between two control points, 1 past the second one
+fn interpolation_weight(value: f32, start: f32, end: f32) -> f32 {
+    if end <= start {
+        return 1.0;
+    }
+    ((value - start) / (end - start)).clamp(0.0, 1.0)
+}
+
+/// 1D texture a transfer function is baked into, the raymarcher interpolates between its entries
+pub struct TransferFunctionTexture {
+    texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+}
+
+impl TransferFunctionTexture {
+    const FORMAT: VoxelFormat = VoxelFormat::Rgba16Float;
+
+    pub fn new(device: &Device) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Transfer Function Texture"),
+            size: wgpu::Extent3d { width: TRANSFER_FUNCTION_RESOLUTION, height: 1, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D1,
+            format: Self::FORMAT.texture_format(),
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Self { texture, view }
+    }
+
+    /// Bakes the transfer function and uploads it
+    pub fn write(&self, transfer_function: &TransferFunction, queue: &Queue) {
+        let entries = transfer_function.bake(TRANSFER_FUNCTION_RESOLUTION);
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            &Self::FORMAT.encode(bytemuck::cast_slice(&entries)),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(TRANSFER_FUNCTION_RESOLUTION * Self::FORMAT.bytes_per_voxel() as u32),
+                rows_per_image: None,
+            },
+            wgpu::Extent3d { width: TRANSFER_FUNCTION_RESOLUTION, height: 1, depth_or_array_layers: 1 },
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use glam::{UVec3, Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+use wgpu::{Device, Queue};
+
+use crate::voxel::format::VoxelFormat;
+
+/// Amount of entries along each axis of the texture a 2D transfer function is baked into
+pub const TRANSFER_FUNCTION_2D_RESOLUTION: u32 = 256;
+
+/// Amount of bins along each axis of the histogram over density and gradient magnitude
+pub const GRADIENT_HISTOGRAM_BINS: usize = 128;
+
+/// Largest gradient magnitude that central differences of densities between 0 and 1 can reach,
+/// used for the gradient axis if the gradients of a volume can't be computed, e.g. for streamed volumes
+pub const GRADIENT_LIMIT: f32 = 0.8660254;
+
+/// How the opacity of a widget falls off inside its box
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum WidgetShape {
+    /// Constant opacity in the whole box
+    #[default]
+    Rectangle,
+    /// Opacity that falls off from the vertical center line towards the sides of a triangle standing on its tip at the bottom of the box,
+    /// which selects the boundary between two materials whose density spreads with rising gradient magnitude
+    Triangle,
+    /// Opacity that falls off from the center like a Gaussian whose standard deviation is a quarter of the box
+    Gaussian,
+}
+
+/// Assigns a color and an opacity to a region of density and gradient magnitude
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TransferFunctionWidget {
+    pub shape: WidgetShape,
+    /// Lower corner of the box, x is the density and y the normalized gradient magnitude, both between 0 and 1
+    #[serde(with = "vec2_array")]
+    pub min: Vec2,
+    /// Upper corner of the box
+    #[serde(with = "vec2_array")]
+    pub max: Vec2,
+    pub color: [f32; 3],
+    pub opacity: f32,
+}
+
+impl TransferFunctionWidget {
+    /// Share of the widget's opacity at a density and gradient magnitude
+    pub fn weight(&self, position: Vec2) -> f32 {
+        let size = (self.max - self.min).max(Vec2::splat(f32::EPSILON));
+        let center = (self.min + self.max) / 2.0;
+        match self.shape {
+            WidgetShape::Gaussian => {
+                let distance = (position - center) / (size / 4.0);
+                (-0.5 * distance.length_squared()).exp()
+            },
+            _ if position.cmplt(self.min).any() || position.cmpgt(self.max).any() => 0.0,
+            WidgetShape::Rectangle => 1.0,
+            WidgetShape::Triangle => {
+                let half_width = size.x / 2.0 * (position.y - self.min.y) / size.y;
+                if half_width <= 0.0 {
+                    return 0.0;
+                }
+                (1.0 - (position.x - center.x).abs() / half_width).max(0.0)
+            },
+        }
+    }
+}
+
+/// Stores a [`Vec2`] as an array of two numbers, glam is built without its serde support
+mod vec2_array {
+    use glam::Vec2;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(vector: &Vec2, serializer: S) -> Result<S::Ok, S::Error> {
+        vector.to_array().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec2, D::Error> {
+        <[f32; 2]>::deserialize(deserializer).map(Vec2::from)
+    }
+}
+
+/// Maps pairs of density and gradient magnitude to colors and opacities with a set of widgets.
+/// Opacities of overlapping widgets add up and their colors are blended by opacity.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransferFunction2D {
+    pub widgets: Vec<TransferFunctionWidget>,
+}
+
+impl Default for TransferFunction2D {
+    /// A single triangle over the whole range that shows the boundaries of the volume
+    fn default() -> Self {
+        Self {
+            widgets: vec![TransferFunctionWidget { shape: WidgetShape::Triangle, min: Vec2::ZERO, max: Vec2::ONE, color: [0.9; 3], opacity: 1.0 }],
+        }
+    }
+}
+
+impl TransferFunction2D {
+    /// Color and opacity at a density and a gradient magnitude that is normalized to 0..1
+    pub fn sample(&self, position: Vec2) -> [f32; 4] {
+        let mut opacity = 0.0;
+        let mut color = Vec3::ZERO;
+        for widget in &self.widgets {
+            let alpha = widget.opacity * widget.weight(position);
+            opacity += alpha;
+            color += Vec3::from(widget.color) * alpha;
+        }
+        if opacity > 0.0 {
+            color /= opacity;
+        }
+        [color.x, color.y, color.z, opacity.min(1.0)]
+    }
+
+    /// Samples the transfer function on an evenly spaced grid from 0 to 1, density changes fastest
+    pub fn bake(&self, resolution: u32) -> Vec<[f32; 4]> {
+        let last = resolution.saturating_sub(1).max(1) as f32;
+        (0..resolution * resolution)
+            .map(|i| self.sample(Vec2::new((i % resolution) as f32, (i / resolution) as f32) / last))
+            .collect()
+    }
+}
+
+/// Amount of voxels per pair of density and gradient magnitude
+#[derive(Debug, Clone, PartialEq)]
+pub struct GradientHistogram {
+    /// Largest gradient magnitude of the volume, which maps to the top of the gradient axis
+    pub max_gradient: f32,
+    /// [`GRADIENT_HISTOGRAM_BINS`]² counts, density bins change fastest
+    pub counts: Vec<u32>,
+}
+
+impl GradientHistogram {
+    /// Counts the densities and gradient magnitudes of all voxels of a volume, densities are looked up by voxel index
+    pub fn new(dimensions: UVec3, density: impl Fn(usize) -> f32) -> Self {
+        let positions = || (0..dimensions.z).flat_map(move |z| (0..dimensions.y).flat_map(move |y| (0..dimensions.x).map(move |x| UVec3::new(x, y, z))));
+        let max_gradient = positions().map(|position| gradient_magnitude(dimensions, &density, position)).fold(0.0, f32::max);
+
+        let bins = GRADIENT_HISTOGRAM_BINS;
+        let bin = |value: f32| ((value.clamp(0.0, 1.0) * bins as f32) as usize).min(bins - 1);
+        let mut counts = vec![0; bins * bins];
+        for (index, position) in positions().enumerate() {
+            let gradient = gradient_magnitude(dimensions, &density, position) / max_gradient.max(f32::EPSILON);
+            counts[bin(density(index)) + bins * bin(gradient)] += 1;
+        }
+        Self { max_gradient, counts }
+    }
+}
+
+/// Magnitude of the gradient of the densities at a voxel from central differences, in density per voxel.
+/// Missing neighbors at the border are replaced by the voxel itself, like the sampler clamps to the edge of the texture.
+pub fn gradient_magnitude(dimensions: UVec3, density: &impl Fn(usize) -> f32, position: UVec3) -> f32 {
+    let index = |position: UVec3| (position.x + dimensions.x * (position.y + dimensions.y * position.z)) as usize;
+    let mut gradient = Vec3::ZERO;
+    for axis in 0..3 {
+        let (mut next, mut previous) = (position, position);
+        next[axis] = (position[axis] + 1).min(dimensions[axis] - 1);
+        previous[axis] = position[axis].saturating_sub(1);
+        gradient[axis] = (density(index(next)) - density(index(previous))) / 2.0;
+    }
+    gradient.length()
+}
+
+/// 2D texture a 2D transfer function is baked into, sampled with linear filtering
+pub struct TransferFunction2DTexture {
+    texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+}
+
+impl TransferFunction2DTexture {
+    const FORMAT: VoxelFormat = VoxelFormat::Rgba16Float;
+
+    pub fn new(device: &Device) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("2D Transfer Function Texture"),
+            size: wgpu::Extent3d { width: TRANSFER_FUNCTION_2D_RESOLUTION, height: TRANSFER_FUNCTION_2D_RESOLUTION, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::FORMAT.texture_format(),
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        Self { texture, view, sampler }
+    }
+
+    /// Bakes the transfer function and uploads it
+    pub fn write(&self, transfer_function: &TransferFunction2D, queue: &Queue) {
+        let entries = transfer_function.bake(TRANSFER_FUNCTION_2D_RESOLUTION);
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            &Self::FORMAT.encode(bytemuck::cast_slice(&entries)),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(TRANSFER_FUNCTION_2D_RESOLUTION * Self::FORMAT.bytes_per_voxel() as u32),
+                rows_per_image: None,
+            },
+            wgpu::Extent3d { width: TRANSFER_FUNCTION_2D_RESOLUTION, height: TRANSFER_FUNCTION_2D_RESOLUTION, depth_or_array_layers: 1 },
+        );
+    }
+}
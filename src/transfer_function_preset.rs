@@ -0,0 +1,331 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{transfer_function::{ColorPoint, OpacityPoint, TransferFunction}, transfer_function_2d::TransferFunction2D, voxel::grid::VoxelGrid};
+
+/// Amount of entries of an exported DiffDVR texture transfer function
+const DIFFDVR_TEXTURE_RESOLUTION: usize = 256;
+/// Amount of values a DiffDVR Gaussian transfer function is sampled at when it is imported
+const GAUSSIAN_SAMPLES: usize = 256;
+
+/// Transfer functions and their settings as they are saved to a JSON file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransferFunctionPreset {
+    pub transfer_function: TransferFunction,
+    /// Missing for transfer functions imported from other applications, the 2D transfer function of the volume is kept then
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transfer_function_2d: Option<TransferFunction2D>,
+    /// Whether the transfer function also recolors RGBA volumes
+    #[serde(default)]
+    pub active: bool,
+    /// Whether the 2D transfer function is used instead of the 1D one
+    #[serde(default)]
+    pub use_2d: bool,
+}
+
+impl TransferFunctionPreset {
+    pub fn from_grid(grid: &VoxelGrid) -> Self {
+        Self {
+            transfer_function: grid.transfer_function.clone(),
+            transfer_function_2d: Some(grid.transfer_function_2d.clone()),
+            active: grid.transfer_function_settings.use_transfer_function_active(),
+            use_2d: grid.transfer_function_settings.transfer_function_2d_active(),
+        }
+    }
+
+    /// Imported 1D transfer function that is used for RGBA volumes as well
+    fn imported(transfer_function: TransferFunction) -> Self {
+        Self { transfer_function, transfer_function_2d: None, active: true, use_2d: false }
+    }
+
+    /// Replaces the transfer functions of the grid, their buffers still have to be updated
+    pub fn apply(self, grid: &mut VoxelGrid) {
+        grid.transfer_function = self.transfer_function;
+        if let Some(transfer_function_2d) = self.transfer_function_2d {
+            grid.transfer_function_2d = transfer_function_2d;
+        }
+        grid.transfer_function_settings.set_transfer_function_active(self.active);
+        grid.transfer_function_settings.set_transfer_function_2d_active(self.use_2d);
+    }
+}
+
+/// How DiffDVR interprets the control points of its transfer function tensor of shape batch x points x channels.
+/// Opacities are absorption coefficients in both renderers, so they are taken over unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffDvrMode {
+    /// A single point of opacity and color scale that the density is multiplied with
+    Identity,
+    /// Evenly spaced RGBA entries that are interpolated linearly between their centers
+    Texture,
+    /// RGBA entries at arbitrary positions, the last channel, that are interpolated linearly
+    Linear,
+    /// RGBA entries weighted by a normal distribution with the mean and standard deviation in the last two channels
+    Gaussian,
+}
+
+impl DiffDvrMode {
+    const ALL: [DiffDvrMode; 4] = [DiffDvrMode::Identity, DiffDvrMode::Texture, DiffDvrMode::Linear, DiffDvrMode::Gaussian];
+
+    /// Name of the mode in the exported file, the lower case name of DiffDVR's `TFMode`
+    pub fn name(self) -> &'static str {
+        match self {
+            DiffDvrMode::Identity => "identity",
+            DiffDvrMode::Texture => "texture",
+            DiffDvrMode::Linear => "linear",
+            DiffDvrMode::Gaussian => "gaussian",
+        }
+    }
+
+    /// Amount of channels of each control point
+    fn channels(self) -> usize {
+        match self {
+            DiffDvrMode::Identity => 2,
+            DiffDvrMode::Texture => 4,
+            DiffDvrMode::Linear => 5,
+            DiffDvrMode::Gaussian => 6,
+        }
+    }
+}
+
+/// File formats a transfer function can be saved in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferFunctionFormat {
+    /// Both transfer functions and their settings
+    Preset,
+    /// ParaView color map JSON, colors and opacities only
+    ParaViewJson,
+    /// ParaView color map XML
+    ParaViewXml,
+    /// Tensor of DiffDVR's transfer function, which `diffdvr.py` loads
+    DiffDvr(DiffDvrMode),
+}
+
+impl TransferFunctionFormat {
+    /// Formats a transfer function can be saved in, Identity and Gaussian tensors can't represent arbitrary transfer functions
+    pub const SAVE_FORMATS: [TransferFunctionFormat; 5] = [
+        TransferFunctionFormat::Preset,
+        TransferFunctionFormat::ParaViewJson,
+        TransferFunctionFormat::ParaViewXml,
+        TransferFunctionFormat::DiffDvr(DiffDvrMode::Texture),
+        TransferFunctionFormat::DiffDvr(DiffDvrMode::Linear),
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TransferFunctionFormat::Preset => "Transfer Function Preset",
+            TransferFunctionFormat::ParaViewJson => "ParaView Color Map (JSON)",
+            TransferFunctionFormat::ParaViewXml => "ParaView Color Map (XML)",
+            TransferFunctionFormat::DiffDvr(DiffDvrMode::Identity) => "DiffDVR Tensor (Identity)",
+            TransferFunctionFormat::DiffDvr(DiffDvrMode::Texture) => "DiffDVR Tensor (Texture)",
+            TransferFunctionFormat::DiffDvr(DiffDvrMode::Linear) => "DiffDVR Tensor (Linear)",
+            TransferFunctionFormat::DiffDvr(DiffDvrMode::Gaussian) => "DiffDVR Tensor (Gaussian)",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            TransferFunctionFormat::ParaViewXml => "xml",
+            _ => "json",
+        }
+    }
+}
+
+/// Saves the transfer function of a preset, only the preset format keeps the 2D transfer function and the settings
+pub fn save(path: &Path, preset: &TransferFunctionPreset, format: TransferFunctionFormat) -> Result<()> {
+    let name = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("Transfer Function");
+    let text = match format {
+        TransferFunctionFormat::Preset => serde_json::to_string_pretty(preset)?,
+        TransferFunctionFormat::ParaViewJson => serde_json::to_string_pretty(&paraview_json(&preset.transfer_function, name))?,
+        TransferFunctionFormat::ParaViewXml => paraview_xml(&preset.transfer_function, name),
+        TransferFunctionFormat::DiffDvr(mode) => serde_json::to_string_pretty(&diffdvr_tensor(&preset.transfer_function, mode)?)?,
+    };
+    fs::write(path, text).with_context(|| format!("Failed to write transfer function '{}'", path.display()))
+}
+
+/// Loads a preset, a ParaView color map or a DiffDVR tensor, which is told apart by the extension and the content of the file
+pub fn load(path: &Path) -> Result<TransferFunctionPreset> {
+    let text = fs::read_to_string(path).with_context(|| format!("Failed to read transfer function '{}'", path.display()))?;
+    let preset = if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("xml")) {
+        TransferFunctionPreset::imported(read_paraview_xml(&text)?)
+    } else {
+        let value: Value = serde_json::from_str(&text)?;
+        if value.get("tf_mode").is_some() {
+            TransferFunctionPreset::imported(read_diffdvr_tensor(&value)?)
+        } else if value.is_array() || value.get("RGBPoints").is_some() {
+            TransferFunctionPreset::imported(read_paraview_json(&value)?)
+        } else {
+            serde_json::from_value(value)?
+        }
+    };
+    Ok(preset)
+}
+
+/// Values of all control points, where both the colors and the opacities are piecewise linear in between
+fn control_values(transfer_function: &TransferFunction) -> Vec<f32> {
+    let mut values: Vec<f32> = transfer_function.colors.iter().map(|point| point.value)
+        .chain(transfer_function.opacities.iter().map(|point| point.value))
+        .collect();
+    values.sort_by(f32::total_cmp);
+    values.dedup();
+    if values.is_empty() {
+        values = vec![0.0, 1.0];
+    }
+    values
+}
+
+/// Builds a transfer function from control points over an arbitrary value range, which is mapped to 0..1
+fn from_points(colors: Vec<(f32, [f32; 3])>, opacities: Option<Vec<(f32, f32)>>) -> Result<TransferFunction> {
+    if colors.is_empty() {
+        bail!("Transfer function has no color points");
+    }
+    let (min, max) = colors.iter().map(|(value, _)| *value).fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), value| (min.min(value), max.max(value)));
+    let normalize = |value: f32| if max > min { ((value - min) / (max - min)).clamp(0.0, 1.0) } else { 0.0 };
+    let mut transfer_function = TransferFunction {
+        colors: colors.iter().map(|(value, color)| ColorPoint { value: normalize(*value), color: *color }).collect(),
+        opacities: match opacities {
+            Some(opacities) => opacities.iter().map(|(value, opacity)| OpacityPoint { value: normalize(*value), opacity: *opacity }).collect(),
+            None => TransferFunction::default().opacities,
+        },
+    };
+    transfer_function.sort();
+    Ok(transfer_function)
+}
+
+fn paraview_json(transfer_function: &TransferFunction, name: &str) -> Value {
+    let rgb_points: Vec<f32> = transfer_function.colors.iter().flat_map(|point| [point.value, point.color[0], point.color[1], point.color[2]]).collect();
+    // Midpoint 0.5 and sharpness 0 make the opacity linear between the points
+    let points: Vec<f32> = transfer_function.opacities.iter().flat_map(|point| [point.value, point.opacity, 0.5, 0.0]).collect();
+    json!([{ "Name": name, "ColorSpace": "RGB", "RGBPoints": rgb_points, "Points": points }])
+}
+
+/// Reads the first color map of a ParaView JSON file, opacity midpoints and sharpness are ignored
+fn read_paraview_json(value: &Value) -> Result<TransferFunction> {
+    let color_map = match value {
+        Value::Array(color_maps) => color_maps.first().ok_or_else(|| anyhow!("ParaView file contains no color map"))?,
+        color_map => color_map,
+    };
+    let numbers = |key: &str| -> Result<Option<Vec<f32>>> {
+        match color_map.get(key) {
+            Some(points) => Ok(Some(serde_json::from_value(points.clone()).with_context(|| format!("'{}' of the color map are no numbers", key))?)),
+            None => Ok(None),
+        }
+    };
+    let rgb_points = numbers("RGBPoints")?.ok_or_else(|| anyhow!("Color map has no 'RGBPoints'"))?;
+    let colors = rgb_points.chunks_exact(4).map(|point| (point[0], [point[1], point[2], point[3]])).collect();
+    let opacities = numbers("Points")?.map(|points| points.chunks_exact(4).map(|point| (point[0], point[1])).collect());
+    from_points(colors, opacities)
+}
+
+fn paraview_xml(transfer_function: &TransferFunction, name: &str) -> String {
+    let mut xml = format!("<ColorMaps>\n  <ColorMap name=\"{}\" space=\"RGB\">\n", name.replace(['"', '<', '>', '&'], "_"));
+    for value in control_values(transfer_function) {
+        let [r, g, b, o] = transfer_function.sample(value);
+        xml += &format!("    <Point x=\"{}\" o=\"{}\" r=\"{}\" g=\"{}\" b=\"{}\"/>\n", value, o, r, g, b);
+    }
+    xml += "  </ColorMap>\n</ColorMaps>\n";
+    xml
+}
+
+/// Reads the points of the first color map of a ParaView XML file
+fn read_paraview_xml(text: &str) -> Result<TransferFunction> {
+    let mut colors = vec![];
+    let mut opacities = vec![];
+    let first_map = text.split("</ColorMap>").next().unwrap_or(text);
+    for tag in first_map.split('<').filter_map(|tag| tag.strip_prefix("Point")) {
+        let tag = tag.split('>').next().unwrap_or(tag).replace('\'', "\"");
+        // Attributes alternate between `name=` and their quoted value
+        let mut parts = tag.split('"');
+        let mut attributes = vec![];
+        while let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+            attributes.push((name.trim().trim_end_matches('=').trim(), value));
+        }
+        let attribute = |name: &str| -> Result<Option<f32>> {
+            attributes.iter().find(|(attribute, _)| *attribute == name)
+                .map(|(_, value)| value.trim().parse::<f32>().with_context(|| format!("Attribute '{}' of a point is no number", name)))
+                .transpose()
+        };
+        let x = attribute("x")?.ok_or_else(|| anyhow!("Point of the color map has no 'x'"))?;
+        colors.push((x, [attribute("r")?.unwrap_or(0.0), attribute("g")?.unwrap_or(0.0), attribute("b")?.unwrap_or(0.0)]));
+        if let Some(opacity) = attribute("o")? {
+            opacities.push((x, opacity));
+        }
+    }
+    let opacities = if opacities.is_empty() { None } else { Some(opacities) };
+    from_points(colors, opacities)
+}
+
+/// Transfer function as DiffDVR tensor with a batch of one, `diffdvr.py` turns `tf` into a tensor and `tf_mode` into a `TFMode`
+fn diffdvr_tensor(transfer_function: &TransferFunction, mode: DiffDvrMode) -> Result<Value> {
+    let points: Vec<Vec<f32>> = match mode {
+        DiffDvrMode::Texture => (0..DIFFDVR_TEXTURE_RESOLUTION)
+            .map(|i| transfer_function.sample((i as f32 + 0.5) / DIFFDVR_TEXTURE_RESOLUTION as f32).to_vec())
+            .collect(),
+        DiffDvrMode::Linear => control_values(transfer_function).into_iter()
+            .map(|value| {
+                let [r, g, b, opacity] = transfer_function.sample(value);
+                vec![r, g, b, opacity, value]
+            })
+            .collect(),
+        DiffDvrMode::Identity | DiffDvrMode::Gaussian => bail!("Transfer functions can't be exported as DiffDVR {} tensor", mode.name()),
+    };
+    Ok(json!({ "tf_mode": mode.name(), "tf": [points] }))
+}
+
+/// Converts the first transfer function of the batch of a DiffDVR tensor to control points
+fn read_diffdvr_tensor(value: &Value) -> Result<TransferFunction> {
+    let name = value["tf_mode"].as_str().ok_or_else(|| anyhow!("'tf_mode' is no string"))?;
+    let mode = DiffDvrMode::ALL.into_iter().find(|mode| mode.name().eq_ignore_ascii_case(name))
+        .ok_or_else(|| anyhow!("Unknown DiffDVR transfer function mode '{}'", name))?;
+    let batch: Vec<Vec<Vec<f32>>> = serde_json::from_value(value["tf"].clone()).context("'tf' is no tensor of batch x points x channels")?;
+    let points = batch.into_iter().next().filter(|points| !points.is_empty()).ok_or_else(|| anyhow!("DiffDVR tensor contains no transfer function"))?;
+    if let Some(point) = points.iter().find(|point| point.len() != mode.channels()) {
+        bail!("DiffDVR {} transfer functions have {} channels, but a point has {}", mode.name(), mode.channels(), point.len());
+    }
+
+    let transfer_function = match mode {
+        DiffDvrMode::Identity => {
+            let (opacity_scale, color_scale) = (points[0][0], points[0][1]);
+            TransferFunction {
+                colors: vec![ColorPoint { value: 0.0, color: [0.0; 3] }, ColorPoint { value: 1.0, color: [color_scale; 3] }],
+                opacities: vec![OpacityPoint { value: 0.0, opacity: 0.0 }, OpacityPoint { value: 1.0, opacity: opacity_scale }],
+            }
+        },
+        DiffDvrMode::Texture => {
+            let value = |i: usize| (i as f32 + 0.5) / points.len() as f32;
+            TransferFunction {
+                colors: points.iter().enumerate().map(|(i, point)| ColorPoint { value: value(i), color: [point[0], point[1], point[2]] }).collect(),
+                opacities: points.iter().enumerate().map(|(i, point)| OpacityPoint { value: value(i), opacity: point[3] }).collect(),
+            }
+        },
+        DiffDvrMode::Linear => {
+            let mut transfer_function = TransferFunction {
+                colors: points.iter().map(|point| ColorPoint { value: point[4], color: [point[0], point[1], point[2]] }).collect(),
+                opacities: points.iter().map(|point| OpacityPoint { value: point[4], opacity: point[3] }).collect(),
+            };
+            transfer_function.sort();
+            transfer_function
+        },
+        DiffDvrMode::Gaussian => {
+            // The sum of Gaussians isn't piecewise linear, so it is sampled densely
+            let samples: Vec<(f32, [f32; 4])> = (0..GAUSSIAN_SAMPLES).map(|i| {
+                let value = i as f32 / (GAUSSIAN_SAMPLES - 1) as f32;
+                let mut sample = [0.0; 4];
+                for point in &points {
+                    let weight = (-(value - point[4]).powi(2) / (2.0 * point[5].powi(2).max(f32::EPSILON))).exp();
+                    for (channel, entry) in sample.iter_mut().enumerate() {
+                        *entry += point[channel] * weight;
+                    }
+                }
+                (value, sample)
+            }).collect();
+            TransferFunction {
+                colors: samples.iter().map(|(value, sample)| ColorPoint { value: *value, color: [sample[0], sample[1], sample[2]] }).collect(),
+                opacities: samples.iter().map(|(value, sample)| OpacityPoint { value: *value, opacity: sample[3] }).collect(),
+            }
+        },
+    };
+    Ok(transfer_function)
+}
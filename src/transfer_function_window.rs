@@ -0,0 +1,455 @@
+use egui::{emath::Rangef, pos2, vec2, Color32, ColorImage, Context, Painter, Pos2, Rect, Sense, Shape, Stroke, TextureHandle, TextureOptions, Ui};
+use glam::Vec2;
+
+use crate::{colormap::{Colormap, ColormapSelection}, transfer_function::{ColorPoint, OpacityPoint, TransferFunction}, transfer_function_preset::TransferFunctionFormat, transfer_function_2d::{GradientHistogram, TransferFunction2D, TransferFunctionWidget, WidgetShape, GRADIENT_HISTOGRAM_BINS}, voxel::grid::VoxelGrid};
+
+const OPACITY_HEIGHT: f32 = 160.0;
+const EDITOR_2D_HEIGHT: f32 = 256.0;
+const HANDLE_SIZE: f32 = 8.0;
+/// Colors that new widgets cycle through
+const WIDGET_COLORS: [[f32; 3]; 4] = [[0.9, 0.3, 0.2], [0.2, 0.6, 0.9], [0.9, 0.8, 0.3], [0.4, 0.8, 0.4]];
+const COLOR_BAR_HEIGHT: f32 = 20.0;
+const MARKER_SIZE: f32 = 10.0;
+const POINT_RADIUS: f32 = 5.0;
+/// Amount of rectangles the color bar is drawn with
+const GRADIENT_STEPS: usize = 128;
+
+/// What the user did in the window
+pub enum TransferFunctionRequest {
+    /// The transfer functions or their settings were changed
+    Update,
+    /// Load transfer functions from a file
+    Load,
+    /// Save the transfer functions to a file
+    Save(TransferFunctionFormat),
+}
+
+/// GUI window for editing the transfer functions of the volume on top of its histograms
+#[derive(Default)]
+pub struct TransferFunctionWindow {
+    /// Color control point whose color is edited
+    selected_color: Option<usize>,
+    /// Colormap the colors were set to, until they are edited by hand
+    colormap: Option<ColormapSelection>,
+    /// Widget of the 2D transfer function that is edited
+    selected_widget: Option<usize>,
+    /// Shape of the widgets that are drawn onto the 2D histogram
+    new_widget_shape: WidgetShape,
+    /// Corner where the widget that is being drawn started
+    drawing_start: Option<Vec2>,
+    /// Image of the 2D histogram and the counts it was made from
+    histogram_texture: Option<(Vec<u32>, TextureHandle)>,
+}
+
+impl TransferFunctionWindow {
+    pub fn show(&mut self, ctx: &Context, open: &mut bool, grid: &mut VoxelGrid) -> Option<TransferFunctionRequest> {
+        let mut changed = false;
+        let mut request = None;
+
+        egui::Window::new("Transfer Function").open(open).show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Load...").clicked() {
+                    request = Some(TransferFunctionRequest::Load);
+                }
+                ui.menu_button("Save As", |ui| {
+                    for format in TransferFunctionFormat::SAVE_FORMATS {
+                        if ui.button(format.name()).clicked() {
+                            request = Some(TransferFunctionRequest::Save(format));
+                            ui.close_menu();
+                        }
+                    }
+                });
+            });
+            let mut use_2d = grid.transfer_function_settings.transfer_function_2d_active();
+            ui.horizontal(|ui| {
+                changed |= ui.radio_value(&mut use_2d, false, "1D (Density)").changed();
+                changed |= ui.radio_value(&mut use_2d, true, "2D (Density and Gradient Magnitude)").changed();
+            });
+            grid.transfer_function_settings.set_transfer_function_2d_active(use_2d);
+
+            if use_2d {
+                // Computing the histogram also sets the range of the gradient axis
+                let gradient_range = grid.transfer_function_settings.gradient_range();
+                let histogram = match grid.gradient_histogram() {
+                    Ok(histogram) => Some(histogram.clone()),
+                    Err(err) => {
+                        ui.label(format!("{:#}, the gradient axis covers the largest possible gradients", err));
+                        None
+                    },
+                };
+                changed |= gradient_range != grid.transfer_function_settings.gradient_range();
+                changed |= self.editor_2d(ui, &mut grid.transfer_function_2d, histogram.as_ref());
+            } else {
+                let histogram = grid.histogram().to_vec();
+                changed |= self.editor_1d(ui, &mut grid.transfer_function, &histogram);
+            }
+        });
+
+        match changed {
+            true => Some(TransferFunctionRequest::Update),
+            false => request,
+        }
+    }
+
+    /// Clears the selected control point, widget and colormap, e.g. after other transfer functions were loaded
+    pub fn clear_selection(&mut self) {
+        self.selected_color = None;
+        self.selected_widget = None;
+        self.colormap = None;
+    }
+
+    fn editor_1d(&mut self, ui: &mut Ui, transfer_function: &mut TransferFunction, histogram: &[u32]) -> bool {
+        ui.label("Click to add a point, drag to move it and right-click to delete it");
+        let mut changed = opacity_editor(ui, transfer_function, histogram);
+        let mut colors_edited = self.color_editor(ui, transfer_function);
+        changed |= self.colormap_editor(ui, transfer_function);
+
+        ui.horizontal(|ui| {
+            if let Some(point) = self.selected_color.and_then(|index| transfer_function.colors.get_mut(index)) {
+                colors_edited |= egui::color_picker::color_edit_button_rgb(ui, &mut point.color).changed();
+                ui.label(format!("Color at {:.3}", point.value));
+            }
+            if ui.button("Reset").clicked() {
+                *transfer_function = TransferFunction::default();
+                self.selected_color = None;
+                self.colormap = None;
+                changed = true;
+            }
+        });
+        if colors_edited {
+            // Colors edited by hand no longer follow the colormap
+            self.colormap = None;
+        }
+        changed || colors_edited
+    }
+
+    /// Sets the colors to a built-in colormap, the opacity curve stays as it is
+    fn colormap_editor(&mut self, ui: &mut Ui, transfer_function: &mut TransferFunction) -> bool {
+        let previous = self.colormap;
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_label("Colormap")
+                .selected_text(self.colormap.map_or("Custom", |selection| selection.colormap.name()))
+                .show_ui(ui, |ui| {
+                    for colormap in Colormap::ALL {
+                        let selected = self.colormap.is_some_and(|selection| selection.colormap == colormap);
+                        if ui.selectable_label(selected, colormap.name()).clicked() {
+                            // Switching between colormaps keeps the direction and the range
+                            self.colormap.get_or_insert(ColormapSelection::new(colormap)).colormap = colormap;
+                        }
+                    }
+                });
+            if let Some(selection) = &mut self.colormap {
+                ui.checkbox(&mut selection.reversed, "Reversed");
+                ui.add(egui::DragValue::new(&mut selection.min).clamp_range(0.0..=selection.max).speed(0.005).prefix("From "));
+                ui.add(egui::DragValue::new(&mut selection.max).clamp_range(selection.min..=1.0).speed(0.005).prefix("To "));
+            }
+        });
+
+        match self.colormap {
+            Some(selection) if self.colormap != previous => {
+                transfer_function.colors = selection.color_points();
+                self.selected_color = None;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Draws the widgets over the histogram of density (x) and gradient magnitude (y)
+    fn editor_2d(&mut self, ui: &mut Ui, transfer_function: &mut TransferFunction2D, histogram: Option<&GradientHistogram>) -> bool {
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            ui.label("Draw a");
+            ui.radio_value(&mut self.new_widget_shape, WidgetShape::Rectangle, "Rectangle");
+            ui.radio_value(&mut self.new_widget_shape, WidgetShape::Triangle, "Triangle");
+            ui.radio_value(&mut self.new_widget_shape, WidgetShape::Gaussian, "Gaussian");
+        });
+        ui.label("Drag on the histogram to draw a widget, drag a widget or its corners to change it and right-click to delete it");
+
+        let (response, painter) = ui.allocate_painter(vec2(ui.available_width(), EDITOR_2D_HEIGHT), Sense::click_and_drag());
+        let rect = response.rect;
+        let to_screen = |position: Vec2| pos2(rect.left() + position.x * rect.width(), rect.bottom() - position.y * rect.height());
+        let from_screen = |position: Pos2| Vec2::new(
+            (position.x - rect.left()) / rect.width(),
+            (rect.bottom() - position.y) / rect.height(),
+        ).clamp(Vec2::ZERO, Vec2::ONE);
+        let to_normalized = |delta: egui::Vec2| Vec2::new(delta.x / rect.width(), -delta.y / rect.height());
+
+        let mut remove = None;
+        for (i, widget) in transfer_function.widgets.iter_mut().enumerate() {
+            let widget_rect = Rect::from_two_pos(to_screen(widget.min), to_screen(widget.max));
+            let widget_response = ui.interact(widget_rect, response.id.with(("widget", i)), Sense::click_and_drag());
+            if widget_response.clicked() || widget_response.drag_started() {
+                self.selected_widget = Some(i);
+            }
+            if widget_response.dragged() {
+                // Moves the whole box without leaving the histogram
+                let delta = to_normalized(widget_response.drag_delta()).clamp(-widget.min, Vec2::ONE - widget.max);
+                widget.min += delta;
+                widget.max += delta;
+                changed = true;
+            }
+            if widget_response.secondary_clicked() {
+                remove = Some(i);
+            }
+
+            if self.selected_widget == Some(i) {
+                for corner in [widget.min, widget.max] {
+                    let handle = Rect::from_center_size(to_screen(corner), vec2(HANDLE_SIZE, HANDLE_SIZE));
+                    let handle_response = ui.interact(handle, response.id.with(("corner", i, corner == widget.min)), Sense::drag());
+                    if let (true, Some(position)) = (handle_response.dragged(), handle_response.interact_pointer_pos()) {
+                        let position = from_screen(position);
+                        if corner == widget.min {
+                            widget.min = position.min(widget.max);
+                        } else {
+                            widget.max = position.max(widget.min);
+                        }
+                        changed = true;
+                    }
+                }
+            }
+        }
+        if let Some(i) = remove {
+            transfer_function.widgets.remove(i);
+            self.selected_widget = None;
+            changed = true;
+        }
+
+        // Dragging over empty space draws a new widget
+        if response.drag_started() {
+            self.drawing_start = ui.input(|input| input.pointer.press_origin()).map(from_screen);
+        }
+        let drawn = self.drawing_start.zip(response.interact_pointer_pos().map(from_screen));
+        if let (true, Some((start, end))) = (response.drag_stopped(), drawn) {
+            if (start - end).abs().min_element() > 0.01 {
+                transfer_function.widgets.push(TransferFunctionWidget {
+                    shape: self.new_widget_shape,
+                    min: start.min(end),
+                    max: start.max(end),
+                    color: WIDGET_COLORS[transfer_function.widgets.len() % WIDGET_COLORS.len()],
+                    opacity: 0.5,
+                });
+                self.selected_widget = Some(transfer_function.widgets.len() - 1);
+                changed = true;
+            }
+            self.drawing_start = None;
+        }
+
+        painter.rect_filled(rect, 0.0, ui.visuals().extreme_bg_color);
+        if let Some(histogram) = histogram {
+            let texture = self.histogram_texture(ui.ctx(), &histogram.counts);
+            painter.image(texture.id(), rect, Rect::from_min_max(Pos2::ZERO, pos2(1.0, 1.0)), Color32::WHITE);
+        }
+        for (i, widget) in transfer_function.widgets.iter().enumerate() {
+            let stroke = match self.selected_widget == Some(i) {
+                true => Stroke::new(2.0, ui.visuals().selection.stroke.color),
+                false => Stroke::new(1.0, ui.visuals().text_color()),
+            };
+            draw_widget(&painter, widget, to_screen, stroke);
+            if self.selected_widget == Some(i) {
+                for corner in [widget.min, widget.max] {
+                    painter.rect_filled(Rect::from_center_size(to_screen(corner), vec2(HANDLE_SIZE, HANDLE_SIZE)), 0.0, stroke.color);
+                }
+            }
+        }
+        if let (true, Some((start, end))) = (response.dragged(), drawn) {
+            painter.rect_stroke(Rect::from_two_pos(to_screen(start), to_screen(end)), 0.0, Stroke::new(1.0, ui.visuals().text_color()));
+        }
+
+        ui.horizontal(|ui| {
+            if let Some(widget) = self.selected_widget.and_then(|index| transfer_function.widgets.get_mut(index)) {
+                egui::ComboBox::from_id_source("widget_shape")
+                    .selected_text(format!("{:?}", widget.shape))
+                    .show_ui(ui, |ui| {
+                        for shape in [WidgetShape::Rectangle, WidgetShape::Triangle, WidgetShape::Gaussian] {
+                            changed |= ui.selectable_value(&mut widget.shape, shape, format!("{:?}", shape)).changed();
+                        }
+                    });
+                changed |= egui::color_picker::color_edit_button_rgb(ui, &mut widget.color).changed();
+                changed |= ui.add(egui::Slider::new(&mut widget.opacity, 0.0..=1.0).text("Opacity")).changed();
+            }
+            if ui.button("Reset").clicked() {
+                *transfer_function = TransferFunction2D::default();
+                self.selected_widget = None;
+                changed = true;
+            }
+        });
+        changed
+    }
+
+    /// Image of the 2D histogram with logarithmic brightness, made again when the counts change
+    fn histogram_texture(&mut self, ctx: &Context, counts: &[u32]) -> &TextureHandle {
+        if !matches!(&self.histogram_texture, Some((cached, _)) if cached == counts) {
+            let bins = GRADIENT_HISTOGRAM_BINS;
+            let scale = (counts.iter().copied().max().unwrap_or(0) as f32).ln_1p().max(f32::EPSILON);
+            // Rows of the image run from the top, so the largest gradients come first
+            let pixels = (0..bins * bins).map(|i| {
+                let count = counts[i % bins + bins * (bins - 1 - i / bins)];
+                Color32::from_gray(((count as f32).ln_1p() / scale * 255.0) as u8)
+            }).collect();
+            let image = ColorImage { size: [bins, bins], pixels };
+            let texture = ctx.load_texture("gradient_histogram", image, TextureOptions::NEAREST);
+            self.histogram_texture = Some((counts.to_vec(), texture));
+        }
+        &self.histogram_texture.as_ref().expect("Histogram texture was just created").1
+    }
+
+    /// Draws the colors as a bar with a marker below every control point
+    fn color_editor(&mut self, ui: &mut Ui, transfer_function: &mut TransferFunction) -> bool {
+        let mut changed = false;
+        let (response, painter) = ui.allocate_painter(vec2(ui.available_width(), COLOR_BAR_HEIGHT + MARKER_SIZE), Sense::click());
+        let bar = Rect::from_min_size(response.rect.min, vec2(response.rect.width(), COLOR_BAR_HEIGHT));
+
+        let mut remove = None;
+        let len = transfer_function.colors.len();
+        for i in 0..len {
+            let x = bar.left() + transfer_function.colors[i].value * bar.width();
+            let marker = Rect::from_min_size(pos2(x - MARKER_SIZE / 2.0, bar.bottom()), vec2(MARKER_SIZE, MARKER_SIZE));
+            let marker_response = ui.interact(marker, response.id.with(i), Sense::click_and_drag());
+            if marker_response.clicked() || marker_response.drag_started() {
+                self.selected_color = Some(i);
+            }
+            if let (true, Some(position)) = (marker_response.dragged(), marker_response.interact_pointer_pos()) {
+                let (min, max) = neighbor_range(&transfer_function.colors.iter().map(|point| point.value).collect::<Vec<_>>(), i);
+                transfer_function.colors[i].value = ((position.x - bar.left()) / bar.width()).clamp(min, max);
+                changed = true;
+            }
+            if marker_response.secondary_clicked() && len > 1 {
+                remove = Some(i);
+            }
+        }
+        if let Some(i) = remove {
+            transfer_function.colors.remove(i);
+            self.selected_color = None;
+            changed = true;
+        }
+
+        if let (true, Some(position)) = (response.clicked(), response.interact_pointer_pos()) {
+            let value = ((position.x - bar.left()) / bar.width()).clamp(0.0, 1.0);
+            let color = transfer_function.color(value);
+            transfer_function.colors.push(ColorPoint { value, color });
+            transfer_function.sort();
+            self.selected_color = transfer_function.colors.iter().position(|point| point.value == value);
+            changed = true;
+        }
+
+        for step in 0..GRADIENT_STEPS {
+            let x = |step: usize| bar.left() + step as f32 / GRADIENT_STEPS as f32 * bar.width();
+            let value = (step as f32 + 0.5) / GRADIENT_STEPS as f32;
+            painter.rect_filled(Rect::from_x_y_ranges(Rangef::new(x(step), x(step + 1)), bar.y_range()), 0.0, color32(transfer_function.color(value)));
+        }
+        let stroke = Stroke::new(1.0, ui.visuals().text_color());
+        for (i, point) in transfer_function.colors.iter().enumerate() {
+            let x = bar.left() + point.value * bar.width();
+            let tip = pos2(x, bar.bottom());
+            let marker = vec![tip, tip + vec2(MARKER_SIZE / 2.0, MARKER_SIZE), tip + vec2(-MARKER_SIZE / 2.0, MARKER_SIZE)];
+            let stroke = if self.selected_color == Some(i) { Stroke::new(2.0, ui.visuals().selection.stroke.color) } else { stroke };
+            painter.add(Shape::convex_polygon(marker, color32(point.color), stroke));
+        }
+
+        changed
+    }
+}
+
+/// Draws the opacity curve over the histogram, the area is 0 to 1 along both axes
+fn opacity_editor(ui: &mut Ui, transfer_function: &mut TransferFunction, histogram: &[u32]) -> bool {
+    let mut changed = false;
+    let (response, painter) = ui.allocate_painter(vec2(ui.available_width(), OPACITY_HEIGHT), Sense::click());
+    let rect = response.rect;
+    let to_screen = |value: f32, opacity: f32| pos2(rect.left() + value * rect.width(), rect.bottom() - opacity * rect.height());
+    let from_screen = |position: Pos2| (
+        ((position.x - rect.left()) / rect.width()).clamp(0.0, 1.0),
+        ((rect.bottom() - position.y) / rect.height()).clamp(0.0, 1.0),
+    );
+
+    let mut remove = None;
+    let len = transfer_function.opacities.len();
+    for i in 0..len {
+        let point = transfer_function.opacities[i];
+        let handle = Rect::from_center_size(to_screen(point.value, point.opacity), vec2(4.0 * POINT_RADIUS, 4.0 * POINT_RADIUS));
+        let point_response = ui.interact(handle, response.id.with(i), Sense::click_and_drag());
+        if let (true, Some(position)) = (point_response.dragged(), point_response.interact_pointer_pos()) {
+            let (value, opacity) = from_screen(position);
+            let (min, max) = neighbor_range(&transfer_function.opacities.iter().map(|point| point.value).collect::<Vec<_>>(), i);
+            transfer_function.opacities[i] = OpacityPoint { value: value.clamp(min, max), opacity };
+            changed = true;
+        }
+        if point_response.secondary_clicked() && len > 1 {
+            remove = Some(i);
+        }
+    }
+    if let Some(i) = remove {
+        transfer_function.opacities.remove(i);
+        changed = true;
+    }
+
+    if let (true, Some(position)) = (response.clicked(), response.interact_pointer_pos()) {
+        let (value, opacity) = from_screen(position);
+        transfer_function.opacities.push(OpacityPoint { value, opacity });
+        transfer_function.sort();
+        changed = true;
+    }
+
+    painter.rect_filled(rect, 0.0, ui.visuals().extreme_bg_color);
+    draw_histogram(&painter, rect, histogram, ui.visuals().weak_text_color().gamma_multiply(0.5));
+
+    let mut curve = vec![to_screen(0.0, transfer_function.opacity(0.0))];
+    curve.extend(transfer_function.opacities.iter().map(|point| to_screen(point.value, point.opacity)));
+    curve.push(to_screen(1.0, transfer_function.opacity(1.0)));
+    let stroke = Stroke::new(1.5, ui.visuals().text_color());
+    painter.add(Shape::line(curve, stroke));
+    for point in &transfer_function.opacities {
+        painter.circle(to_screen(point.value, point.opacity), POINT_RADIUS, color32(transfer_function.color(point.value)), Stroke::new(1.0, stroke.color));
+    }
+
+    changed
+}
+
+/// Draws the outline of a widget filled with its color at its opacity
+fn draw_widget(painter: &Painter, widget: &TransferFunctionWidget, to_screen: impl Fn(Vec2) -> Pos2, stroke: Stroke) {
+    let [r, g, b] = widget.color.map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8);
+    let fill = Color32::from_rgba_unmultiplied(r, g, b, (widget.opacity.clamp(0.0, 1.0) * 160.0) as u8);
+    let (min, max) = (to_screen(widget.min), to_screen(widget.max));
+    let rect = Rect::from_two_pos(min, max);
+    match widget.shape {
+        WidgetShape::Rectangle => {
+            painter.rect(rect, 0.0, fill, stroke);
+        },
+        WidgetShape::Triangle => {
+            let tip = pos2(rect.center().x, min.y);
+            painter.add(Shape::convex_polygon(vec![tip, pos2(max.x, max.y), pos2(min.x, max.y)], fill, stroke));
+        },
+        WidgetShape::Gaussian => {
+            // The box spans two standard deviations to each side
+            painter.add(Shape::ellipse_filled(rect.center(), rect.size() / 4.0, fill));
+            painter.add(Shape::ellipse_stroke(rect.center(), rect.size() / 2.0, stroke));
+        },
+    }
+}
+
+/// Draws the histogram with logarithmic heights, so that the rare densities of thin structures stay visible next to the empty space
+fn draw_histogram(painter: &Painter, rect: Rect, histogram: &[u32], color: Color32) {
+    let max = histogram.iter().copied().max().unwrap_or(0);
+    if max == 0 {
+        return;
+    }
+    let scale = (max as f32).ln_1p();
+    let width = rect.width() / histogram.len() as f32;
+    for (i, count) in histogram.iter().enumerate() {
+        let height = (*count as f32).ln_1p() / scale * rect.height();
+        let left = rect.left() + i as f32 * width;
+        painter.rect_filled(Rect::from_x_y_ranges(Rangef::new(left, left + width), Rangef::new(rect.bottom() - height, rect.bottom())), 0.0, color);
+    }
+}
+
+/// Values a control point can be moved to without passing its neighbors
+fn neighbor_range(values: &[f32], index: usize) -> (f32, f32) {
+    let min = if index > 0 { values[index - 1] } else { 0.0 };
+    let max = values.get(index + 1).copied().unwrap_or(1.0);
+    (min, max)
+}
+
+fn color32(color: [f32; 3]) -> Color32 {
+    let [r, g, b] = color.map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8);
+    Color32::from_rgb(r, g, b)
+}
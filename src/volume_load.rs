@@ -0,0 +1,242 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Result};
+use glam::{UVec3, Vec3};
+use wgpu::{Device, Queue};
+
+use crate::{loaders::{dicom::{list_series, DicomSeries}, progress::LoadProgress, registry::LoaderRegistry, stream::{Slab, SlabSink, SliceDownsampler}, vtk::{read_image, VtkImage}}, time_series::{TimeSeries, TimeSeriesSource}, voxel::{format::VoxelFormat, grid::VoxelGrid, volume::VolumeData}};
+
+/// Amount of slabs that are kept in memory between the worker and the upload to the GPU
+const SLAB_QUEUE_LEN: usize = 4;
+
+/// Messages sent from the worker thread to the GUI
+enum LoadMessage {
+    /// A volume that was read at once
+    Volume(VolumeData),
+    /// Size and format of a streamed volume, sent before its first slab, and its original size if it was downsampled to fit the device
+    Start { dimensions: UVec3, spacing: Vec3, format: VoxelFormat, downsampled_from: Option<UVec3> },
+    /// Voxels of consecutive z slices of a streamed volume
+    Slab { z: u32, slab: Slab },
+    /// All slabs of a streamed volume were sent
+    Finished,
+    /// Series that were found in a scanned DICOM folder
+    DicomSeries(Vec<DicomSeries>),
+    /// First frame of a time series, together with its source that knows the value range of the frames
+    TimeSeries { source: TimeSeriesSource, first_frame: VolumeData },
+    /// Arrays of a VTK file
+    VtkImage(Box<VtkImage>),
+}
+
+/// What a finished load produced
+pub enum LoadResult {
+    Grid(Box<VoxelGrid>),
+    /// Grid of the first frame of a time series, the following frames are read once playback starts
+    TimeSeries { grid: Box<VoxelGrid>, source: TimeSeriesSource, first_frame: Arc<VolumeData> },
+    /// Series of a scanned DICOM folder, one of which the user picks to be loaded
+    DicomSeries(Vec<DicomSeries>),
+    /// Arrays of a VTK file, one of which the user picks to be loaded
+    VtkImage(Box<VtkImage>),
+}
+
+/// Forwards the slabs of a streamed volume to the GUI, blocking while the GUI is still uploading earlier slabs.
+/// Volumes that exceed the 3D texture limit of the device are downsampled on the way.
+struct ChannelSink {
+    sender: flume::Sender<Result<LoadMessage>>,
+    max_dimension: u32,
+    downsampler: Option<SliceDownsampler>,
+    /// First z slice of the next downsampled slab
+    downsampled_z: u32,
+}
+
+impl ChannelSink {
+    fn send(&self, message: LoadMessage) -> Result<()> {
+        if self.sender.send(Ok(message)).is_err() {
+            bail!("Loading was cancelled");
+        }
+        Ok(())
+    }
+}
+
+impl SlabSink for ChannelSink {
+    fn start(&mut self, dimensions: UVec3, spacing: Vec3, format: VoxelFormat) -> Result<()> {
+        if dimensions.max_element() <= self.max_dimension {
+            return self.send(LoadMessage::Start { dimensions, spacing, format, downsampled_from: None });
+        }
+
+        let downsampler = SliceDownsampler::new(dimensions, self.max_dimension);
+        let message = LoadMessage::Start {
+            dimensions: downsampler.output_dimensions(),
+            spacing: spacing * downsampler.factor() as f32,
+            format,
+            downsampled_from: Some(dimensions),
+        };
+        self.downsampler = Some(downsampler);
+        self.send(message)
+    }
+
+    fn write_slab(&mut self, z: u32, slab: Slab) -> Result<()> {
+        let Some(downsampler) = &mut self.downsampler else {
+            return self.send(LoadMessage::Slab { z, slab });
+        };
+
+        let slab = downsampler.add_slab(&slab);
+        if slab.voxel_count() == 0 {
+            return Ok(());
+        }
+        let output_dimensions = downsampler.output_dimensions();
+        let z = self.downsampled_z;
+        self.downsampled_z += (slab.voxel_count() / (output_dimensions.x as usize * output_dimensions.y as usize)) as u32;
+        self.send(LoadMessage::Slab { z, slab })
+    }
+}
+
+/// A volume that is read on a worker thread, so that the window stays responsive while large files are loaded
+pub struct VolumeLoad {
+    /// Window title once the volume is loaded
+    pub title: String,
+    /// Source path of the loaded Voxel Grid
+    pub source_path: Option<String>,
+    /// Whether the render settings stored in the NetCDF file at the source path are restored
+    pub restore_netcdf_settings: bool,
+    progress: Arc<LoadProgress>,
+    receiver: flume::Receiver<Result<LoadMessage>>,
+    /// Grid that the slabs of a streamed volume are uploaded to until it is complete
+    streamed_grid: Option<VoxelGrid>,
+}
+
+impl VolumeLoad {
+    /// Starts reading a volume on a new thread
+    pub fn spawn<F>(title: &str, source_path: Option<String>, read: F) -> Self
+    where F: FnOnce(&LoadProgress) -> Result<VolumeData> + Send + 'static {
+        Self::start(title, source_path, u32::MAX, flume::bounded(1), move |progress, _| read(progress).map(LoadMessage::Volume))
+    }
+
+    /// Starts streaming a volume on a new thread. Its slabs are uploaded to the GPU as they arrive,
+    /// so that only a few of them are kept in memory at once.
+    /// Volumes with more than `max_dimension` voxels along an axis are downsampled until they fit,
+    /// bricking only applies to volumes that [`VolumeLoad::spawn`] reads into memory.
+    pub fn stream<F>(title: &str, source_path: Option<String>, max_dimension: u32, read: F) -> Self
+    where F: FnOnce(&LoadProgress, &mut dyn SlabSink) -> Result<()> + Send + 'static {
+        Self::start(title, source_path, max_dimension, flume::bounded(SLAB_QUEUE_LEN), move |progress, sink| {
+            read(progress, sink)?;
+            Ok(LoadMessage::Finished)
+        })
+    }
+
+    /// Starts scanning a DICOM folder for its series on a new thread, since every file of it is opened
+    pub fn scan_dicom(directory: &str) -> Self {
+        let path = directory.to_string();
+        Self::start(directory, Some(directory.to_string()), u32::MAX, flume::bounded(1), move |progress, _| list_series(&path, progress).map(LoadMessage::DicomSeries))
+    }
+
+    /// Starts reading the first frame of a time series on a new thread, files are read with the given loaders
+    pub fn time_series(source: TimeSeriesSource, file_loaders: Arc<LoaderRegistry>) -> Self {
+        let title = source.frame_name(0);
+        let source_path = Some(source.path(0).to_string());
+        Self::start(&title, source_path, u32::MAX, flume::bounded(1), move |progress, _| {
+            let mut source = source;
+            let first_frame = TimeSeries::read_first_frame(&mut source, &file_loaders, progress)?;
+            Ok(LoadMessage::TimeSeries { source, first_frame })
+        })
+    }
+
+    /// Starts reading the arrays of a VTK file on a new thread
+    pub fn scan_vtk(path: &str) -> Self {
+        let file_path = path.to_string();
+        Self::start(path, Some(path.to_string()), u32::MAX, flume::bounded(1), move |progress, _| {
+            read_image(&file_path, progress).map(|image| LoadMessage::VtkImage(Box::new(image)))
+        })
+    }
+
+    fn start<F>(title: &str, source_path: Option<String>, max_dimension: u32, channel: (flume::Sender<Result<LoadMessage>>, flume::Receiver<Result<LoadMessage>>), read: F) -> Self
+    where F: FnOnce(&LoadProgress, &mut ChannelSink) -> Result<LoadMessage> + Send + 'static {
+        let (sender, receiver) = channel;
+        let progress = Arc::new(LoadProgress::default());
+        let worker_progress = progress.clone();
+        std::thread::spawn(move || {
+            let mut sink = ChannelSink { sender, max_dimension, downsampler: None, downsampled_z: 0 };
+            let result = read(&worker_progress, &mut sink);
+            // The receiver is gone if the load was replaced in the meantime
+            let _ = sink.sender.send(result);
+        });
+
+        Self {
+            title: title.to_string(),
+            source_path,
+            restore_netcdf_settings: false,
+            progress,
+            receiver,
+            streamed_grid: None,
+        }
+    }
+
+    pub fn progress(&self) -> &LoadProgress {
+        &self.progress
+    }
+
+    /// Asks the loader to stop. Loaders stop at their next progress report and their result is discarded.
+    pub fn cancel(&self) {
+        self.progress.cancel();
+    }
+
+    /// Uploads the slabs that arrived since the last call.
+    /// Returns the result or the error once the worker has finished.
+    pub fn poll(&mut self, device: &Device, queue: &Queue) -> Option<Result<LoadResult>> {
+        // Limits the uploads per frame, so that the window keeps drawing while a fast loader streams
+        for _ in 0..SLAB_QUEUE_LEN {
+            let message = match self.receiver.try_recv() {
+                Ok(Ok(message)) => message,
+                Ok(Err(err)) => return Some(Err(err)),
+                Err(flume::TryRecvError::Empty) => return None,
+                Err(flume::TryRecvError::Disconnected) => return Some(Err(anyhow!("Loading '{}' stopped unexpectedly", self.title))),
+            };
+
+            match message {
+                LoadMessage::Volume(volume) => return Some(Ok(LoadResult::Grid(Box::new(self.create_grid(volume, device, queue))))),
+                LoadMessage::TimeSeries { source, first_frame } => {
+                    let first_frame = Arc::new(first_frame);
+                    let grid = Box::new(self.create_grid(first_frame.as_ref().clone(), device, queue));
+                    return Some(Ok(LoadResult::TimeSeries { grid, source, first_frame }));
+                },
+                LoadMessage::Start { dimensions, spacing, format, downsampled_from } => match VoxelGrid::streamed(dimensions, spacing, format, device, queue) {
+                    Ok(grid) => {
+                        if let Some(original) = downsampled_from {
+                            self.progress.notice(format!(
+                                "The volume has a size of {}, but the device supports at most {} voxels per side. It was downsampled to {}, \
+                                because volumes that are streamed from the file aren't kept in memory and can't be split into bricks.",
+                                original, device.limits().max_texture_dimension_3d, dimensions
+                            ));
+                        }
+                        self.streamed_grid = Some(grid);
+                    },
+                    // Dropping the load disconnects the worker, which stops at its next slab
+                    Err(err) => return Some(Err(err)),
+                },
+                LoadMessage::Slab { z, slab } => {
+                    if let Some(grid) = &mut self.streamed_grid {
+                        grid.write_slab(z, &slab, queue);
+                    }
+                },
+                LoadMessage::Finished => {
+                    return Some(self.streamed_grid.take().map(|grid| LoadResult::Grid(Box::new(grid))).ok_or_else(|| anyhow!("Loading '{}' finished without a volume", self.title)));
+                },
+                LoadMessage::DicomSeries(series) => return Some(Ok(LoadResult::DicomSeries(series))),
+                LoadMessage::VtkImage(image) => return Some(Ok(LoadResult::VtkImage(image))),
+            }
+        }
+        None
+    }
+
+    /// Uploads a volume that was read at once, telling the user if it had to be split into bricks
+    fn create_grid(&self, volume: VolumeData, device: &Device, queue: &Queue) -> VoxelGrid {
+        let grid = VoxelGrid::from_volume_data(volume, device, queue);
+        if let Some(layout) = grid.brick_layout() {
+            self.progress.notice(format!(
+                "The volume has a size of {}, but the device supports at most {} voxels per side. It was split into {} bricks, \
+                of which the {} closest visible ones are kept on the GPU.",
+                grid.dimensions, device.limits().max_texture_dimension_3d, layout.brick_count(), layout.slot_count()
+            ));
+        }
+        grid
+    }
+}
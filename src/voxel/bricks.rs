@@ -0,0 +1,251 @@
+use std::collections::HashSet;
+
+use glam::{Mat4, UVec3, Vec3, Vec4};
+use wgpu::{Buffer, Queue};
+
+use crate::texture_3d::Texture3D;
+
+/// Voxels per side of a brick as it is stored in the atlas, including a border of one voxel on every side.
+/// The border holds the neighbouring voxels, so that linear filtering doesn't show seams between bricks.
+pub const BRICK_SIZE: u32 = 64;
+
+/// Voxels per side of the part of a volume that a single brick covers
+pub const BRICK_CORE: u32 = BRICK_SIZE - 2;
+
+/// Upper bound for the bytes of the brick atlas on the GPU
+const ATLAS_BYTES: u64 = 512 << 20;
+
+/// Amount of bricks that are uploaded per frame, so that the window keeps drawing while bricks stream in
+const MAX_UPLOADS_PER_FRAME: usize = 16;
+
+/// Page table entry of a brick that is not in the atlas
+const NOT_RESIDENT: u32 = 0;
+
+/// Page table entry of a brick without any visible voxel, which is never uploaded.
+/// Entries of resident bricks are their atlas slot plus two.
+const EMPTY: u32 = 1;
+
+/// How a volume is split into bricks and how many of them fit into the atlas at once
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BrickLayout {
+    /// Amount of bricks along each axis of the volume
+    pub brick_counts: UVec3,
+    /// Amount of brick slots along each axis of the atlas
+    pub atlas_slots: UVec3,
+}
+
+impl BrickLayout {
+    /// Splits a volume into bricks and fits as many slots into the atlas as the device limit and the memory budget allow
+    pub fn new(dimensions: UVec3, bytes_per_voxel: usize, max_texture_dimension: u32) -> Self {
+        let brick_counts = (dimensions + BRICK_CORE - 1) / BRICK_CORE;
+        let brick_bytes = BRICK_SIZE as u64 * BRICK_SIZE as u64 * BRICK_SIZE as u64 * bytes_per_voxel as u64;
+        let budget_slots = (ATLAS_BYTES / brick_bytes).max(1);
+        let slots_per_axis = ((budget_slots as f64).cbrt().floor() as u32)
+            .clamp(1, (max_texture_dimension / BRICK_SIZE).max(1));
+
+        // The atlas never needs more slots along an axis than there are bricks in the volume
+        let atlas_slots = UVec3::splat(slots_per_axis).min(brick_counts);
+        Self { brick_counts, atlas_slots }
+    }
+
+    pub fn brick_count(&self) -> usize {
+        self.brick_counts.x as usize * self.brick_counts.y as usize * self.brick_counts.z as usize
+    }
+
+    pub fn slot_count(&self) -> usize {
+        self.atlas_slots.x as usize * self.atlas_slots.y as usize * self.atlas_slots.z as usize
+    }
+
+    /// Size of the atlas texture in voxels
+    pub fn atlas_size(&self) -> UVec3 {
+        self.atlas_slots * BRICK_SIZE
+    }
+
+    fn brick_position(&self, brick: usize) -> UVec3 {
+        let counts = self.brick_counts;
+        let brick = brick as u32;
+        UVec3::new(brick % counts.x, brick / counts.x % counts.y, brick / (counts.x * counts.y))
+    }
+
+    fn slot_position(&self, slot: usize) -> UVec3 {
+        let slots = self.atlas_slots;
+        let slot = slot as u32;
+        UVec3::new(slot % slots.x, slot / slots.x % slots.y, slot / (slots.x * slots.y))
+    }
+}
+
+/// Keeps the bricks of a volume that is too large for a single 3D texture in an atlas.
+/// Bricks are uploaded from the voxels in host memory once they become visible, the closest ones first.
+/// The pool doesn't hold the voxels itself, so that it works with every voxel format.
+pub struct BrickPool {
+    pub layout: BrickLayout,
+    /// Atlas entry of every brick, uploaded to the page table buffer the raymarcher samples through
+    page_table: Vec<u32>,
+    /// Brick held by every atlas slot
+    slots: Vec<Option<usize>>,
+    /// View-projection matrix the resident bricks were chosen for
+    last_view_proj: Option<Mat4>,
+}
+
+impl BrickPool {
+    /// `is_visible` tells whether the voxel at an index has a non-zero alpha
+    pub fn new(dimensions: UVec3, bytes_per_voxel: usize, max_texture_dimension: u32, is_visible: impl Fn(usize) -> bool) -> Self {
+        let layout = BrickLayout::new(dimensions, bytes_per_voxel, max_texture_dimension);
+        let mut pool = Self {
+            layout,
+            page_table: vec![],
+            slots: vec![],
+            last_view_proj: None,
+        };
+        pool.reset(dimensions, is_visible);
+        pool
+    }
+
+    /// Forgets all resident bricks, e.g. after the voxels were replaced, and finds the empty ones again
+    pub fn reset(&mut self, dimensions: UVec3, is_visible: impl Fn(usize) -> bool) {
+        self.page_table = occupied_bricks(dimensions, is_visible, &self.layout).into_iter()
+            .map(|occupied| if occupied { NOT_RESIDENT } else { EMPTY })
+            .collect();
+        self.slots = vec![None; self.layout.slot_count()];
+        self.last_view_proj = None;
+    }
+
+    /// Amount of bricks in the atlas and amount of bricks with visible voxels
+    pub fn residency(&self) -> (usize, usize) {
+        let resident = self.slots.iter().filter(|slot| slot.is_some()).count();
+        let occupied = self.page_table.iter().filter(|entry| **entry != EMPTY).count();
+        (resident, occupied)
+    }
+
+    /// Uploads the visible bricks closest to the camera and evicts bricks that are no longer needed.
+    /// `brick_bytes` returns the texels of the brick at a position including its border, see [`brick_voxels`].
+    /// Returns whether the page table changed and has to be uploaded again.
+    #[allow(clippy::too_many_arguments)]
+    pub fn update(&mut self, view_proj: Mat4, camera_position: Vec3, box_min: Vec3, box_size: Vec3, dimensions: UVec3, brick_bytes: impl Fn(UVec3) -> Vec<u8>, atlas: &Texture3D, queue: &Queue) -> bool {
+        if self.last_view_proj == Some(view_proj) {
+            return false;
+        }
+
+        let voxel_size = box_size / dimensions.as_vec3();
+        let mut visible: Vec<(f32, usize)> = (0..self.layout.brick_count())
+            .filter(|brick| self.page_table[*brick] != EMPTY)
+            .filter_map(|brick| {
+                let min = (self.layout.brick_position(brick) * BRICK_CORE).min(dimensions);
+                let max = (min + BRICK_CORE).min(dimensions);
+                let world_min = box_min + min.as_vec3() * voxel_size;
+                let world_max = box_min + max.as_vec3() * voxel_size;
+                in_frustum(view_proj, world_min, world_max)
+                    .then(|| (((world_min + world_max) / 2.0).distance_squared(camera_position), brick))
+            })
+            .collect();
+        visible.sort_by(|a, b| a.0.total_cmp(&b.0));
+        visible.truncate(self.slots.len());
+        let wanted: HashSet<usize> = visible.iter().map(|(_, brick)| *brick).collect();
+
+        // Slots that are free or hold a brick that is out of view, in reverse order of use
+        let mut free_slots: Vec<usize> = self.slots.iter().enumerate()
+            .filter(|(_, brick)| !matches!(brick, Some(brick) if wanted.contains(brick)))
+            .map(|(slot, _)| slot)
+            .rev()
+            .collect();
+
+        let mut changed = false;
+        let mut uploads = 0;
+        let mut complete = true;
+        for (_, brick) in visible {
+            if self.page_table[brick] != NOT_RESIDENT {
+                continue;
+            }
+            if uploads == MAX_UPLOADS_PER_FRAME {
+                complete = false;
+                break;
+            }
+            let Some(slot) = free_slots.pop() else { break };
+            if let Some(evicted) = self.slots[slot] {
+                self.page_table[evicted] = NOT_RESIDENT;
+            }
+
+            let bytes = brick_bytes(self.layout.brick_position(brick));
+            atlas.write_region(queue, &bytes, self.layout.slot_position(slot) * BRICK_SIZE, UVec3::splat(BRICK_SIZE));
+            self.slots[slot] = Some(brick);
+            self.page_table[brick] = slot as u32 + 2;
+            uploads += 1;
+            changed = true;
+        }
+
+        // Bricks that didn't fit into this frame's uploads are handled in the next one, even if the camera stands still
+        self.last_view_proj = if complete { Some(view_proj) } else { None };
+        changed
+    }
+
+    pub fn write_page_table(&self, buffer: &Buffer, queue: &Queue) {
+        queue.write_buffer(buffer, 0, bytemuck::cast_slice(&self.page_table));
+    }
+
+    pub fn page_table_len(&self) -> usize {
+        self.page_table.len()
+    }
+}
+
+/// Finds the bricks that contain a visible voxel in their core or border
+fn occupied_bricks(dimensions: UVec3, is_visible: impl Fn(usize) -> bool, layout: &BrickLayout) -> Vec<bool> {
+    // A brick stores the voxels from one before its core up to one after it, so a voxel lies in up to two bricks per axis
+    let brick_range = |v: u32, count: u32| (v.saturating_sub(1) / BRICK_CORE)..=((v + 1) / BRICK_CORE).min(count - 1);
+
+    let counts = layout.brick_counts;
+    let mut occupied = vec![false; layout.brick_count()];
+    let mut index = 0;
+    for z in 0..dimensions.z {
+        for y in 0..dimensions.y {
+            for x in 0..dimensions.x {
+                if is_visible(index) {
+                    for bz in brick_range(z, counts.z) {
+                        for by in brick_range(y, counts.y) {
+                            for bx in brick_range(x, counts.x) {
+                                occupied[(bx + counts.x * (by + counts.y * bz)) as usize] = true;
+                            }
+                        }
+                    }
+                }
+                index += 1;
+            }
+        }
+    }
+    occupied
+}
+
+/// Copies the voxels of a brick including its border, voxels outside the volume repeat the closest voxel at the edge
+pub fn brick_voxels<T: Copy>(brick: UVec3, dimensions: UVec3, voxels: &[T]) -> Vec<T> {
+    let origin = (brick * BRICK_CORE).as_ivec3() - 1;
+    let max = dimensions.as_ivec3() - 1;
+    let mut brick_voxels = Vec::with_capacity((BRICK_SIZE * BRICK_SIZE * BRICK_SIZE) as usize);
+    for z in 0..BRICK_SIZE as i32 {
+        let vz = (origin.z + z).clamp(0, max.z) as usize;
+        for y in 0..BRICK_SIZE as i32 {
+            let vy = (origin.y + y).clamp(0, max.y) as usize;
+            let row = (vy + dimensions.y as usize * vz) * dimensions.x as usize;
+            for x in 0..BRICK_SIZE as i32 {
+                let vx = (origin.x + x).clamp(0, max.x) as usize;
+                brick_voxels.push(voxels[row + vx]);
+            }
+        }
+    }
+    brick_voxels
+}
+
+/// Whether a box is at least partly inside the view frustum. Boxes are culled if all corners lie outside the same clip plane.
+fn in_frustum(view_proj: Mat4, min: Vec3, max: Vec3) -> bool {
+    let corners = (0..8).map(|i| {
+        let corner = Vec3::new(
+            if i & 1 == 0 { min.x } else { max.x },
+            if i & 2 == 0 { min.y } else { max.y },
+            if i & 4 == 0 { min.z } else { max.z },
+        );
+        view_proj * corner.extend(1.0)
+    }).collect::<Vec<Vec4>>();
+
+    let outside = |plane: fn(&Vec4) -> bool| corners.iter().all(plane);
+    !(outside(|c| c.x < -c.w) || outside(|c| c.x > c.w)
+        || outside(|c| c.y < -c.w) || outside(|c| c.y > c.w)
+        || outside(|c| c.z < 0.0) || outside(|c| c.z > c.w))
+}
@@ -0,0 +1,133 @@
+use wgpu::{Features, TextureFormat};
+
+use super::voxel::Voxel;
+
+/// Format of the 3D texture a volume is stored in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VoxelFormat {
+    /// 8-bit RGBA colors, the format of [`Voxel`]
+    #[default]
+    Rgba8,
+    /// A single 8-bit density per voxel, colored by the transfer function
+    R8Unorm,
+    /// A single normalized 16-bit scalar per voxel, e.g. for 16-bit CT data
+    R16Unorm,
+    /// A single half precision scalar per voxel, used for float data on devices that can't filter 32-bit floats
+    R16Float,
+    /// A single float scalar per voxel
+    R32Float,
+    /// Half precision RGBA colors, used for float colors on devices that can't filter 32-bit floats
+    Rgba16Float,
+    /// Float RGBA colors, e.g. preshaded DiffDVR reconstructions
+    Rgba32Float,
+}
+
+/// Device features that allow storing volumes at a higher precision, requested if the adapter supports them
+pub const PRECISION_FEATURES: Features = Features::TEXTURE_FORMAT_16BIT_NORM.union(Features::FLOAT32_FILTERABLE);
+
+impl VoxelFormat {
+    pub fn texture_format(&self) -> TextureFormat {
+        match self {
+            Self::Rgba8 => TextureFormat::Rgba8Unorm,
+            Self::R8Unorm => TextureFormat::R8Unorm,
+            Self::R16Unorm => TextureFormat::R16Unorm,
+            Self::R16Float => TextureFormat::R16Float,
+            Self::R32Float => TextureFormat::R32Float,
+            Self::Rgba16Float => TextureFormat::Rgba16Float,
+            Self::Rgba32Float => TextureFormat::Rgba32Float,
+        }
+    }
+
+    /// Amount of values per voxel, either a single scalar or RGBA
+    pub fn channels(&self) -> usize {
+        match self {
+            Self::R8Unorm | Self::R16Unorm | Self::R16Float | Self::R32Float => 1,
+            Self::Rgba8 | Self::Rgba16Float | Self::Rgba32Float => 4,
+        }
+    }
+
+    pub fn bytes_per_voxel(&self) -> usize {
+        match self {
+            Self::R8Unorm => 1,
+            Self::R16Unorm | Self::R16Float => 2,
+            Self::Rgba8 | Self::R32Float => 4,
+            Self::Rgba16Float => 8,
+            Self::Rgba32Float => 16,
+        }
+    }
+
+    /// Closest format with the same channels that the device can sample with linear filtering
+    pub fn supported(&self, features: Features) -> Self {
+        match self {
+            Self::R16Unorm if !features.contains(Features::TEXTURE_FORMAT_16BIT_NORM) => Self::R32Float.supported(features),
+            Self::R32Float if !features.contains(Features::FLOAT32_FILTERABLE) => Self::R16Float,
+            Self::Rgba32Float if !features.contains(Features::FLOAT32_FILTERABLE) => Self::Rgba16Float,
+            format => *format,
+        }
+    }
+
+    /// Encodes values between 0 and 1 into the bytes of the texture, `channels()` values per voxel
+    pub fn encode(&self, values: &[f32]) -> Vec<u8> {
+        match self {
+            Self::Rgba8 | Self::R8Unorm => values.iter().map(|value| (value.clamp(0.0, 1.0) * 255.0).round() as u8).collect(),
+            Self::R16Unorm => values.iter().flat_map(|value| ((value.clamp(0.0, 1.0) * 65535.0).round() as u16).to_ne_bytes()).collect(),
+            Self::R16Float | Self::Rgba16Float => values.iter().flat_map(|value| f16_bits(*value).to_ne_bytes()).collect(),
+            Self::R32Float | Self::Rgba32Float => bytemuck::cast_slice(values).to_vec(),
+        }
+    }
+}
+
+/// Voxel values at the precision of the source data, kept next to the 8-bit colors of a volume
+#[derive(Debug, Clone)]
+pub struct PreciseVoxels {
+    /// Format the values are meant to be stored in, which also determines the amount of values per voxel
+    pub format: VoxelFormat,
+    /// Values normalized to 0..1 like the 8-bit colors, `format.channels()` per voxel with x changing fastest
+    pub values: Vec<f32>,
+}
+
+impl PreciseVoxels {
+    /// Widens 8-bit colors, e.g. for frames of a time series that were read without their full precision
+    pub fn from_voxels(format: VoxelFormat, voxels: &[Voxel]) -> Self {
+        let values = match format.channels() {
+            1 => voxels.iter().map(|voxel| voxel.color[3] as f32 / 255.0).collect(),
+            _ => voxels.iter().flat_map(|voxel| voxel.color.map(|channel| channel as f32 / 255.0)).collect(),
+        };
+        Self { format, values }
+    }
+
+    /// Color of a voxel, scalars are used for every channel
+    pub fn color(&self, index: usize) -> [f32; 4] {
+        match self.format.channels() {
+            1 => [self.values[index]; 4],
+            _ => std::array::from_fn(|channel| self.values[4 * index + channel]),
+        }
+    }
+}
+
+/// Converts a float into the bits of a half precision float, rounding to the nearest value
+fn f16_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        // Too small for a normal half float, stored as subnormal or zero
+        if exponent < -10 {
+            return sign;
+        }
+        let shift = (14 - exponent) as u32;
+        let mantissa = mantissa | 0x80_0000;
+        return sign | ((mantissa + (1 << (shift - 1))) >> shift) as u16;
+    }
+    // A carry out of the mantissa correctly increases the exponent
+    sign | ((((exponent as u32) << 10) | (mantissa >> 13)) + ((mantissa >> 12) & 1)) as u16
+}
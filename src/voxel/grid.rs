@@ -1,24 +1,63 @@
-use std::ops::{Index, IndexMut};
+use std::{borrow::Cow, ops::{Index, IndexMut}};
 
-use glam::{UVec3, Vec3Swizzles};
+use anyhow::{bail, Result};
+
+use glam::{Mat4, UVec3, Vec3, Vec3Swizzles};
 use wgpu::{util::{BufferInitDescriptor, DeviceExt}, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BufferUsages, Device, Queue, ShaderStages};
 
-use crate::texture_3d::Texture3D;
+use crate::{texture_3d::Texture3D, transfer_function::{TransferFunction, TransferFunctionTexture}, transfer_function_2d::{GradientHistogram, TransferFunction2D, TransferFunction2DTexture, GRADIENT_LIMIT}};
+
+use crate::loaders::stream::Slab;
 
-use super::voxel::Voxel;
+use super::{bricks::{brick_voxels, BrickLayout, BrickPool, BRICK_CORE}, format::{PreciseVoxels, VoxelFormat}, mips::{level_count, level_size, MipBuilder, MipFilter}, volume::VolumeData, voxel::Voxel};
 pub struct VoxelGrid {
+    /// Copy of the voxels in host memory, empty for streamed grids
     voxels: Vec<Voxel>,
+    /// Voxels at the precision of the source, uploaded instead of the 8-bit voxels if present
+    precise: Option<PreciseVoxels>,
+    /// Format of the texture, which may be less precise than the source if the device doesn't support it
+    pub format: VoxelFormat,
+    /// Whether the voxels were streamed into the texture without keeping a copy in host memory
+    streamed: bool,
+    /// Filter the lower resolution mip levels of the texture are downsampled with
+    mip_filter: MipFilter,
+    /// Builds the mip levels of a streamed grid while its slabs arrive
+    mip_builder: Option<MipBuilder>,
+    /// Whether the mip levels and histograms still belong to earlier voxels, see [`VoxelGrid::set_voxels`]
+    mips_outdated: bool,
+    /// Mip level that every sample is read from instead of the level picked from the ray footprint, e.g. to compare levels
+    pub forced_lod: Option<u32>,
     pub dimensions: UVec3,
+    /// Physical size of a single voxel along each axis
+    pub spacing: Vec3,
+    /// Position of the first voxel in the space of the file the volume was loaded from, see [`VolumeData::origin`]
+    pub origin: Vec3,
     // voxels_buffer: wgpu::Buffer,
     pub voxels_bind_group_layout: BindGroupLayout,
     pub voxels_bind_group: BindGroup,
     pub voxel_texture: Texture3D,
     pub voxel_texture_bind_group_layout: BindGroupLayout,
     pub voxel_texture_bind_group: BindGroup,
+    /// Bricks of volumes that exceed the 3D texture limit of the device, `voxel_texture` is their atlas then
+    bricks: Option<BrickPool>,
+    /// Atlas entries of the bricks, holds a single unused entry for volumes that aren't bricked
+    page_table_buffer: wgpu::Buffer,
     voxel_grid_buffer: wgpu::Buffer,
     raymarch_color_buffer: wgpu::Buffer,
     pub attenuation: f32,
-    pub transfer_function_colors: RaymarchTransferFunctionColors,
+    pub transfer_function_settings: RaymarchTransferFunctionSettings,
+    /// Colors and opacities of the densities, applied to scalar volumes and to RGBA volumes if it is enabled
+    pub transfer_function: TransferFunction,
+    transfer_function_texture: TransferFunctionTexture,
+    /// Colors and opacities over density and gradient magnitude, used instead of the 1D transfer function if it is enabled in the settings
+    pub transfer_function_2d: TransferFunction2D,
+    transfer_function_2d_texture: TransferFunction2DTexture,
+    /// Amount of voxels per density range, see [`HISTOGRAM_BINS`]
+    histogram: Vec<u32>,
+    /// Histogram over density and gradient magnitude, computed once it is needed
+    gradient_histogram: Option<GradientHistogram>,
+    /// File the volume was loaded from
+    pub source_path: Option<String>,
 }
 
 #[repr(C)]
@@ -29,19 +68,79 @@ pub struct VoxelGridUniform {
     box_size: [f32; 4],
     // Buffer is needed for byte alignment in wgsl and has no further use
     buffer: [f32; 4],
+    /// Amount of bricks along each axis, w is 1 if the volume is bricked
+    bricks: [u32; 4],
+    /// Amount of brick slots along each axis of the atlas, w is the amount of voxels per side of a brick's core
+    atlas_slots: [u32; 4],
+    /// x is the amount of channels of the texture, scalar volumes use their single channel as gray value and density
+    format: [u32; 4],
+    /// x is the forced mip level or -1 if the level is picked from the ray footprint, y is the highest mip level
+    lod: [f32; 4],
 }
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-pub struct RaymarchTransferFunctionColors {
-    pub color_a: [f32; 4],
-    pub color_b: [f32; 4],
-    pub color_c: [f32; 4],
-    pub use_transfer_function: [u32; 4],
+pub struct RaymarchTransferFunctionSettings {
+    /// x is 1 if RGBA volumes are colored by the transfer function instead of their own colors, y is 1 if the 2D transfer function is used
+    use_transfer_function: [u32; 4],
+    /// x is the gradient magnitude at the top of the gradient axis of the 2D transfer function
+    gradient_range: [f32; 4],
 }
 
+/// Amount of bins of the density histogram that is drawn under the transfer function
+pub const HISTOGRAM_BINS: usize = 256;
+
 impl VoxelGrid {
     pub fn new(dimensions: UVec3, device: &Device, queue: &Queue) -> Self {
+        Self::from_volume_data(VolumeData::new(dimensions), device, queue)
+    }
+
+    /// Creates a Voxel Grid and uploads the voxels of the volume into its 3D texture.
+    /// Values at full precision are stored in the closest format the device supports.
+    /// The texture holds a full mip pyramid that is downsampled from the voxels.
+    /// Volumes that exceed the 3D texture limit of the device are split into bricks that are uploaded once they become visible.
+    pub fn from_volume_data(data: VolumeData, device: &Device, queue: &Queue) -> Self {
+        let format = data.format().supported(device.features());
+        let max_dimension = device.limits().max_texture_dimension_3d;
+        if data.dimensions.max_element() > max_dimension {
+            let is_visible = |index: usize| match &data.precise {
+                Some(precise) => precise.color(index)[3] > 0.0,
+                None => data.voxels[index].color[3] != 0,
+            };
+            let bricks = BrickPool::new(data.dimensions, format.bytes_per_voxel(), max_dimension, is_visible);
+            let texture = Texture3D::new(device, bricks.layout.atlas_size(), format.texture_format(), 1, Some("Voxel Brick Atlas"));
+            return Self::with_texture(data, format, texture, Some(bricks), device, queue);
+        }
+
+        let texels = texels(format, &data.voxels, data.precise.as_ref());
+        let texture = Texture3D::from_image(device, queue, &texels, data.dimensions, format.texture_format(), level_count(data.dimensions), Some("Voxel 3DTexture")).unwrap();
+        let grid = Self::with_texture(data, format, texture, None, device, queue);
+        grid.update_mips(queue);
+        grid
+    }
+
+    /// Creates an empty Voxel Grid whose texture is filled slab by slab with [`VoxelGrid::write_slab`].
+    /// No voxels are kept in host memory, so streamed grids can't be exported or compared,
+    /// and they can't be split into bricks, so they have to fit into a single 3D texture.
+    /// Their mip levels are built with the default filter while the slabs arrive.
+    pub fn streamed(dimensions: UVec3, spacing: Vec3, format: VoxelFormat, device: &Device, queue: &Queue) -> Result<Self> {
+        let max_dimension = device.limits().max_texture_dimension_3d;
+        if dimensions.max_element() > max_dimension {
+            bail!("Streamed volumes can have at most {} voxels per side, but the volume has a size of {}", max_dimension, dimensions);
+        }
+
+        let format = format.supported(device.features());
+        let texture = Texture3D::new(device, dimensions, format.texture_format(), level_count(dimensions), Some("Voxel 3DTexture"));
+        let data = VolumeData { dimensions, spacing, origin: Vec3::ZERO, voxels: vec![], precise: None, scalar: format.channels() == 1 };
+        let mut grid = Self::with_texture(data, format, texture, None, device, queue);
+        grid.streamed = true;
+        grid.mip_builder = Some(MipBuilder::new(dimensions, format.channels(), grid.mip_filter));
+        Ok(grid)
+    }
+
+    fn with_texture(data: VolumeData, format: VoxelFormat, texture: Texture3D, bricks: Option<BrickPool>, device: &Device, queue: &Queue) -> Self {
+        let VolumeData { dimensions, spacing, origin, voxels, precise, .. } = data;
+        let histogram = histogram(&voxels, precise.as_ref());
         let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
             label: Some("voxel_grid_bind_group_layout_descriptor"),
             entries: &[
@@ -65,6 +164,35 @@ impl VoxelGrid {
                         min_binding_size: None
                     },
                     count: None
+                },
+
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D1,
+                        multisampled: false
+                    },
+                    count: None
+                },
+
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false
+                    },
+                    count: None
+                },
+
+                BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None
                 }
                 // BindGroupLayoutEntry {
                 //     binding: 1,
@@ -80,8 +208,6 @@ impl VoxelGrid {
             ]
         });
 
-        let voxels: Vec<Voxel> = vec![Voxel::default(); dimensions.x as usize * dimensions.y as usize * dimensions.z as usize];
-
 
         // let voxels_buffer = device.create_buffer_init(&BufferInitDescriptor {
         //     label: Some("voxel_grid_buffer_init_descriptor_voxels"),
@@ -89,15 +215,22 @@ impl VoxelGrid {
         //     usage: BufferUsages::STORAGE | BufferUsages::COPY_DST
         // });
 
+        let transfer_function = TransferFunction::default();
+        let transfer_function_texture = TransferFunctionTexture::new(device);
+        transfer_function_texture.write(&transfer_function, queue);
+        let transfer_function_2d = TransferFunction2D::default();
+        let transfer_function_2d_texture = TransferFunction2DTexture::new(device);
+        transfer_function_2d_texture.write(&transfer_function_2d, queue);
+
         let raymarch_color_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("raymarch_color_buffer"),
-            contents: bytemuck::cast_slice(&[RaymarchTransferFunctionColors::new()]),
+            contents: bytemuck::cast_slice(&[RaymarchTransferFunctionSettings::new()]),
             usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
         });
 
         let voxel_grid_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("voxel_grid_buffer_init_descriptor_voxel_grid"),
-            contents: bytemuck::cast_slice(&[VoxelGridUniform::new(dimensions, 1.0)]),
+            contents: bytemuck::cast_slice(&[VoxelGridUniform::new(dimensions, spacing, 1.0, format, bricks.as_ref().map(|bricks| bricks.layout), texture.texture.mip_level_count(), None)]),
             usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
         });
         
@@ -136,12 +269,25 @@ impl VoxelGrid {
                             size: None
                         }
                     )
+                },
+
+                BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&transfer_function_texture.view)
+                },
+
+                BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&transfer_function_2d_texture.view)
+                },
+
+                BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(&transfer_function_2d_texture.sampler)
                 }
             ]
         });
 
-        let texture = Texture3D::from_image(&device, &queue, bytemuck::cast_slice(&voxels), dimensions, Some("Voxel 3DTexture")).unwrap();
-
         let voxel_texture_bind_group_layout = device.create_bind_group_layout(
             &wgpu::BindGroupLayoutDescriptor {
                 label: Some("voxel_texture_bind_group_layout"),
@@ -161,11 +307,28 @@ impl VoxelGrid {
                         visibility: wgpu::ShaderStages::FRAGMENT,
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                         count: None
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None
+                        },
+                        count: None
                     }
                 ]
             }
         );
 
+        let page_table_len = bricks.as_ref().map_or(1, |bricks| bricks.page_table_len());
+        let page_table_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("voxel_grid_page_table_buffer"),
+            contents: bytemuck::cast_slice(&vec![0u32; page_table_len]),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST
+        });
+
         let voxel_texture_bind_group = device.create_bind_group(
             &wgpu::BindGroupDescriptor {
                 layout: &voxel_texture_bind_group_layout,
@@ -177,6 +340,10 @@ impl VoxelGrid {
                     wgpu::BindGroupEntry {
                         binding: 1,
                         resource: wgpu::BindingResource::Sampler(&texture.sampler)
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: page_table_buffer.as_entire_binding()
                     }
                 ],
                 label: Some("voxel_texture_bind_group")
@@ -185,17 +352,35 @@ impl VoxelGrid {
 
         Self {
             voxels,
+            precise,
+            format,
+            streamed: false,
+            mip_filter: MipFilter::default(),
+            mip_builder: None,
+            mips_outdated: false,
+            forced_lod: None,
             dimensions,
+            spacing,
+            origin,
             voxels_bind_group_layout: layout,
             // voxels_buffer,
             voxels_bind_group,
             voxel_texture: texture,
             voxel_texture_bind_group_layout,
             voxel_texture_bind_group,
+            bricks,
+            page_table_buffer,
             voxel_grid_buffer,
             raymarch_color_buffer,
             attenuation: 1.0,
-            transfer_function_colors: RaymarchTransferFunctionColors::new()
+            transfer_function_settings: RaymarchTransferFunctionSettings::new(),
+            transfer_function,
+            transfer_function_texture,
+            transfer_function_2d,
+            transfer_function_2d_texture,
+            histogram,
+            gradient_histogram: None,
+            source_path: None,
         }
     }
 
@@ -203,46 +388,239 @@ impl VoxelGrid {
         let index = self.get_index(position);
         self.voxels[index].set_color(color);
     }
+
+    /// Voxels in host memory, streamed grids have none
+    pub fn voxels(&self) -> Result<&[Voxel]> {
+        if self.streamed {
+            bail!("The volume was streamed to the GPU and its voxels are not kept in memory");
+        }
+        Ok(&self.voxels)
+    }
+
+    /// Voxels at the precision of the source, if it is higher than 8 bits and they are kept in memory
+    pub fn precise_voxels(&self) -> Option<&PreciseVoxels> {
+        self.precise.as_ref()
+    }
+
+    /// Color of the voxel at an index at the precision of the source, scalars are used for every channel
+    pub fn voxel_color(&self, index: usize) -> [f32; 4] {
+        match &self.precise {
+            Some(precise) => precise.color(index),
+            None => self.voxels[index].color.map(|channel| channel as f32 / 255.0),
+        }
+    }
+
+    /// Whether the volume holds a single density per voxel, which is always colored by the transfer function
+    pub fn is_scalar(&self) -> bool {
+        self.format.channels() == 1
+    }
+
+    /// Uploads the voxels of consecutive z slices of a streamed grid, starting with slice `z`,
+    /// and the slices of the mip levels they complete. Slabs have to arrive in order.
+    pub fn write_slab(&mut self, z: u32, slab: &Slab, queue: &Queue) {
+        let slice_len = self.dimensions.x as usize * self.dimensions.y as usize;
+        let depth = (slab.voxel_count() / slice_len) as u32;
+        self.voxel_texture.write_slab(queue, &slab.encode(self.format), self.dimensions, z, depth);
+
+        let channels = self.format.channels();
+        let values = slab.values(channels);
+        add_to_histogram(&mut self.histogram, values.chunks_exact(channels).map(|voxel| voxel[channels - 1]));
+        let mut completed = vec![];
+        if let Some(builder) = &mut self.mip_builder {
+            for slice in values.chunks_exact(slice_len * channels) {
+                completed.extend(builder.add_slice(slice.to_vec()));
+            }
+        }
+        self.write_mips(completed, queue);
+    }
+
+    /// Amount of voxels per density range, streamed grids count the voxels that arrived so far
+    pub fn histogram(&self) -> &[u32] {
+        &self.histogram
+    }
+
+    /// Histogram over density and gradient magnitude, which is computed on the first call.
+    /// Sets the range of the gradient axis of the 2D transfer function to the largest gradient magnitude of the volume.
+    pub fn gradient_histogram(&mut self) -> Result<&GradientHistogram> {
+        if self.gradient_histogram.is_none() {
+            let voxels = self.voxels()?;
+            let histogram = match &self.precise {
+                Some(precise) => {
+                    let channels = precise.format.channels();
+                    GradientHistogram::new(self.dimensions, |index| precise.values[index * channels + channels - 1])
+                },
+                None => GradientHistogram::new(self.dimensions, |index| voxels[index].color[3] as f32 / 255.0),
+            };
+            self.transfer_function_settings.gradient_range[0] = histogram.max_gradient.max(f32::EPSILON);
+            self.gradient_histogram = Some(histogram);
+        }
+        Ok(self.gradient_histogram.as_ref().expect("Gradient histogram was just computed"))
+    }
+
+    /// Amount of mip levels of the texture, bricked grids only have their full resolution
+    pub fn mip_levels(&self) -> u32 {
+        self.voxel_texture.texture.mip_level_count()
+    }
+
+    pub fn mip_filter(&self) -> MipFilter {
+        self.mip_filter
+    }
+
+    /// Downsamples the mip levels again with another filter, which needs the voxels in host memory
+    pub fn set_mip_filter(&mut self, filter: MipFilter, queue: &Queue) -> Result<()> {
+        self.voxels()?;
+        self.mip_filter = filter;
+        self.update_mips(queue);
+        Ok(())
+    }
+
+    /// Replaces all voxels with the voxels of a volume of the same size and uploads them.
+    /// Volumes in another format are converted to the format of the grid.
+    /// Bricked grids upload their visible bricks again on the next call to [`VoxelGrid::update_bricks`].
+    /// With `defer_mips`, e.g. while a time series plays, only the full resolution is uploaded and every sample reads it,
+    /// the mip levels and histograms are rebuilt on the next call to [`VoxelGrid::update_deferred_mips`].
+    pub fn set_voxels(&mut self, data: &VolumeData, defer_mips: bool, queue: &Queue) -> Result<()> {
+        if self.streamed {
+            bail!("The volume was streamed to the GPU and its voxels can't be replaced");
+        }
+        if data.dimensions != self.dimensions {
+            bail!("A volume with a size of {} can't replace the voxels of a grid with a size of {}", data.dimensions, self.dimensions);
+        }
+
+        self.voxels.copy_from_slice(&data.voxels);
+        self.precise = match &data.precise {
+            _ if matches!(self.format, VoxelFormat::Rgba8 | VoxelFormat::R8Unorm) => None,
+            Some(precise) if precise.format.channels() == self.format.channels() => Some(precise.clone()),
+            _ => Some(PreciseVoxels::from_voxels(self.format, &data.voxels)),
+        };
+        match &mut self.bricks {
+            Some(bricks) => {
+                let (voxels, precise) = (&self.voxels, &self.precise);
+                bricks.reset(self.dimensions, |index| match precise {
+                    Some(precise) => precise.color(index)[3] > 0.0,
+                    None => voxels[index].color[3] != 0,
+                });
+            },
+            None => self.write_voxels(queue),
+        }
+
+        let was_outdated = self.mips_outdated;
+        self.mips_outdated = true;
+        if !defer_mips {
+            self.update_deferred_mips(queue);
+        } else if !was_outdated {
+            self.update_voxel_grid_buffer(queue);
+        }
+        Ok(())
+    }
+
+    /// Builds the mip levels and histograms that [`VoxelGrid::set_voxels`] deferred, does nothing if they are up to date
+    pub fn update_deferred_mips(&mut self, queue: &Queue) {
+        if !self.mips_outdated {
+            return;
+        }
+        self.mips_outdated = false;
+        self.histogram = histogram(&self.voxels, self.precise.as_ref());
+        self.gradient_histogram = None;
+        if self.bricks.is_none() {
+            self.update_mips(queue);
+        }
+        self.update_voxel_grid_buffer(queue);
+    }
+
+    /// Streams the bricks that are visible from the camera into the atlas, does nothing for grids that aren't bricked
+    pub fn update_bricks(&mut self, view_proj: Mat4, camera_position: Vec3, queue: &Queue) {
+        let (box_min, box_size) = self.bounding_box();
+        let (dimensions, format, voxels, precise) = (self.dimensions, self.format, &self.voxels, &self.precise);
+        let brick_bytes = |brick: UVec3| match precise {
+            Some(precise) if format.channels() == 1 => format.encode(&brick_voxels(brick, dimensions, &precise.values)),
+            Some(precise) => format.encode(bytemuck::cast_slice(&brick_voxels(brick, dimensions, bytemuck::cast_slice::<f32, [f32; 4]>(&precise.values)))),
+            None if format == VoxelFormat::R8Unorm => brick_voxels(brick, dimensions, voxels).iter().map(|voxel| voxel.color[3]).collect(),
+            None => bytemuck::cast_slice(&brick_voxels(brick, dimensions, voxels)).to_vec(),
+        };
+        if let Some(bricks) = &mut self.bricks {
+            if bricks.update(view_proj, camera_position, box_min, box_size, self.dimensions, brick_bytes, &self.voxel_texture, queue) {
+                bricks.write_page_table(&self.page_table_buffer, queue);
+            }
+        }
+    }
+
+    /// How the volume is split into bricks, if it exceeds the 3D texture limit of the device
+    pub fn brick_layout(&self) -> Option<BrickLayout> {
+        self.bricks.as_ref().map(|bricks| bricks.layout)
+    }
+
+    /// Amount of bricks in the atlas and amount of bricks with visible voxels, if the grid is bricked
+    pub fn brick_residency(&self) -> Option<(usize, usize)> {
+        self.bricks.as_ref().map(|bricks| bricks.residency())
+    }
     
-    fn get_index(&self, position: UVec3) -> usize {
+    pub fn get_index(&self, position: UVec3) -> usize {
         if position.x >= self.dimensions.x || position.y >= self.dimensions.y || position.z >= self.dimensions.z {
             panic!("Tried to access grid outside array")
         }
         return (position.x + self.dimensions.x * (position.y + (self.dimensions.y) * position.z)) as usize;
     }
 
+    /// Uploads all voxels into the texture. Bricked grids upload their bricks in [`VoxelGrid::update_bricks`] instead.
     pub fn update_buffer(&self, queue: &Queue) {
-
-        let size = wgpu::Extent3d {
-            width: self.dimensions.x,
-            height: self.dimensions.y,
-            depth_or_array_layers: self.dimensions.z,
-        };
+        if self.bricks.is_some() {
+            return;
+        }
 
         // queue.write_buffer(&self.voxels_buffer, 0, bytemuck::cast_slice(&self.voxels));
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                aspect: wgpu::TextureAspect::All,
-                texture: &self.voxel_texture.texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO
-            },
-            bytemuck::cast_slice(&self.voxels),
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * self.dimensions.x),
-                rows_per_image: Some(self.dimensions.y),
-            },
-            size
-        );
+        self.write_voxels(queue);
+        self.update_mips(queue);
+    }
+
+    /// Uploads all voxels into the full resolution level of the texture
+    fn write_voxels(&self, queue: &Queue) {
+        let texels = texels(self.format, &self.voxels, self.precise.as_ref());
+        self.voxel_texture.write_slab(queue, &texels, self.dimensions, 0, self.dimensions.z);
+    }
+
+    /// Downsamples the voxels in host memory slice by slice into the lower resolution mip levels of the texture
+    fn update_mips(&self, queue: &Queue) {
+        if self.mip_levels() == 1 {
+            return;
+        }
+        let channels = self.format.channels();
+        let slice_len = self.dimensions.x as usize * self.dimensions.y as usize;
+        let mut builder = MipBuilder::new(self.dimensions, channels, self.mip_filter);
+        for z in 0..self.dimensions.z as usize {
+            let slice = match &self.precise {
+                Some(precise) => precise.values[z * slice_len * channels..(z + 1) * slice_len * channels].to_vec(),
+                None => PreciseVoxels::from_voxels(self.format, &self.voxels[z * slice_len..(z + 1) * slice_len]).values,
+            };
+            self.write_mips(builder.add_slice(slice), queue);
+        }
+    }
+
+    /// Uploads downsampled slices given by their mip level and z
+    fn write_mips(&self, slices: Vec<(u32, u32, Vec<f32>)>, queue: &Queue) {
+        for (level, z, values) in slices {
+            let size = level_size(self.dimensions, level);
+            self.voxel_texture.write_level(queue, &self.format.encode(&values), level, UVec3::new(0, 0, z), UVec3::new(size.x, size.y, 1));
+        }
     }
 
     pub fn update_voxel_grid_buffer(&mut self, queue: &Queue) {
-        queue.write_buffer(&self.voxel_grid_buffer, 0, bytemuck::cast_slice(&[VoxelGridUniform::new(self.dimensions, self.attenuation)]));
+        // Outdated mip levels would show earlier voxels
+        let forced_lod = if self.mips_outdated { Some(0) } else { self.forced_lod };
+        let uniform = VoxelGridUniform::new(self.dimensions, self.spacing, self.attenuation, self.format, self.bricks.as_ref().map(|bricks| bricks.layout), self.mip_levels(), forced_lod);
+        queue.write_buffer(&self.voxel_grid_buffer, 0, bytemuck::cast_slice(&[uniform]));
     }
 
+    /// Returns the minimum corner and the size of the box the volume is rendered in
+    pub fn bounding_box(&self) -> (Vec3, Vec3) {
+        bounding_box(self.dimensions, self.spacing)
+    }
+
+    /// Uploads the transfer function and whether it is used
     pub fn update_transfer_function_buffer(&mut self, queue: &Queue) {
-        queue.write_buffer(&self.raymarch_color_buffer, 0, bytemuck::cast_slice(&[self.transfer_function_colors]));
+        queue.write_buffer(&self.raymarch_color_buffer, 0, bytemuck::cast_slice(&[self.transfer_function_settings]));
+        self.transfer_function_texture.write(&self.transfer_function, queue);
+        self.transfer_function_2d_texture.write(&self.transfer_function_2d, queue);
     }
 }
 
@@ -262,30 +640,71 @@ impl IndexMut<UVec3> for VoxelGrid {
     }
 }
 
-impl VoxelGridUniform {
-    pub fn new(dimensions: UVec3, attenuation: f32) -> Self {
-        println!("Has dimensions {}", dimensions);
-        let min_dimension = u32::min(dimensions.x, u32::min(dimensions.y, dimensions.z));
+/// Encodes the voxels in the format of the texture, preferring the values at full precision.
+/// Single channel 8-bit textures hold the alpha of the voxels.
+fn texels<'a>(format: VoxelFormat, voxels: &'a [Voxel], precise: Option<&PreciseVoxels>) -> Cow<'a, [u8]> {
+    match precise {
+        Some(precise) => Cow::Owned(format.encode(&precise.values)),
+        None if format == VoxelFormat::R8Unorm => Cow::Owned(voxels.iter().map(|voxel| voxel.color[3]).collect()),
+        None => Cow::Borrowed(bytemuck::cast_slice(voxels)),
+    }
+}
 
-        let box_size = dimensions.as_vec3() / min_dimension as f32;
-        let box_min = -box_size / 2.0;
+/// Counts the densities of the voxels, which are the alpha of RGBA voxels
+fn histogram(voxels: &[Voxel], precise: Option<&PreciseVoxels>) -> Vec<u32> {
+    let mut histogram = vec![0; HISTOGRAM_BINS];
+    match precise {
+        Some(precise) => {
+            let channels = precise.format.channels();
+            add_to_histogram(&mut histogram, precise.values.chunks_exact(channels).map(|voxel| voxel[channels - 1]));
+        },
+        None => add_to_histogram(&mut histogram, voxels.iter().map(|voxel| voxel.color[3] as f32 / 255.0)),
+    }
+    histogram
+}
+
+fn add_to_histogram(histogram: &mut [u32], densities: impl Iterator<Item = f32>) {
+    let bins = histogram.len();
+    for density in densities {
+        histogram[((density.clamp(0.0, 1.0) * bins as f32) as usize).min(bins - 1)] += 1;
+    }
+}
+
+/// Calculates the box of a volume centered around the origin.
+/// The physical extent of the volume is scaled so that its shortest side has a length of 1.
+fn bounding_box(dimensions: UVec3, spacing: Vec3) -> (Vec3, Vec3) {
+    let extent = dimensions.as_vec3() * spacing;
+    let box_size = extent / extent.min_element();
+    let box_min = -box_size / 2.0;
+    (box_min, box_size)
+}
+
+impl VoxelGridUniform {
+    pub fn new(dimensions: UVec3, spacing: Vec3, attenuation: f32, format: VoxelFormat, bricks: Option<BrickLayout>, mip_levels: u32, forced_lod: Option<u32>) -> Self {
+        let (box_min, box_size) = bounding_box(dimensions, spacing);
+        let (bricks, atlas_slots) = match bricks {
+            Some(layout) => (layout.brick_counts.extend(1), layout.atlas_slots.extend(BRICK_CORE)),
+            None => (glam::UVec4::ZERO, glam::UVec4::ZERO),
+        };
 
         Self {
             dimensions: dimensions.xyzx().to_array(),
             box_min: [box_min.x, box_min.y, box_min.z, 0.0],
             box_size: [box_size.x, box_size.y, box_size.z, 0.0],
             buffer: [attenuation; 4],
+            bricks: bricks.to_array(),
+            atlas_slots: atlas_slots.to_array(),
+            format: [format.channels() as u32, 0, 0, 0],
+            lod: [forced_lod.map_or(-1.0, |level| level.min(mip_levels - 1) as f32), (mip_levels - 1) as f32, 0.0, 0.0],
         }
     }
 }
 
-impl RaymarchTransferFunctionColors {
+impl RaymarchTransferFunctionSettings {
     pub fn new() -> Self {
         Self {
-            color_a: [0.117, 0.188, 0.62, 1.0],
-            color_b: [0.7294, 0.7294, 0.7294, 1.0],
-            color_c: [0.5725, 0.0, 0.039, 1.0],
             use_transfer_function: [0; 4],
+            gradient_range: [GRADIENT_LIMIT, 0.0, 0.0, 0.0],
         }
     }
 
@@ -296,4 +715,18 @@ impl RaymarchTransferFunctionColors {
     pub fn set_transfer_function_active(&mut self, val: bool) {
         self.use_transfer_function[0] = val as u32;
     }
+
+    /// Whether the 2D transfer function over density and gradient magnitude is used instead of the 1D one
+    pub fn transfer_function_2d_active(&self) -> bool {
+        self.use_transfer_function[1] != 0
+    }
+
+    pub fn set_transfer_function_2d_active(&mut self, active: bool) {
+        self.use_transfer_function[1] = active as u32;
+    }
+
+    /// Gradient magnitude at the top of the gradient axis of the 2D transfer function
+    pub fn gradient_range(&self) -> f32 {
+        self.gradient_range[0]
+    }
 }
\ No newline at end of file
@@ -0,0 +1,160 @@
+use std::collections::VecDeque;
+
+use glam::UVec3;
+
+/// Filter the levels of a mip pyramid are downsampled with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MipFilter {
+    /// Averages blocks of 2³ voxels
+    #[default]
+    Box,
+    /// Binomial weights of 1, 3, 3, 1 along each axis, which approximate a Gaussian and blur a little more
+    Gaussian,
+}
+
+/// Amount of levels of a full mip pyramid down to a single voxel
+pub fn level_count(dimensions: UVec3) -> u32 {
+    32 - dimensions.max_element().max(1).leading_zeros()
+}
+
+/// Size of a mip level, each level halves the size of the previous one and rounds down
+pub fn level_size(dimensions: UVec3, level: u32) -> UVec3 {
+    (dimensions >> level).max(UVec3::ONE)
+}
+
+/// Builds the levels of a mip pyramid while the slices of a volume arrive one after another,
+/// so that the whole volume never has to be kept in memory, e.g. for streamed volumes.
+pub struct MipBuilder {
+    channels: usize,
+    filter: MipFilter,
+    /// Builder of every level from the first downsampled one on
+    levels: Vec<LevelBuilder>,
+}
+
+/// Downsamples the slices of a level into the slices of the next one
+struct LevelBuilder {
+    /// Size of the level the slices are read from
+    source_size: UVec3,
+    size: UVec3,
+    /// Source slices that are already downsampled along x and y, the first one is the source slice `first_z`
+    pending: VecDeque<Vec<f32>>,
+    first_z: u32,
+    received: u32,
+    next_z: u32,
+}
+
+impl MipBuilder {
+    pub fn new(dimensions: UVec3, channels: usize, filter: MipFilter) -> Self {
+        let levels = (1..level_count(dimensions))
+            .map(|level| LevelBuilder {
+                source_size: level_size(dimensions, level - 1),
+                size: level_size(dimensions, level),
+                pending: VecDeque::new(),
+                first_z: 0,
+                received: 0,
+                next_z: 0,
+            })
+            .collect();
+        Self { channels, filter, levels }
+    }
+
+    /// Adds the next slice of the full resolution volume with `channels` values per voxel.
+    /// Returns the level, the z and the values of every slice of a lower resolution that it completed.
+    pub fn add_slice(&mut self, slice: Vec<f32>) -> Vec<(u32, u32, Vec<f32>)> {
+        let mut completed = vec![];
+        let mut inputs = vec![slice];
+        for (i, level) in self.levels.iter_mut().enumerate() {
+            let mut outputs = vec![];
+            for input in inputs {
+                outputs.extend(level.add(input, self.channels, self.filter));
+            }
+            completed.extend(outputs.iter().map(|(z, values)| (i as u32 + 1, *z, values.clone())));
+            inputs = outputs.into_iter().map(|(_, values)| values).collect();
+            if inputs.is_empty() {
+                break;
+            }
+        }
+        completed
+    }
+}
+
+impl LevelBuilder {
+    fn add(&mut self, slice: Vec<f32>, channels: usize, filter: MipFilter) -> Vec<(u32, Vec<f32>)> {
+        let rows = downsample_axis(&slice, channels, self.source_size.x, self.source_size.y, self.size.x, filter);
+        let columns = transpose(&rows, channels, self.size.x, self.source_size.y);
+        let slice = transpose(&downsample_axis(&columns, channels, self.source_size.y, self.size.x, self.size.y, filter), channels, self.size.y, self.size.x);
+        self.pending.push_back(slice);
+        self.received += 1;
+
+        let mut outputs = vec![];
+        while self.next_z < self.size.z {
+            let sources = taps(self.next_z, self.source_size.z, self.size.z, filter);
+            if sources.iter().any(|(z, _)| *z >= self.received) {
+                break;
+            }
+            let mut output = vec![0.0; self.pending[0].len()];
+            for (z, weight) in sources {
+                for (value, source) in output.iter_mut().zip(&self.pending[(z - self.first_z) as usize]) {
+                    *value += source * weight;
+                }
+            }
+            outputs.push((self.next_z, output));
+            self.next_z += 1;
+
+            // Slices before the first tap of the next output are no longer needed
+            let first_needed = taps(self.next_z, self.source_size.z, self.size.z, filter).first().map_or(self.received, |(z, _)| *z);
+            while self.first_z < first_needed && !self.pending.is_empty() {
+                self.pending.pop_front();
+                self.first_z += 1;
+            }
+        }
+        outputs
+    }
+}
+
+/// Source voxels and their weights that make up voxel `output` along an axis of length `len` that is downsampled to `output_len`
+fn taps(output: u32, len: u32, output_len: u32, filter: MipFilter) -> Vec<(u32, f32)> {
+    if len == 1 {
+        return vec![(0, 1.0)];
+    }
+    let first = 2 * output;
+    let mut taps = match filter {
+        MipFilter::Box => {
+            // The last voxel of an odd axis is added to the last block, so that no voxel is lost
+            let count = if output + 1 == output_len && len % 2 == 1 { 3 } else { 2 };
+            (first..first + count).map(|source| (source, 1.0 / count as f32)).collect::<Vec<_>>()
+        },
+        MipFilter::Gaussian => [(-1, 1.0), (0, 3.0), (1, 3.0), (2, 1.0)].iter()
+            .map(|(offset, weight)| ((first as i64 + offset).clamp(0, len as i64 - 1) as u32, weight / 8.0))
+            .collect(),
+    };
+    taps.sort_by_key(|(source, _)| *source);
+    taps
+}
+
+/// Downsamples the rows of a slice of `width` x `height` voxels to `output_width` voxels
+fn downsample_axis(values: &[f32], channels: usize, width: u32, height: u32, output_width: u32, filter: MipFilter) -> Vec<f32> {
+    let taps: Vec<Vec<(u32, f32)>> = (0..output_width).map(|x| taps(x, width, output_width, filter)).collect();
+    let mut output = Vec::with_capacity(output_width as usize * height as usize * channels);
+    for row in values.chunks_exact(width as usize * channels) {
+        for taps in &taps {
+            for channel in 0..channels {
+                output.push(taps.iter().map(|(x, weight)| row[*x as usize * channels + channel] * weight).sum());
+            }
+        }
+    }
+    output
+}
+
+/// Swaps the axes of a slice of `width` x `height` voxels
+fn transpose(values: &[f32], channels: usize, width: u32, height: u32) -> Vec<f32> {
+    let (width, height) = (width as usize, height as usize);
+    let mut output = vec![0.0; values.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let (from, to) = ((x + width * y) * channels, (y + height * x) * channels);
+            output[to..to + channels].copy_from_slice(&values[from..from + channels]);
+        }
+    }
+    output
+}
@@ -1,3 +1,7 @@
+pub mod bricks;
+pub mod format;
 pub mod grid;
+pub mod mips;
 pub mod voxel;
+pub mod volume;
 pub mod init;
\ No newline at end of file
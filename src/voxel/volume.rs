@@ -0,0 +1,150 @@
+use glam::{UVec3, Vec3};
+
+use super::{format::{PreciseVoxels, VoxelFormat}, voxel::Voxel};
+
+/// Voxels of a volume that are kept on the CPU, e.g. while loading or as prefetched frames of a time series.
+/// Uploaded to the GPU by creating a Voxel Grid from it.
+#[derive(Clone)]
+pub struct VolumeData {
+    pub dimensions: UVec3,
+    /// Physical size of a single voxel along each axis
+    pub spacing: Vec3,
+    /// Position of the first voxel in the space of the file, written back on export. The renderer centers every volume instead.
+    pub origin: Vec3,
+    pub voxels: Vec<Voxel>,
+    /// Values at the precision of the source, uploaded instead of the 8-bit colors if present
+    pub precise: Option<PreciseVoxels>,
+    /// Whether every voxel holds a single density, which is stored in a single channel texture and colored by the transfer function
+    pub scalar: bool,
+}
+
+impl VolumeData {
+    pub fn new(dimensions: UVec3) -> Self {
+        Self {
+            dimensions,
+            spacing: Vec3::ONE,
+            origin: Vec3::ZERO,
+            voxels: vec![Voxel::default(); dimensions.x as usize * dimensions.y as usize * dimensions.z as usize],
+            precise: None,
+            scalar: false,
+        }
+    }
+
+    /// Format the volume is meant to be stored in on the GPU
+    pub fn format(&self) -> VoxelFormat {
+        match &self.precise {
+            Some(precise) => precise.format,
+            None if self.scalar => VoxelFormat::R8Unorm,
+            None => VoxelFormat::Rgba8,
+        }
+    }
+
+    pub fn set_color(&mut self, position: UVec3, color: [u8; 4]) {
+        let index = self.get_index(position);
+        self.voxels[index].set_color(color);
+    }
+
+    fn get_index(&self, position: UVec3) -> usize {
+        if position.x >= self.dimensions.x || position.y >= self.dimensions.y || position.z >= self.dimensions.z {
+            panic!("Tried to access volume outside array")
+        }
+        (position.x + self.dimensions.x * (position.y + self.dimensions.y * position.z)) as usize
+    }
+
+    /// Reorders and flips the axes of the volume so that each axis points along the world axis closest to its direction.
+    /// `directions` holds the world space directions of the volume's x, y and z axis.
+    /// Volumes whose axes can't be mapped onto distinct world axes are left unchanged.
+    /// The origin moves to the voxel that comes first after flipping.
+    pub fn reorient(&mut self, directions: [Vec3; 3]) {
+        let mut world_axes = [0usize; 3];
+        let mut flipped = [false; 3];
+        for (axis, direction) in directions.iter().enumerate() {
+            let abs = direction.abs();
+            world_axes[axis] = if abs.x >= abs.y && abs.x >= abs.z { 0 } else if abs.y >= abs.z { 1 } else { 2 };
+            flipped[axis] = direction[world_axes[axis]] < 0.0;
+        }
+        if world_axes[0] == world_axes[1] || world_axes[1] == world_axes[2] || world_axes[0] == world_axes[2] {
+            return;
+        }
+        if world_axes == [0, 1, 2] && flipped == [false; 3] {
+            return;
+        }
+
+        let old_dimensions = self.dimensions.to_array();
+        for axis in 0..3 {
+            if flipped[axis] {
+                self.origin += directions[axis].normalize_or_zero() * (old_dimensions[axis] - 1) as f32 * self.spacing[axis];
+            }
+        }
+        let mut dimensions = [0u32; 3];
+        let mut spacing = [0f32; 3];
+        for axis in 0..3 {
+            dimensions[world_axes[axis]] = old_dimensions[axis];
+            spacing[world_axes[axis]] = self.spacing[axis];
+        }
+        let dimensions = UVec3::from_array(dimensions);
+
+        // New index of every voxel
+        let mut new_indices = Vec::with_capacity(self.voxels.len());
+        for z in 0..old_dimensions[2] {
+            for y in 0..old_dimensions[1] {
+                for x in 0..old_dimensions[0] {
+                    let mut position = [0u32; 3];
+                    for (axis, coordinate) in [x, y, z].into_iter().enumerate() {
+                        let coordinate = if flipped[axis] { old_dimensions[axis] - 1 - coordinate } else { coordinate };
+                        position[world_axes[axis]] = coordinate;
+                    }
+                    let [nx, ny, nz] = position;
+                    new_indices.push((nx + dimensions.x * (ny + dimensions.y * nz)) as usize);
+                }
+            }
+        }
+
+        let mut voxels = self.voxels.clone();
+        for (index, new_index) in new_indices.iter().enumerate() {
+            voxels[*new_index] = self.voxels[index];
+        }
+        if let Some(precise) = &mut self.precise {
+            let channels = precise.format.channels();
+            let mut values = precise.values.clone();
+            for (index, new_index) in new_indices.iter().enumerate() {
+                values[new_index * channels..(new_index + 1) * channels].copy_from_slice(&precise.values[index * channels..(index + 1) * channels]);
+            }
+            precise.values = values;
+        }
+
+        self.dimensions = dimensions;
+        self.spacing = Vec3::from_array(spacing);
+        self.voxels = voxels;
+    }
+
+    /// Linearly interpolates every voxel between this and another volume of the same size.
+    /// Values at full precision are only kept if both volumes have them in the same format.
+    pub fn lerp(&self, other: &VolumeData, t: f32) -> VolumeData {
+        let voxels = self.voxels.iter().zip(other.voxels.iter()).map(|(a, b)| {
+            let color = std::array::from_fn(|channel| {
+                let from = a.color[channel] as f32;
+                let to = b.color[channel] as f32;
+                (from + (to - from) * t).round() as u8
+            });
+            Voxel { color }
+        }).collect();
+
+        let precise = match (&self.precise, &other.precise) {
+            (Some(a), Some(b)) if a.format == b.format => Some(PreciseVoxels {
+                format: a.format,
+                values: a.values.iter().zip(b.values.iter()).map(|(from, to)| from + (to - from) * t).collect(),
+            }),
+            _ => None,
+        };
+
+        VolumeData {
+            dimensions: self.dimensions,
+            spacing: self.spacing,
+            origin: self.origin,
+            voxels,
+            precise,
+            scalar: self.scalar && other.scalar,
+        }
+    }
+}
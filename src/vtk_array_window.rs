@@ -0,0 +1,49 @@
+use egui::Context;
+
+use crate::loaders::vtk::VtkImage;
+
+/// GUI window for choosing which point-data array of a VTK file is rendered
+pub struct VtkArrayWindow {
+    path: String,
+    image: VtkImage,
+    selected: usize,
+}
+
+impl VtkArrayWindow {
+    pub fn new(path: &str, image: VtkImage) -> Self {
+        Self {
+            path: path.to_string(),
+            selected: image.default_array(),
+            image,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Hands over the read image, so that the selected array can be converted on a worker thread
+    pub fn into_image(self) -> VtkImage {
+        self.image
+    }
+
+    /// Draws the window and returns the index of the selected array once the load button is pressed
+    pub fn show(&mut self, ctx: &Context, open: &mut bool) -> Option<usize> {
+        let mut request = None;
+
+        egui::Window::new("Open VTK Array").open(open).show(ctx, |ui| {
+            ui.label(&self.path);
+            ui.label(format!("Dimensions: {}, spacing: {}", self.image.dimensions, self.image.spacing));
+
+            for (i, array) in self.image.arrays.iter().enumerate() {
+                ui.radio_value(&mut self.selected, i, format!("{} ({} components)", array.name, array.components));
+            }
+
+            if ui.button("Load").clicked() {
+                request = Some(self.selected);
+            }
+        });
+
+        request
+    }
+}